@@ -0,0 +1,125 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use terminal_core::Terminal;
+use terminal_core::ffi::{CCell, terminal_get_row, terminal_get_row_ptr};
+
+const ROWS: usize = 24;
+const COLS: usize = 80;
+
+/// Lines of plain text that wrap only at the newline, like `cat` on a log
+fn plain_text() -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..2000 {
+        out.extend_from_slice(
+            format!("line {i:05} the quick brown fox jumps over the lazy dog\r\n").as_bytes(),
+        );
+    }
+    out
+}
+
+/// Every word in a different colour, like `ls --color` or a syntax highlighter
+fn heavy_sgr() -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..2000 {
+        for word in 0..8 {
+            let fg = 30 + (i + word) % 8;
+            out.extend_from_slice(
+                format!("\x1b[1;{fg};48;5;{}mword\x1b[0m ", (i * 7 + word) % 256).as_bytes(),
+            );
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Full-width lines inside a scroll region, so every line scrolls the region
+fn scrolling() -> Vec<u8> {
+    let mut out = b"\x1b[2;23r\x1b[23;1H".to_vec();
+    let line = "x".repeat(COLS - 1);
+    for _ in 0..2000 {
+        out.extend_from_slice(line.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+fn bench_process_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_bytes");
+    for (name, input) in [
+        ("plain_text", plain_text()),
+        ("heavy_sgr", heavy_sgr()),
+        ("scrolling", scrolling()),
+    ] {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_function(name, |b| {
+            let mut term = Terminal::new(ROWS, COLS);
+            b.iter(|| term.process_bytes(black_box(&input)));
+        });
+    }
+    group.finish();
+}
+
+/// Output arriving in small reads, where per-call overhead dominates
+fn bench_small_reads(c: &mut Criterion) {
+    let input = plain_text();
+    let mut group = c.benchmark_group("small_reads");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("16_byte_chunks", |b| {
+        let mut term = Terminal::new(ROWS, COLS);
+        b.iter(|| {
+            for chunk in input.chunks(16) {
+                term.process_bytes(black_box(chunk));
+            }
+        });
+    });
+    group.finish();
+}
+
+/// `ESC[2J` in a loop on a large grid, where clearing rows is all the work
+fn bench_clear_screen(c: &mut Criterion) {
+    let input = b"\x1b[2J".repeat(100);
+    let mut group = c.benchmark_group("clear_screen");
+    group.throughput(Throughput::Elements(100));
+    group.bench_function("200x400", |b| {
+        let mut term = Terminal::new(200, 400);
+        b.iter(|| term.process_bytes(black_box(&input)));
+    });
+    group.finish();
+}
+
+/// Fetching every row of an unchanged, colorful screen for one frame:
+/// per-cell conversion into a caller buffer versus the cached row pointers
+fn bench_row_fetch(c: &mut Criterion) {
+    let mut terminal = Terminal::new(ROWS, COLS);
+    terminal.process_bytes(&heavy_sgr());
+    let term = &mut terminal as *mut Terminal;
+
+    let mut group = c.benchmark_group("row_fetch");
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("get_row", |b| {
+        let mut buffer = vec![CCell::from(&Default::default()); COLS];
+        b.iter(|| {
+            for row in 0..ROWS as u16 {
+                black_box(terminal_get_row(term, row, buffer.as_mut_ptr(), COLS));
+            }
+        });
+    });
+    group.bench_function("get_row_ptr", |b| {
+        b.iter(|| {
+            for row in 0..ROWS as u16 {
+                let mut len = 0;
+                black_box(terminal_get_row_ptr(term, row, &mut len));
+            }
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_process_bytes,
+    bench_small_reads,
+    bench_clear_screen,
+    bench_row_fetch
+);
+criterion_main!(benches);
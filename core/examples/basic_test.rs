@@ -5,7 +5,10 @@ fn main() {
 
     // Create a terminal
     let mut term = Terminal::new(24, 80);
-    println!("✓ Created terminal: {} rows x {} cols", term.rows, term.cols);
+    println!(
+        "✓ Created terminal: {} rows x {} cols",
+        term.rows, term.cols
+    );
 
     // Test basic text output
     term.process_bytes(b"Hello, World!");
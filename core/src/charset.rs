@@ -0,0 +1,115 @@
+//! Character sets designated into G0/G1 with `ESC ( x` / `ESC ) x` and
+//! selected with SI/SO: DEC special graphics and the national replacement
+//! character sets (NRCS) that remap a handful of ASCII positions.
+
+/// A 94-character set that can be designated into G0 or G1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    #[default]
+    Ascii,
+    /// DEC special graphics (line drawing), designator `0`
+    DecSpecialGraphics,
+    /// United Kingdom, designator `A`
+    Uk,
+    /// German, designator `K`
+    German,
+    /// French, designator `R` or `f`
+    French,
+    /// Swedish, designator `H` or `7`
+    Swedish,
+}
+
+impl Charset {
+    /// Charset for a designator final byte; unknown ones fall back to US-ASCII
+    pub fn from_designator(byte: u8) -> Self {
+        match byte {
+            b'0' => Charset::DecSpecialGraphics,
+            b'A' => Charset::Uk,
+            b'K' => Charset::German,
+            b'R' | b'f' => Charset::French,
+            b'H' | b'7' => Charset::Swedish,
+            _ => Charset::Ascii,
+        }
+    }
+
+    /// Map a printed character through this set
+    pub fn map(self, c: char) -> char {
+        match self {
+            Charset::Ascii => c,
+            Charset::DecSpecialGraphics => match c {
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => c,
+            },
+            Charset::Uk => match c {
+                '#' => '£',
+                _ => c,
+            },
+            Charset::German => match c {
+                '@' => '§',
+                '[' => 'Ä',
+                '\\' => 'Ö',
+                ']' => 'Ü',
+                '{' => 'ä',
+                '|' => 'ö',
+                '}' => 'ü',
+                '~' => 'ß',
+                _ => c,
+            },
+            Charset::French => match c {
+                '#' => '£',
+                '@' => 'à',
+                '[' => '°',
+                '\\' => 'ç',
+                ']' => '§',
+                '{' => 'é',
+                '|' => 'ù',
+                '}' => 'è',
+                '~' => '¨',
+                _ => c,
+            },
+            Charset::Swedish => match c {
+                '@' => 'É',
+                '[' => 'Ä',
+                '\\' => 'Ö',
+                ']' => 'Å',
+                '^' => 'Ü',
+                '`' => 'é',
+                '{' => 'ä',
+                '|' => 'ö',
+                '}' => 'å',
+                '~' => 'ü',
+                _ => c,
+            },
+        }
+    }
+}
@@ -1,93 +1,124 @@
-use crate::grid::{Cell, Color, NamedColor, Rgb};
-use crate::terminal::Terminal;
+// Every exported function null-checks its pointer arguments before use; the
+// host owns the contract that non-null pointers are valid. These are C entry
+// points, so marking them `unsafe fn` would add nothing for their callers.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use crate::grid::{Palette, Rgb};
+use crate::input::{Key, KeypadKey, Modifiers};
+use crate::render::convert_row;
+pub use crate::render::{CCell, CELL_FLAG_ROW_WRAPPED};
+use crate::terminal::{CursorShape, ImageDimension, NormalizationForm, PromptMarkKind, Terminal};
+use nix::sys::signal::Signal;
 use std::ffi::CStr;
 use std::os::raw::c_char;
 use std::slice;
 
-/// C-compatible cell structure for FFI
+/// C-compatible RGB color
 #[repr(C)]
-pub struct CCell {
-    pub ch: u32, // Unicode codepoint
-    pub fg_r: u8,
-    pub fg_g: u8,
-    pub fg_b: u8,
-    pub bg_r: u8,
-    pub bg_g: u8,
-    pub bg_b: u8,
-    pub flags: u8,
-}
-
-impl From<&Cell> for CCell {
-    fn from(cell: &Cell) -> Self {
-        let (fg_r, fg_g, fg_b) = color_to_rgb(&cell.fg);
-        let (bg_r, bg_g, bg_b) = color_to_rgb(&cell.bg);
-
-        CCell {
-            ch: cell.c as u32,
-            fg_r,
-            fg_g,
-            fg_b,
-            bg_r,
-            bg_g,
-            bg_b,
-            flags: cell.flags.0,
+pub struct CRgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl From<Rgb> for CRgb {
+    fn from(rgb: Rgb) -> Self {
+        CRgb {
+            r: rgb.r,
+            g: rgb.g,
+            b: rgb.b,
         }
     }
 }
 
-/// Convert Color to RGB tuple
-fn color_to_rgb(color: &Color) -> (u8, u8, u8) {
-    match color {
-        Color::Spec(rgb) => (rgb.r, rgb.g, rgb.b),
-        Color::Spec256(idx) => {
-            // Convert 256 color palette to RGB (simplified)
-            // This should use a proper color palette lookup
-            let idx = *idx;
-            if idx < 16 {
-                // Standard colors
-                named_color_to_rgb(idx as usize)
-            } else if idx < 232 {
-                // 216 color cube
-                let idx = idx - 16;
-                let r = ((idx / 36) * 51) as u8;
-                let g = (((idx % 36) / 6) * 51) as u8;
-                let b = ((idx % 6) * 51) as u8;
-                (r, g, b)
-            } else {
-                // Grayscale
-                let gray = ((idx - 232) * 10 + 8) as u8;
-                (gray, gray, gray)
-            }
-        }
-        Color::Named(named) => named_color_to_rgb(*named as usize),
-    }
-}
-
-/// Convert named color to RGB
-fn named_color_to_rgb(color: usize) -> (u8, u8, u8) {
-    match color {
-        0 => (0, 0, 0),         // Black
-        1 => (205, 49, 49),     // Red
-        2 => (13, 188, 121),    // Green
-        3 => (229, 229, 16),    // Yellow
-        4 => (36, 114, 200),    // Blue
-        5 => (188, 63, 188),    // Magenta
-        6 => (17, 168, 205),    // Cyan
-        7 => (229, 229, 229),   // White
-        8 => (102, 102, 102),   // Bright Black
-        9 => (241, 76, 76),     // Bright Red
-        10 => (35, 209, 139),   // Bright Green
-        11 => (245, 245, 67),   // Bright Yellow
-        12 => (59, 142, 234),   // Bright Blue
-        13 => (214, 112, 214),  // Bright Magenta
-        14 => (41, 184, 219),   // Bright Cyan
-        15 => (255, 255, 255),  // Bright White
-        16 => (200, 200, 200),  // Foreground
-        17 => (20, 20, 20),     // Background
-        _ => (200, 200, 200),
+/// C-compatible dirty column span (end inclusive)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CDirtySpan {
+    pub row: u16,
+    pub start_col: u16,
+    pub end_col: u16,
+}
+
+/// C-compatible summary of one `terminal_process_and_damage` feed
+#[repr(C)]
+pub struct CDamage {
+    /// Total dirty rows, which may exceed the buffer passed in
+    pub dirty_row_count: u32,
+    pub scrolled_lines: u32,
+    pub title_changed: bool,
+    pub bell: bool,
+}
+
+/// C-compatible scrollbar geometry: the view shows `visible_rows` lines
+/// starting `view_offset` lines above the live screen
+#[repr(C)]
+pub struct CScrollInfo {
+    pub scrollback_len: u32,
+    pub view_offset: u32,
+    pub visible_rows: u16,
+}
+
+/// C-compatible OSC 133 prompt mark (`kind` is a `PROMPT_MARK_*` value)
+#[repr(C)]
+pub struct CPromptMark {
+    pub line: u32,
+    pub col: u16,
+    pub kind: u8,
+    pub has_exit_code: bool,
+    pub exit_code: i32,
+}
+
+pub const PROMPT_MARK_PROMPT_START: u8 = 0;
+pub const PROMPT_MARK_COMMAND_START: u8 = 1;
+pub const PROMPT_MARK_OUTPUT_START: u8 = 2;
+pub const PROMPT_MARK_COMMAND_END: u8 = 3;
+
+/// C-compatible OSC 1337 inline image description (`*_unit` is an
+/// `IMAGE_UNIT_*` value; sizes are 0 for `IMAGE_UNIT_AUTO`)
+#[repr(C)]
+pub struct CImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub width_unit: u8,
+    pub height_unit: u8,
+    pub preserve_aspect_ratio: bool,
+    /// `inline=1` (not `inline`, a C keyword)
+    pub inline_image: bool,
+}
+
+pub const IMAGE_UNIT_AUTO: u8 = 0;
+pub const IMAGE_UNIT_CELLS: u8 = 1;
+pub const IMAGE_UNIT_PIXELS: u8 = 2;
+pub const IMAGE_UNIT_PERCENT: u8 = 3;
+
+fn image_dimension(dimension: ImageDimension) -> (u32, u8) {
+    match dimension {
+        ImageDimension::Auto => (0, IMAGE_UNIT_AUTO),
+        ImageDimension::Cells(n) => (n, IMAGE_UNIT_CELLS),
+        ImageDimension::Pixels(n) => (n, IMAGE_UNIT_PIXELS),
+        ImageDimension::Percent(n) => (n, IMAGE_UNIT_PERCENT),
     }
 }
 
+/// C-compatible dirty row version, acknowledged with `terminal_mark_clean_up_to`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CRowVersion {
+    pub row: u16,
+    pub version: u64,
+}
+
+/// C-compatible search match (rows are absolute line numbers, end inclusive)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CSearchMatch {
+    pub start_row: u32,
+    pub start_col: u16,
+    pub end_row: u32,
+    pub end_col: u16,
+}
+
 /// Create a new terminal
 #[unsafe(no_mangle)]
 pub extern "C" fn terminal_new(rows: u16, cols: u16) -> *mut Terminal {
@@ -128,6 +159,43 @@ pub extern "C" fn terminal_process_bytes(term: *mut Terminal, data: *const u8, l
     }
 }
 
+/// Process bytes and report what changed in one call. Dirty rows are written
+/// to `rows` (may be NULL) up to `rows_len`.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_process_and_damage(
+    term: *mut Terminal,
+    data: *const u8,
+    len: usize,
+    rows: *mut u16,
+    rows_len: usize,
+) -> CDamage {
+    let mut out = CDamage {
+        dirty_row_count: 0,
+        scrolled_lines: 0,
+        title_changed: false,
+        bell: false,
+    };
+    if term.is_null() || data.is_null() {
+        return out;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        let damage = terminal.process_and_damage(slice::from_raw_parts(data, len));
+        if !rows.is_null() {
+            let rows_buffer = slice::from_raw_parts_mut(rows, rows_len);
+            for (slot, &row) in rows_buffer.iter_mut().zip(&damage.dirty_rows) {
+                *slot = row as u16;
+            }
+        }
+        out.dirty_row_count = damage.dirty_rows.len() as u32;
+        out.scrolled_lines = damage.scrolled_lines.min(u32::MAX as usize) as u32;
+        out.title_changed = damage.title_changed;
+        out.bell = damage.bell;
+    }
+    out
+}
+
 /// Send input to the PTY
 #[unsafe(no_mangle)]
 pub extern "C" fn terminal_send_input(term: *mut Terminal, data: *const u8, len: usize) -> i32 {
@@ -145,6 +213,320 @@ pub extern "C" fn terminal_send_input(term: *mut Terminal, data: *const u8, len:
     }
 }
 
+/// Returned by `terminal_send_text` when the string is not valid UTF-8
+pub const INVALID_UTF8: i32 = -3;
+
+/// Send a NUL-terminated UTF-8 string to the PTY. Returns 0 on success, -1 on
+/// a NULL argument or write failure, `INVALID_UTF8` if the text is malformed
+/// (nothing is sent in that case).
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_send_text(term: *mut Terminal, utf8: *const c_char) -> i32 {
+    if term.is_null() || utf8.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let Ok(text) = CStr::from_ptr(utf8).to_str() else {
+            return INVALID_UTF8;
+        };
+        match (*term).send_input(text.as_bytes()) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        }
+    }
+}
+
+/// Write a NUL-terminated UTF-8 string straight into the grid at `row`/`col`
+/// in the cursor's style, bypassing the parser (tests, replay). Text past the
+/// last column is dropped and the cursor doesn't move. Returns 0 on success,
+/// -1 on a NULL argument, `INVALID_UTF8` if the text is malformed.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_put_str(
+    term: *mut Terminal,
+    row: u16,
+    col: u16,
+    utf8: *const c_char,
+) -> i32 {
+    if term.is_null() || utf8.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let Ok(text) = CStr::from_ptr(utf8).to_str() else {
+            return INVALID_UTF8;
+        };
+        (*term).put_str(row as usize, col as usize, text);
+        0
+    }
+}
+
+/// Set the NUL-terminated UTF-8 answerback message sent in reply to ENQ
+/// (empty: ENQ is ignored, the default). Returns 0 on success, -1 on a NULL
+/// argument, `INVALID_UTF8` if the text is malformed.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_answerback(term: *mut Terminal, utf8: *const c_char) -> i32 {
+    if term.is_null() || utf8.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let Ok(text) = CStr::from_ptr(utf8).to_str() else {
+            return INVALID_UTF8;
+        };
+        (*term).answerback = text.to_string();
+        0
+    }
+}
+
+/// Number of columns a NUL-terminated UTF-8 string would occupy when printed
+/// from column 0 (wide characters count 2, tabs expand, zero-width characters
+/// count 0). Returns -1 on a NULL argument, `INVALID_UTF8` if the text is
+/// malformed.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_measure(term: *const Terminal, utf8: *const c_char) -> i32 {
+    if term.is_null() || utf8.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let Ok(text) = CStr::from_ptr(utf8).to_str() else {
+            return INVALID_UTF8;
+        };
+        (*term).measure(text).min(i32::MAX as usize) as i32
+    }
+}
+
+/// Key codes for `terminal_encode_key`. Values below this are Unicode scalar
+/// values for character keys.
+pub const KEY_ENTER: u32 = 0x11_0000;
+pub const KEY_TAB: u32 = 0x11_0001;
+pub const KEY_BACKSPACE: u32 = 0x11_0002;
+pub const KEY_ESCAPE: u32 = 0x11_0003;
+pub const KEY_UP: u32 = 0x11_0004;
+pub const KEY_DOWN: u32 = 0x11_0005;
+pub const KEY_RIGHT: u32 = 0x11_0006;
+pub const KEY_LEFT: u32 = 0x11_0007;
+pub const KEY_HOME: u32 = 0x11_0008;
+pub const KEY_END: u32 = 0x11_0009;
+pub const KEY_INSERT: u32 = 0x11_000a;
+pub const KEY_DELETE: u32 = 0x11_000b;
+pub const KEY_PAGE_UP: u32 = 0x11_000c;
+pub const KEY_PAGE_DOWN: u32 = 0x11_000d;
+/// F1-F12 are `KEY_F1 + n - 1`
+pub const KEY_F1: u32 = 0x11_0100;
+/// Keypad 0-9 are `KEY_KP_0 + n`
+pub const KEY_KP_0: u32 = 0x11_0200;
+pub const KEY_KP_ENTER: u32 = 0x11_0210;
+pub const KEY_KP_PLUS: u32 = 0x11_0211;
+pub const KEY_KP_MINUS: u32 = 0x11_0212;
+pub const KEY_KP_MULTIPLY: u32 = 0x11_0213;
+pub const KEY_KP_DIVIDE: u32 = 0x11_0214;
+pub const KEY_KP_DECIMAL: u32 = 0x11_0215;
+pub const KEY_KP_EQUAL: u32 = 0x11_0216;
+
+/// Map an FFI key code to a key
+fn key_from_code(code: u32) -> Option<Key> {
+    let key = match code {
+        KEY_ENTER => Key::Enter,
+        KEY_TAB => Key::Tab,
+        KEY_BACKSPACE => Key::Backspace,
+        KEY_ESCAPE => Key::Escape,
+        KEY_UP => Key::Up,
+        KEY_DOWN => Key::Down,
+        KEY_RIGHT => Key::Right,
+        KEY_LEFT => Key::Left,
+        KEY_HOME => Key::Home,
+        KEY_END => Key::End,
+        KEY_INSERT => Key::Insert,
+        KEY_DELETE => Key::Delete,
+        KEY_PAGE_UP => Key::PageUp,
+        KEY_PAGE_DOWN => Key::PageDown,
+        code if (KEY_F1..KEY_F1 + 12).contains(&code) => Key::F((code - KEY_F1 + 1) as u8),
+        code if (KEY_KP_0..KEY_KP_0 + 10).contains(&code) => {
+            Key::Keypad(KeypadKey::Digit((code - KEY_KP_0) as u8))
+        }
+        KEY_KP_ENTER => Key::Keypad(KeypadKey::Enter),
+        KEY_KP_PLUS => Key::Keypad(KeypadKey::Plus),
+        KEY_KP_MINUS => Key::Keypad(KeypadKey::Minus),
+        KEY_KP_MULTIPLY => Key::Keypad(KeypadKey::Multiply),
+        KEY_KP_DIVIDE => Key::Keypad(KeypadKey::Divide),
+        KEY_KP_DECIMAL => Key::Keypad(KeypadKey::Decimal),
+        KEY_KP_EQUAL => Key::Keypad(KeypadKey::Equal),
+        code => Key::Char(char::from_u32(code)?),
+    };
+    Some(key)
+}
+
+/// Encode a key press (a `KEY_*` code or a Unicode scalar value) with
+/// `Modifiers` bits for the current input modes. Returns the full
+/// length of the encoding (0 for unknown keys); pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_encode_key(
+    term: *const Terminal,
+    key: u32,
+    mods: u8,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+    let Some(key) = key_from_code(key) else {
+        return 0;
+    };
+
+    unsafe {
+        let bytes = (*term).encode_key(key, Modifiers(mods));
+        copy_out(&bytes, buffer, buffer_len)
+    }
+}
+
+/// Encode `notches` of mouse wheel (positive: up) as arrow keys for alternate
+/// scroll mode; Shift in `mods` sends Page Up/Down instead. Returns the full
+/// length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_wheel_to_keys(
+    term: *const Terminal,
+    notches: i32,
+    mods: u8,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let bytes = (*term).wheel_to_keys(notches, Modifiers(mods));
+        copy_out(&bytes, buffer, buffer_len)
+    }
+}
+
+/// Arrow keys that move the shell's cursor to a clicked cell, clamped to the
+/// logical line holding the cursor. Returns the full length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_cursor_move_sequence(
+    term: *const Terminal,
+    row: u16,
+    col: u16,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let bytes = (*term).cursor_move_sequence(row as usize, col as usize);
+        copy_out(&bytes, buffer, buffer_len)
+    }
+}
+
+/// Show C0 controls that have no action (and DEL) as control picture glyphs
+/// instead of ignoring them (the default)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_print_control_chars(term: *mut Terminal, enabled: bool) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).print_control_chars = enabled;
+    }
+}
+
+/// Interpret C1 controls (U+0080-U+009F, UTF-8 encoded or raw 8-bit) as
+/// controls instead of ignoring them (the default)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_c1_as_control(term: *mut Terminal, enabled: bool) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).c1_as_control = enabled;
+    }
+}
+
+/// Whether bracketed paste (mode 2004) is on; wrap pastes in `ESC [200~` /
+/// `ESC [201~` when it is
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_bracketed_paste(term: *const Terminal) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe { (*term).bracketed_paste }
+}
+
+/// Make pasted data safe to send (see `Terminal::sanitize_paste`). Writes up
+/// to `buffer_len` bytes and sets `*stripped` (may be NULL) if anything was
+/// changed. Returns the full length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_sanitize_paste(
+    term: *const Terminal,
+    data: *const u8,
+    len: usize,
+    buffer: *mut u8,
+    buffer_len: usize,
+    stripped: *mut bool,
+) -> usize {
+    if term.is_null() || data.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let (bytes, changed) = (*term).sanitize_paste(slice::from_raw_parts(data, len));
+        if !stripped.is_null() {
+            *stripped = changed;
+        }
+        copy_out(&bytes, buffer, buffer_len)
+    }
+}
+
+/// Lines per wheel notch used by `terminal_wheel_to_keys` (default 3)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_scroll_multiplier(term: *mut Terminal, lines: u16) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).scroll_multiplier = lines;
+    }
+}
+
+/// Whether the alternate screen (47/1047/1049) is showing
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_is_alt_screen(term: *const Terminal) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe { (*term).alt_screen }
+}
+
+/// Whether alternate scroll mode (1007) is on, i.e. the wheel should be sent
+/// as keys rather than scroll the view
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_alternate_scroll(term: *const Terminal) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe { (*term).alternate_scroll }
+}
+
+/// Whether the keypad is in application mode (DECKPAM)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_application_keypad(term: *const Terminal) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe { (*term).application_keypad }
+}
+
 /// Get a cell at the specified position
 #[unsafe(no_mangle)]
 pub extern "C" fn terminal_get_cell(term: *const Terminal, row: u16, col: u16) -> CCell {
@@ -163,8 +545,12 @@ pub extern "C" fn terminal_get_cell(term: *const Terminal, row: u16, col: u16) -
 
     unsafe {
         let terminal = &*term;
-        if let Some(cell) = terminal.grid.get_cell(row as usize, col as usize) {
-            CCell::from(cell)
+        if let Some(cell) = terminal
+            .grid
+            .visible_row(row as usize)
+            .and_then(|r| r.cells.get(col as usize))
+        {
+            CCell::from_cell(cell, terminal.reverse_video, &terminal.palette)
         } else {
             CCell {
                 ch: ' ' as u32,
@@ -196,11 +582,10 @@ pub extern "C" fn terminal_get_row(
         let terminal = &*term;
         let cells_buffer = slice::from_raw_parts_mut(buffer, buffer_len);
 
-        if let Some(grid_row) = terminal.grid.rows.get(row as usize) {
-            let count = grid_row.cells.len().min(buffer_len);
-            for (i, cell) in grid_row.cells.iter().take(count).enumerate() {
-                cells_buffer[i] = CCell::from(cell);
-            }
+        if let Some(grid_row) = terminal.grid.visible_row(row as usize) {
+            let cells = convert_row(grid_row, terminal.reverse_video, &terminal.palette);
+            let count = cells.len().min(buffer_len);
+            cells_buffer[..count].copy_from_slice(&cells[..count]);
             count
         } else {
             0
@@ -208,107 +593,1619 @@ pub extern "C" fn terminal_get_row(
     }
 }
 
-/// Get cursor position
-#[unsafe(no_mangle)]
-pub extern "C" fn terminal_get_cursor_row(term: *const Terminal) -> u16 {
-    if term.is_null() {
-        return 0;
-    }
-    unsafe { (*term).cursor.row as u16 }
-}
-
+/// Fill `buffer` with the visible screen as `rows * cols` codepoints, row by
+/// row: 0x20 for blanks, 0 for the second column of a wide character.
+/// Returns `rows * cols`; pass NULL to probe.
 #[unsafe(no_mangle)]
-pub extern "C" fn terminal_get_cursor_col(term: *const Terminal) -> u16 {
+pub extern "C" fn terminal_get_codepoints(
+    term: *const Terminal,
+    buffer: *mut u32,
+    buffer_len: usize,
+) -> usize {
     if term.is_null() {
         return 0;
     }
-    unsafe { (*term).cursor.col as u16 }
-}
-
-/// Resize the terminal
-#[unsafe(no_mangle)]
-pub extern "C" fn terminal_resize(term: *mut Terminal, rows: u16, cols: u16) {
-    if term.is_null() {
-        return;
-    }
 
     unsafe {
-        let terminal = &mut *term;
-        terminal.resize(rows as usize, cols as usize);
+        let terminal = &*term;
+        let codepoints: Vec<u32> = terminal
+            .grid
+            .to_codepoint_matrix()
+            .into_iter()
+            .flatten()
+            .collect();
+        copy_out(&codepoints, buffer, buffer_len)
     }
 }
 
-/// Get dirty rows (rows that have changed)
+/// Fill `buffer` with the whole visible screen as `rows * cols` cells, row
+/// by row, in one call so a renderer never mixes rows from different
+/// updates. Returns `rows * cols`; pass NULL to probe.
 #[unsafe(no_mangle)]
-pub extern "C" fn terminal_get_dirty_rows(
+pub extern "C" fn terminal_get_screen(
     term: *const Terminal,
-    buffer: *mut u16,
+    buffer: *mut CCell,
     buffer_len: usize,
 ) -> usize {
-    if term.is_null() || buffer.is_null() {
+    if term.is_null() {
         return 0;
     }
 
     unsafe {
         let terminal = &*term;
-        let dirty_rows = terminal.grid.dirty_rows();
-        let rows_buffer = slice::from_raw_parts_mut(buffer, buffer_len);
-
-        let count = dirty_rows.len().min(buffer_len);
-        for (i, &row) in dirty_rows.iter().take(count).enumerate() {
-            rows_buffer[i] = row as u16;
-        }
-        count
+        let cells: Vec<CCell> = terminal
+            .visible_snapshot()
+            .iter()
+            .flat_map(|row| convert_row(row, terminal.reverse_video, &terminal.palette))
+            .collect();
+        copy_out(&cells, buffer, buffer_len)
     }
 }
 
-/// Mark all cells as clean
+/// Whether visible row `row` continues the previous one (soft wrap): 1 if
+/// so, 0 if not, -1 for an invalid row
 #[unsafe(no_mangle)]
-pub extern "C" fn terminal_mark_clean(term: *mut Terminal) {
+pub extern "C" fn terminal_row_is_wrapped(term: *const Terminal, row: u16) -> i32 {
     if term.is_null() {
-        return;
+        return -1;
     }
 
     unsafe {
-        let terminal = &mut *term;
-        terminal.grid.mark_clean();
+        let terminal = &*term;
+        match terminal.grid.visible_row(row as usize) {
+            Some(grid_row) => grid_row.wrapped as i32,
+            None => -1,
+        }
     }
 }
 
-/// Read from PTY (non-blocking)
+/// Get a pointer to a visible row in renderer-ready form, without copying.
+/// Rows are converted lazily, only when they changed since the last call.
+/// Writes the cell count to `out_len`; returns NULL for an invalid row. The
+/// pointer stays valid until the terminal is next modified. The `row_fetch`
+/// benchmark compares this with `terminal_get_row`.
 #[unsafe(no_mangle)]
-pub extern "C" fn terminal_read_pty(term: *mut Terminal, buffer: *mut u8, buffer_len: usize) -> isize {
-    if term.is_null() || buffer.is_null() {
-        return -1;
+pub extern "C" fn terminal_get_row_ptr(
+    term: *mut Terminal,
+    row: u16,
+    out_len: *mut usize,
+) -> *const CCell {
+    if term.is_null() || out_len.is_null() {
+        return std::ptr::null();
     }
 
     unsafe {
         let terminal = &mut *term;
-        if let Some(ref pty) = terminal.pty {
-            let buf = slice::from_raw_parts_mut(buffer, buffer_len);
-            match pty.read(buf) {
-                Ok(n) => n as isize,
-                Err(_) => -1,
+        let cells = terminal.row_cache.row(
+            &terminal.grid,
+            terminal.reverse_video,
+            &terminal.palette,
+            row as usize,
+        );
+        match cells {
+            Some(cells) => {
+                *out_len = cells.len();
+                cells.as_ptr()
+            }
+            None => {
+                *out_len = 0;
+                std::ptr::null()
             }
-        } else {
-            -1
         }
     }
 }
 
-/// Get PTY master file descriptor (for select/poll)
+/// Get cursor position
 #[unsafe(no_mangle)]
-pub extern "C" fn terminal_get_pty_fd(term: *const Terminal) -> i32 {
+pub extern "C" fn terminal_get_cursor_row(term: *const Terminal) -> u16 {
     if term.is_null() {
-        return -1;
+        return 0;
     }
+    unsafe { (*term).cursor.row as u16 }
+}
 
-    unsafe {
-        let terminal = &*term;
-        if let Some(ref pty) = terminal.pty {
-            pty.master_fd()
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_cursor_col(term: *const Terminal) -> u16 {
+    if term.is_null() {
+        return 0;
+    }
+    unsafe { (*term).cursor.col as u16 }
+}
+
+/// Cursor shapes returned by `terminal_get_cursor_shape`
+pub const CURSOR_SHAPE_BLOCK: u8 = 0;
+pub const CURSOR_SHAPE_UNDERLINE: u8 = 1;
+pub const CURSOR_SHAPE_BAR: u8 = 2;
+
+/// Get the cursor shape (a `CURSOR_SHAPE_*` value) set by DECSCUSR, and
+/// whether it should blink in `*blink` (may be NULL), combining DECSCUSR
+/// with mode 12 as `Terminal::cursor_blinks` does
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_cursor_shape(term: *const Terminal, blink: *mut bool) -> u8 {
+    if term.is_null() {
+        return CURSOR_SHAPE_BLOCK;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        if !blink.is_null() {
+            *blink = terminal.cursor_blinks();
+        }
+        match terminal.cursor_shape {
+            CursorShape::Block => CURSOR_SHAPE_BLOCK,
+            CursorShape::Underline => CURSOR_SHAPE_UNDERLINE,
+            CursorShape::Bar => CURSOR_SHAPE_BAR,
+        }
+    }
+}
+
+/// Resize the terminal; a size of 0 in either dimension is ignored
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_resize(term: *mut Terminal, rows: u16, cols: u16) {
+    if term.is_null() || rows == 0 || cols == 0 {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.resize(rows as usize, cols as usize);
+    }
+}
+
+/// Set the cell size in pixels so the PTY reports the text area's pixel
+/// dimensions; call before or after `terminal_resize`
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_cell_pixel_size(term: *mut Terminal, width: u16, height: u16) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.set_cell_pixel_size(width, height);
+    }
+}
+
+/// Get dirty rows (rows that have changed)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_dirty_rows(
+    term: *const Terminal,
+    buffer: *mut u16,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() || buffer.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        let dirty_rows = terminal.grid.dirty_rows();
+        let rows_buffer = slice::from_raw_parts_mut(buffer, buffer_len);
+
+        let count = dirty_rows.len().min(buffer_len);
+        for (i, &row) in dirty_rows.iter().take(count).enumerate() {
+            rows_buffer[i] = row as u16;
+        }
+        count
+    }
+}
+
+/// Get dirty column spans so only the changed part of each row is
+/// re-uploaded. Returns the total number of spans; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_dirty_spans(
+    term: *const Terminal,
+    buffer: *mut CDirtySpan,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let spans: Vec<CDirtySpan> = (*term)
+            .grid
+            .dirty_spans()
+            .into_iter()
+            .map(|(row, start, end)| CDirtySpan {
+                row: row as u16,
+                start_col: start as u16,
+                end_col: end as u16,
+            })
+            .collect();
+        copy_out(&spans, buffer, buffer_len)
+    }
+}
+
+/// Get dirty rows with their versions. A renderer running asynchronously
+/// acknowledges what it drew with `terminal_mark_clean_up_to`. Returns the
+/// total number of dirty rows; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_dirty_versions(
+    term: *const Terminal,
+    buffer: *mut CRowVersion,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let versions: Vec<CRowVersion> = (*term)
+            .grid
+            .dirty_versions()
+            .into_iter()
+            .map(|(row, version)| CRowVersion {
+                row: row as u16,
+                version,
+            })
+            .collect();
+        copy_out(&versions, buffer, buffer_len)
+    }
+}
+
+/// Mark rows clean only if they are unchanged since their versions were
+/// fetched; rows written in the meantime stay dirty
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_mark_clean_up_to(
+    term: *mut Terminal,
+    versions: *const CRowVersion,
+    count: usize,
+) {
+    if term.is_null() || versions.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        let observed: Vec<(usize, u64)> = slice::from_raw_parts(versions, count)
+            .iter()
+            .map(|v| (v.row as usize, v.version))
+            .collect();
+        terminal.grid.mark_clean_up_to(&observed);
+    }
+}
+
+/// Mark all cells as clean
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_mark_clean(term: *mut Terminal) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.grid.mark_clean();
+    }
+}
+
+/// `terminal_read_pty`/`terminal_pump` result when the shell side has closed
+pub const PTY_CLOSED: isize = -2;
+
+/// Read from PTY (returns -1 on error, `PTY_CLOSED` once the shell has exited)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_read_pty(
+    term: *mut Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> isize {
+    if term.is_null() || buffer.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        if let Some(ref pty) = terminal.pty {
+            let buf = slice::from_raw_parts_mut(buffer, buffer_len);
+            match pty.read(buf) {
+                Ok(n) => n as isize,
+                Err(e) if crate::pty::is_closed(&e) => PTY_CLOSED,
+                Err(_) => -1,
+            }
+        } else {
+            -1
+        }
+    }
+}
+
+/// Read and process all available PTY output; returns bytes processed or -1 on error
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_pump(term: *mut Terminal) -> isize {
+    if term.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        match terminal.pump() {
+            Ok(n) => n as isize,
+            Err(e) if crate::pty::is_closed(&e) => PTY_CLOSED,
+            Err(_) => -1,
+        }
+    }
+}
+
+/// Get PTY master file descriptor (for select/poll)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_pty_fd(term: *const Terminal) -> i32 {
+    if term.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        if let Some(ref pty) = terminal.pty {
+            pty.master_fd()
         } else {
             -1
         }
     }
 }
+
+/// Search scrollback and screen for a UTF-8 string. Returns the total
+/// number of matches; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_search(
+    term: *const Terminal,
+    needle: *const c_char,
+    case_insensitive: bool,
+    buffer: *mut CSearchMatch,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() || needle.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        let needle = match CStr::from_ptr(needle).to_str() {
+            Ok(needle) => needle,
+            Err(_) => return 0,
+        };
+        let matches: Vec<CSearchMatch> = terminal
+            .grid
+            .search(needle, case_insensitive)
+            .iter()
+            .map(|m| CSearchMatch {
+                start_row: m.start_row as u32,
+                start_col: m.start_col as u16,
+                end_row: m.end_row as u32,
+                end_col: m.end_col as u16,
+            })
+            .collect();
+        copy_out(&matches, buffer, buffer_len)
+    }
+}
+
+/// Copy items into a host buffer, returning the full length so a NULL or
+/// short buffer can be used to probe the required size
+///
+/// # Safety
+///
+/// A non-NULL `buffer` must be valid for writes of `buffer_len` items and
+/// must not overlap `items`.
+unsafe fn copy_out<T: Copy>(items: &[T], buffer: *mut T, buffer_len: usize) -> usize {
+    if !buffer.is_null() {
+        let count = items.len().min(buffer_len);
+        unsafe {
+            std::ptr::copy_nonoverlapping(items.as_ptr(), buffer, count);
+        }
+    }
+    items.len()
+}
+
+/// `copy_out` for UTF-8 text
+///
+/// # Safety
+///
+/// As for `copy_out`.
+unsafe fn copy_text_out(text: &str, buffer: *mut u8, buffer_len: usize) -> usize {
+    unsafe { copy_out(text.as_bytes(), buffer, buffer_len) }
+}
+
+/// Export scrollback and screen as plain text (returns the full byte length;
+/// pass a NULL buffer to probe)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_export_text(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { copy_text_out(&(*term).export_text(false), buffer, buffer_len) }
+}
+
+/// Like `terminal_export_text`, but with runs of blank lines collapsed to one
+/// and leading/trailing blank lines dropped (for "save buffer")
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_export_text_trimmed(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { copy_text_out(&(*term).export_text(true), buffer, buffer_len) }
+}
+
+/// Export lines `start_line..end_line` (absolute line numbers) as plain text
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_export_text_range(
+    term: *const Terminal,
+    start_line: u32,
+    end_line: u32,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let text = (*term).export_text_range(start_line as usize, end_line as usize);
+        copy_text_out(&text, buffer, buffer_len)
+    }
+}
+
+/// Copy OSC 133 prompt marks, oldest first, into `buffer`. Returns the total
+/// number of marks; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_prompt_marks(
+    term: *const Terminal,
+    buffer: *mut CPromptMark,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let marks = &(*term).prompt_marks;
+        if !buffer.is_null() {
+            let out = slice::from_raw_parts_mut(buffer, buffer_len);
+            for (slot, mark) in out.iter_mut().zip(marks) {
+                *slot = CPromptMark {
+                    line: mark.line as u32,
+                    col: mark.col as u16,
+                    kind: match mark.kind {
+                        PromptMarkKind::PromptStart => PROMPT_MARK_PROMPT_START,
+                        PromptMarkKind::CommandStart => PROMPT_MARK_COMMAND_START,
+                        PromptMarkKind::OutputStart => PROMPT_MARK_OUTPUT_START,
+                        PromptMarkKind::CommandEnd => PROMPT_MARK_COMMAND_END,
+                    },
+                    has_exit_code: mark.exit_code.is_some(),
+                    exit_code: mark.exit_code.unwrap_or(0),
+                };
+            }
+        }
+        marks.len()
+    }
+}
+
+/// Scroll the view so prompt mark `index` (as returned by
+/// `terminal_get_prompt_marks`) is at the top; false for an invalid index or
+/// a mark whose line has left the scrollback
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_scroll_to_prompt(term: *mut Terminal, index: u32) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe { (*term).scroll_to_prompt(index as usize) }
+}
+
+/// Drop the scrollback, leaving the screen and cursor untouched
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_clear_scrollback(term: *mut Terminal) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).clear_scrollback();
+    }
+}
+
+/// Absolute line number of the oldest line still in scrollback
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_oldest_line(term: *const Terminal) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).grid.oldest_line() as u32 }
+}
+
+/// Absolute line number of the bottom screen row
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_newest_line(term: *const Terminal) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).grid.newest_line() as u32 }
+}
+
+/// Fill `out` with the scrollback length, view offset and visible height in
+/// one call, for sizing and placing a scrollbar thumb (false on NULL)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_scroll_info(term: *const Terminal, out: *mut CScrollInfo) -> bool {
+    if term.is_null() || out.is_null() {
+        return false;
+    }
+
+    unsafe {
+        let grid = &(*term).grid;
+        *out = CScrollInfo {
+            scrollback_len: grid.scrollback.len() as u32,
+            view_offset: grid.view_offset as u32,
+            visible_rows: grid.rows.len() as u16,
+        };
+        true
+    }
+}
+
+/// Absolute line number shown on visible row `row` (follows the scrollback
+/// view)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_visible_to_absolute(term: *const Terminal, row: u16) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).grid.visible_to_absolute(row as usize) as u32 }
+}
+
+/// Visible row showing absolute line `line`, or -1 when it is not in view
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_absolute_to_visible(term: *const Terminal, line: u32) -> i32 {
+    if term.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        (*term)
+            .grid
+            .absolute_to_visible(line as usize)
+            .map_or(-1, |row| row as i32)
+    }
+}
+
+/// Set the default foreground color; text in it repaints
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_default_fg(term: *mut Terminal, r: u8, g: u8, b: u8) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.set_default_colors(Rgb::new(r, g, b), terminal.palette.background);
+    }
+}
+
+/// Set the default background color; blanks and text on it repaint
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_default_bg(term: *mut Terminal, r: u8, g: u8, b: u8) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.set_default_colors(terminal.palette.foreground, Rgb::new(r, g, b));
+    }
+}
+
+/// Set one of the 16 ANSI palette colors, also as the value OSC 104 restores
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_palette_color(term: *mut Terminal, index: u8, r: u8, g: u8, b: u8) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).set_palette_color(index as usize, Rgb::new(r, g, b));
+    }
+}
+
+/// Get the default foreground color
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_default_fg(term: *const Terminal) -> CRgb {
+    if term.is_null() {
+        return Palette::default().foreground.into();
+    }
+    unsafe { (*term).palette.foreground.into() }
+}
+
+/// Get the default background color
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_default_bg(term: *const Terminal) -> CRgb {
+    if term.is_null() {
+        return Palette::default().background.into();
+    }
+    unsafe { (*term).palette.background.into() }
+}
+
+/// Set the default tab stop spacing (resets all tab stops)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_tab_width(term: *mut Terminal, width: u16) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.set_tab_width(width as usize);
+    }
+}
+
+/// Get the window title as UTF-8 (returns the full byte length; pass NULL to probe)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_title(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { copy_text_out(&(*term).title, buffer, buffer_len) }
+}
+
+/// Return and reset the number of audible bells since the last call
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_take_bell_count(term: *mut Terminal) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).take_bell_count() as u32 }
+}
+
+/// Take the number of OSC strings dropped for exceeding the parser's buffer
+/// (64 KiB of payload) since the last call
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_take_truncated_osc_count(term: *mut Terminal) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).take_truncated_osc_count() as u32 }
+}
+
+/// Reap the shell if it has exited: returns its exit code (128 + signal if
+/// killed), -1 while it is still running, -2 without a PTY or on error
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_child_exit_status(term: *mut Terminal) -> i32 {
+    if term.is_null() {
+        return -2;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        match terminal.pty.as_mut().map(|pty| pty.try_wait()) {
+            Some(Ok(Some(status))) => status,
+            Some(Ok(None)) => -1,
+            _ => -2,
+        }
+    }
+}
+
+/// Turn echo on the PTY on or off (false without a PTY or on error)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_pty_echo(term: *const Terminal, enabled: bool) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        terminal
+            .pty
+            .as_ref()
+            .is_some_and(|pty| pty.set_echo(enabled).is_ok())
+    }
+}
+
+/// Switch the PTY between canonical and raw-ish input (false without a PTY or on error)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_pty_canonical(term: *const Terminal, enabled: bool) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        terminal
+            .pty
+            .as_ref()
+            .is_some_and(|pty| pty.set_canonical(enabled).is_ok())
+    }
+}
+
+/// Send signal `sig` to the shell (false without a child, for an unknown
+/// signal number or on error)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_kill_child(term: *const Terminal, sig: i32) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    let Ok(signal) = Signal::try_from(sig) else {
+        return false;
+    };
+    unsafe {
+        let terminal = &*term;
+        terminal
+            .pty
+            .as_ref()
+            .is_some_and(|pty| pty.kill(signal).is_ok())
+    }
+}
+
+/// Send signal `sig` to the PTY's foreground process group, e.g. SIGINT for
+/// whatever job is running (false without a PTY or on error)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_signal_foreground(term: *const Terminal, sig: i32) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    let Ok(signal) = Signal::try_from(sig) else {
+        return false;
+    };
+    unsafe {
+        let terminal = &*term;
+        terminal
+            .pty
+            .as_ref()
+            .is_some_and(|pty| pty.signal_foreground(signal).is_ok())
+    }
+}
+
+/// Get the last captured sixel DCS payload (returns the full length; pass NULL to probe)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_pending_dcs(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let payload = &(*term).pending_dcs;
+        copy_out(payload, buffer, buffer_len)
+    }
+}
+
+/// Discard the captured DCS payload once the host has rendered it
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_clear_pending_dcs(term: *mut Terminal) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).pending_dcs.clear();
+    }
+}
+
+/// Describe the pending OSC 1337 inline image in `info` and copy its decoded
+/// file data into `buffer`. Returns the full data length, or 0 when no image
+/// is pending; pass NULL for either to skip it. Images over about 48 KiB
+/// (64 KiB of base64) are dropped and counted as truncated OSC strings.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_pending_image(
+    term: *const Terminal,
+    info: *mut CImageInfo,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let Some(image) = &(*term).pending_image else {
+            return 0;
+        };
+        if !info.is_null() {
+            let (width, width_unit) = image_dimension(image.width);
+            let (height, height_unit) = image_dimension(image.height);
+            *info = CImageInfo {
+                width,
+                height,
+                width_unit,
+                height_unit,
+                preserve_aspect_ratio: image.preserve_aspect_ratio,
+                inline_image: image.inline,
+            };
+        }
+        copy_out(&image.data, buffer, buffer_len)
+    }
+}
+
+/// Discard the pending inline image once the host has rendered it
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_clear_pending_image(term: *mut Terminal) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).pending_image = None;
+    }
+}
+
+/// Move up to `buffer_len` bytes of queued query replies into `buffer`,
+/// returning how many were copied. Hosts without a PTY must forward these to
+/// the application; `terminal_pump` writes them to the PTY itself.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_take_responses(
+    term: *mut Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() || buffer.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let responses = &mut (*term).responses;
+        let count = responses.len().min(buffer_len);
+        std::ptr::copy_nonoverlapping(responses.as_ptr(), buffer, count);
+        responses.drain(..count);
+        count
+    }
+}
+
+/// `terminal_process_and_respond` result when the replies don't fit in the
+/// output buffer; they stay queued for `terminal_take_responses`
+pub const RESPONSE_BUFFER_TOO_SMALL: isize = -2;
+
+/// Process input and collect the query replies it produced (DSR, DECRQSS,
+/// OSC color queries, ...) in one call. Returns the reply length written to
+/// `out` (0 if none), -1 on a NULL argument, or `RESPONSE_BUFFER_TOO_SMALL`.
+/// Replies queued by earlier calls are included.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_process_and_respond(
+    term: *mut Terminal,
+    input: *const u8,
+    input_len: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> isize {
+    if term.is_null() || input.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.process_bytes(slice::from_raw_parts(input, input_len));
+        let responses = &terminal.responses;
+        if responses.is_empty() {
+            return 0;
+        }
+        if out.is_null() || responses.len() > out_len {
+            return RESPONSE_BUFFER_TOO_SMALL;
+        }
+        copy_out(responses, out, out_len);
+        std::mem::take(&mut terminal.responses).len() as isize
+    }
+}
+
+/// Normalization modes for `terminal_set_normalization`
+pub const NORMALIZE_NONE: u8 = 0;
+pub const NORMALIZE_NFC: u8 = 1;
+pub const NORMALIZE_NFKC: u8 = 2;
+
+/// Select the Unicode normalization applied to printed text. Unknown modes
+/// are ignored and return -1.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_normalization(term: *mut Terminal, mode: u8) -> i32 {
+    if term.is_null() {
+        return -1;
+    }
+
+    let form = match mode {
+        NORMALIZE_NONE => None,
+        NORMALIZE_NFC => Some(NormalizationForm::Nfc),
+        NORMALIZE_NFKC => Some(NormalizationForm::Nfkc),
+        _ => return -1,
+    };
+    unsafe {
+        (*term).normalize = form;
+    }
+    0
+}
+
+/// Enable or disable recording of ignored escape sequences (a debugging aid)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_record_unhandled(term: *mut Terminal, enabled: bool) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).record_unhandled = enabled;
+    }
+}
+
+/// Get the recorded unhandled sequences as text, one per line (e.g.
+/// "CSI >0q"). Returns the full length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_unhandled(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    let text: String = unsafe { &(*term).unhandled }
+        .iter()
+        .map(|seq| format!("{}\n", seq))
+        .collect();
+    unsafe { copy_text_out(&text, buffer, buffer_len) }
+}
+
+/// Discard the recorded unhandled sequences
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_clear_unhandled(term: *mut Terminal) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).unhandled.clear();
+    }
+}
+
+/// Get the working directory reported via OSC 7 (returns the full byte
+/// length, 0 if unknown; pass NULL to probe)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_cwd(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    use std::os::unix::ffi::OsStrExt;
+
+    if term.is_null() {
+        return 0;
+    }
+
+    let Some(cwd) = (unsafe { &(*term).cwd }) else {
+        return 0;
+    };
+    let bytes = cwd.as_os_str().as_bytes();
+    unsafe { copy_out(bytes, buffer, buffer_len) }
+}
+
+/// Get the cursor color set via OSC 12. Returns false (leaving `out`
+/// untouched) when the application hasn't set one.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_cursor_color(term: *const Terminal, out: *mut CRgb) -> bool {
+    if term.is_null() || out.is_null() {
+        return false;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        match terminal.cursor_color {
+            Some(color) => {
+                *out = terminal.palette.resolve(&color).into();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Serialize the screen as text with SGR sequences, e.g. for copying with
+/// colors (returns the full byte length; pass NULL to probe)
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_to_ansi(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    let ansi = unsafe { (*term).grid.to_ansi() };
+    unsafe { copy_text_out(&ansi, buffer, buffer_len) }
+}
+
+/// Scroll the view `delta` lines back into history (negative: towards the
+/// live screen). `terminal_get_cell`/`terminal_get_row` show the scrolled view.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_scroll_view(term: *mut Terminal, delta: i32) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        (*term).grid.scroll_view(delta as isize);
+    }
+}
+
+/// Milliseconds since output last changed the screen, or -1 before it has
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_idle_ms(term: *const Terminal) -> i64 {
+    if term.is_null() {
+        return -1;
+    }
+
+    unsafe {
+        (*term)
+            .idle_since()
+            .map_or(-1, |idle| idle.as_millis().min(i64::MAX as u128) as i64)
+    }
+}
+
+/// Number of notifications (OSC 9 / OSC 777) waiting for the host
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_notification_count(term: *const Terminal) -> u32 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe { (*term).notifications.len() as u32 }
+}
+
+/// Get the title of the oldest pending notification (empty for OSC 9).
+/// Returns the full byte length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_notification_title(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    match unsafe { (*term).notifications.first() } {
+        Some(note) => unsafe { copy_text_out(&note.title, buffer, buffer_len) },
+        None => 0,
+    }
+}
+
+/// Get the message of the oldest pending notification. Returns the full
+/// byte length; pass NULL to probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_notification_body(
+    term: *const Terminal,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> usize {
+    if term.is_null() {
+        return 0;
+    }
+
+    match unsafe { (*term).notifications.first() } {
+        Some(note) => unsafe { copy_text_out(&note.body, buffer, buffer_len) },
+        None => 0,
+    }
+}
+
+/// Discard the oldest pending notification; false if there was none
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_pop_notification(term: *mut Terminal) -> bool {
+    if term.is_null() {
+        return false;
+    }
+
+    unsafe {
+        let notifications = &mut (*term).notifications;
+        if notifications.is_empty() {
+            return false;
+        }
+        notifications.remove(0);
+        true
+    }
+}
+
+/// Bits of the mode mask used by `terminal_get_modes` / `terminal_set_modes`
+pub const MODE_AUTOWRAP: u64 = 1 << 0;
+pub const MODE_ORIGIN: u64 = 1 << 1;
+pub const MODE_INSERT: u64 = 1 << 2;
+pub const MODE_APP_CURSOR: u64 = 1 << 3;
+pub const MODE_APP_KEYPAD: u64 = 1 << 4;
+pub const MODE_BRACKETED_PASTE: u64 = 1 << 5;
+pub const MODE_CURSOR_VISIBLE: u64 = 1 << 6;
+pub const MODE_REVERSE_VIDEO: u64 = 1 << 7;
+pub const MODE_META_SENDS_ESCAPE: u64 = 1 << 8;
+
+/// Snapshot the common boolean modes as a `MODE_*` bitmask
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_get_modes(term: *const Terminal) -> u64 {
+    if term.is_null() {
+        return 0;
+    }
+
+    unsafe {
+        let terminal = &*term;
+        [
+            (MODE_AUTOWRAP, terminal.autowrap),
+            (MODE_ORIGIN, terminal.origin_mode),
+            (MODE_INSERT, terminal.insert_mode),
+            (MODE_APP_CURSOR, terminal.application_cursor),
+            (MODE_APP_KEYPAD, terminal.application_keypad),
+            (MODE_BRACKETED_PASTE, terminal.bracketed_paste),
+            (MODE_CURSOR_VISIBLE, terminal.cursor_visible),
+            (MODE_REVERSE_VIDEO, terminal.reverse_video),
+            (MODE_META_SENDS_ESCAPE, terminal.meta_sends_escape),
+        ]
+        .into_iter()
+        .filter(|&(_, on)| on)
+        .fold(0, |mask, (bit, _)| mask | bit)
+    }
+}
+
+/// Restore modes from a `terminal_get_modes` snapshot. Unlike the escape
+/// sequences, this doesn't move the cursor (e.g. when origin mode changes).
+#[unsafe(no_mangle)]
+pub extern "C" fn terminal_set_modes(term: *mut Terminal, modes: u64) {
+    if term.is_null() {
+        return;
+    }
+
+    unsafe {
+        let terminal = &mut *term;
+        terminal.set_autowrap(modes & MODE_AUTOWRAP != 0);
+        terminal.origin_mode = modes & MODE_ORIGIN != 0;
+        terminal.insert_mode = modes & MODE_INSERT != 0;
+        terminal.application_cursor = modes & MODE_APP_CURSOR != 0;
+        terminal.application_keypad = modes & MODE_APP_KEYPAD != 0;
+        terminal.bracketed_paste = modes & MODE_BRACKETED_PASTE != 0;
+        terminal.cursor_visible = modes & MODE_CURSOR_VISIBLE != 0;
+        terminal.meta_sends_escape = modes & MODE_META_SENDS_ESCAPE != 0;
+        let reverse_video = modes & MODE_REVERSE_VIDEO != 0;
+        if reverse_video != terminal.reverse_video {
+            terminal.reverse_video = reverse_video;
+            terminal.grid.mark_all_dirty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Cell;
+    use crate::pty::Pty;
+
+    #[test]
+    fn test_send_text_reaches_slave() {
+        let mut terminal = Terminal::new(24, 80);
+        terminal.pty = Some(Pty::new(80, 24).unwrap());
+        let term = &mut terminal as *mut Terminal;
+
+        assert_eq!(terminal_send_text(term, c"ls\n".as_ptr()), 0);
+        let slave = terminal.pty.as_ref().unwrap().slave;
+        let mut buffer = [0u8; 16];
+        let n = nix::unistd::read(slave, &mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"ls\n");
+
+        let invalid = [0xffu8, 0];
+        assert_eq!(
+            terminal_send_text(term, invalid.as_ptr() as *const c_char),
+            INVALID_UTF8
+        );
+        assert_eq!(terminal_send_text(term, std::ptr::null()), -1);
+    }
+
+    #[test]
+    fn test_encode_key_codes() {
+        let mut terminal = Terminal::new(24, 80);
+        let term = &mut terminal as *mut Terminal;
+        let mut buffer = [0u8; 16];
+
+        let n = terminal_encode_key(term, KEY_KP_ENTER, 0, buffer.as_mut_ptr(), buffer.len());
+        assert_eq!(&buffer[..n], b"\r");
+        terminal.process_bytes(b"\x1b=");
+        assert!(terminal_application_keypad(term));
+        let n = terminal_encode_key(term, KEY_KP_ENTER, 0, buffer.as_mut_ptr(), buffer.len());
+        assert_eq!(&buffer[..n], b"\x1bOM");
+
+        let n = terminal_encode_key(term, 'a' as u32, Modifiers::CTRL, buffer.as_mut_ptr(), 16);
+        assert_eq!(&buffer[..n], b"\x01");
+        assert_eq!(
+            terminal_encode_key(term, KEY_F1 + 11, 0, std::ptr::null_mut(), 0),
+            5
+        );
+    }
+
+    #[test]
+    fn test_get_unhandled_text() {
+        let mut terminal = Terminal::new(24, 80);
+        let term = &mut terminal as *mut Terminal;
+        terminal_set_record_unhandled(term, true);
+        terminal.process_bytes(b"\x1b[>q\x1b[?9999l");
+
+        let len = terminal_get_unhandled(term, std::ptr::null_mut(), 0);
+        let mut buffer = vec![0u8; len];
+        terminal_get_unhandled(term, buffer.as_mut_ptr(), len);
+        assert_eq!(buffer, b"CSI >0q\nCSI ?9999l\n");
+
+        terminal_clear_unhandled(term);
+        assert_eq!(terminal_get_unhandled(term, std::ptr::null_mut(), 0), 0);
+    }
+
+    #[test]
+    fn test_row_ptr_matches_get_row() {
+        let mut terminal = Terminal::new(4, 20);
+        terminal.process_bytes(b"\x1b[1;31mred\x1b[0m \x1b[44mblue\r\nsecond");
+        let term = &mut terminal as *mut Terminal;
+
+        let check = |term: *mut Terminal| {
+            for row in 0..4 {
+                let mut copied = vec![CCell::from(&Cell::default()); 20];
+                let n = terminal_get_row(term, row, copied.as_mut_ptr(), copied.len());
+                let mut len = 0;
+                let ptr = terminal_get_row_ptr(term, row, &mut len);
+                let packed = unsafe { slice::from_raw_parts(ptr, len) };
+                assert_eq!(packed, &copied[..n]);
+            }
+        };
+        check(term);
+
+        // Changed rows, reverse video and the palette all refresh the cache
+        terminal.process_bytes(b"\x1b[1;1Hx\x1b[?5h");
+        check(term);
+        terminal.set_palette_color(1, Rgb::new(1, 2, 3));
+        check(term);
+
+        // Scrollback rows in view keep their versions, yet still repaint
+        terminal.process_bytes(b"\x1b[4;1H\nmore");
+        assert_eq!(terminal.grid.scrollback.len(), 1);
+        terminal.grid.scroll_view(1);
+        check(term);
+        terminal.set_palette_color(1, Rgb::new(4, 5, 6));
+        check(term);
+
+        let mut len = 7;
+        assert!(terminal_get_row_ptr(term, 9, &mut len).is_null());
+        assert_eq!(len, 0);
+    }
+
+    #[test]
+    fn test_modes_round_trip() {
+        let term = terminal_new(4, 10);
+        assert_eq!(
+            terminal_get_modes(term),
+            MODE_AUTOWRAP | MODE_CURSOR_VISIBLE | MODE_META_SENDS_ESCAPE
+        );
+
+        let data = b"\x1b[?1h\x1b[?2004h\x1b[4h\x1b[?25l\x1b=\x1b[?1034l";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        let modes = terminal_get_modes(term);
+        assert_eq!(
+            modes,
+            MODE_AUTOWRAP | MODE_INSERT | MODE_APP_CURSOR | MODE_APP_KEYPAD | MODE_BRACKETED_PASTE
+        );
+
+        let other = terminal_new(4, 10);
+        terminal_set_modes(other, modes);
+        assert_eq!(terminal_get_modes(other), modes);
+        let all = (MODE_META_SENDS_ESCAPE << 1) - 1;
+        terminal_set_modes(other, all);
+        assert_eq!(terminal_get_modes(other), all);
+        terminal_set_modes(other, 0);
+        assert_eq!(terminal_get_modes(other), 0);
+
+        terminal_free(term);
+        terminal_free(other);
+    }
+
+    #[test]
+    fn test_cursor_shape_reports_blink() {
+        let term = terminal_new(2, 10);
+        let mut blink = false;
+        assert_eq!(
+            terminal_get_cursor_shape(term, &mut blink),
+            CURSOR_SHAPE_BLOCK
+        );
+        assert!(blink);
+
+        let data = b"\x1b[5 q\x1b[?12l";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        assert_eq!(
+            terminal_get_cursor_shape(term, &mut blink),
+            CURSOR_SHAPE_BAR
+        );
+        assert!(!blink);
+        let data = b"\x1b[?12h";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        terminal_get_cursor_shape(term, &mut blink);
+        assert!(blink);
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_scroll_info() {
+        let term = terminal_new(3, 10);
+        let data = b"1\r\n2\r\n3\r\n4\r\n5\r\n6";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        terminal_scroll_view(term, 2);
+
+        let mut info = CScrollInfo {
+            scrollback_len: 0,
+            view_offset: 0,
+            visible_rows: 0,
+        };
+        assert!(terminal_get_scroll_info(term, &mut info));
+        assert_eq!(
+            (info.scrollback_len, info.view_offset, info.visible_rows),
+            (3, 2, 3)
+        );
+        // The view's top line is `view_offset` lines above the screen's
+        let top = terminal_visible_to_absolute(term, 0);
+        assert_eq!(
+            top,
+            terminal_oldest_line(term) + info.scrollback_len - info.view_offset
+        );
+
+        // Scrolling past the history clamps the offset to its length
+        terminal_scroll_view(term, 10);
+        terminal_get_scroll_info(term, &mut info);
+        assert_eq!(info.view_offset, info.scrollback_len);
+        assert!(!terminal_get_scroll_info(term, std::ptr::null_mut()));
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_get_codepoints() {
+        let term = terminal_new(2, 3);
+        let data = "a字\r\nxyz".as_bytes();
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+
+        assert_eq!(terminal_get_codepoints(term, std::ptr::null_mut(), 0), 6);
+        let mut buffer = [u32::MAX; 6];
+        assert_eq!(terminal_get_codepoints(term, buffer.as_mut_ptr(), 6), 6);
+        assert_eq!(buffer, [0x61, '字' as u32, 0, 0x78, 0x79, 0x7a]);
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_get_screen() {
+        let term = terminal_new(2, 3);
+        let data = b"abcde";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+
+        assert_eq!(terminal_get_screen(term, std::ptr::null_mut(), 0), 6);
+        let mut buffer: Vec<CCell> = (0..6).map(|_| terminal_get_cell(term, 1, 2)).collect();
+        assert_eq!(terminal_get_screen(term, buffer.as_mut_ptr(), 4), 6);
+        let text: String = buffer[..4]
+            .iter()
+            .map(|cell| char::from_u32(cell.ch).unwrap())
+            .collect();
+        assert_eq!(text, "abcd");
+        assert_eq!(buffer[0].flags & CELL_FLAG_ROW_WRAPPED, 0);
+        assert_ne!(buffer[3].flags & CELL_FLAG_ROW_WRAPPED, 0);
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_row_is_wrapped() {
+        let term = terminal_new(3, 5);
+        let data = b"abcdefg\r\nx";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+
+        assert_eq!(terminal_row_is_wrapped(term, 0), 0);
+        assert_eq!(terminal_row_is_wrapped(term, 1), 1);
+        assert_eq!(terminal_row_is_wrapped(term, 2), 0);
+        assert_eq!(terminal_row_is_wrapped(term, 3), -1);
+
+        let mut cells = [CCell::from(&Cell::default()); 5];
+        assert_eq!(terminal_get_row(term, 1, cells.as_mut_ptr(), 5), 5);
+        assert_ne!(cells[0].flags & CELL_FLAG_ROW_WRAPPED, 0);
+        assert_eq!(cells[1].flags & CELL_FLAG_ROW_WRAPPED, 0);
+
+        let mut len = 0;
+        let row = terminal_get_row_ptr(term, 2, &mut len);
+        assert_eq!(unsafe { (*row).flags } & CELL_FLAG_ROW_WRAPPED, 0);
+        let row = terminal_get_row_ptr(term, 1, &mut len);
+        assert_ne!(unsafe { (*row).flags } & CELL_FLAG_ROW_WRAPPED, 0);
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_process_and_respond() {
+        let term = terminal_new(5, 10);
+        let mut out = [0u8; 16];
+        let input = b"ab\x1b[6n";
+        let n =
+            terminal_process_and_respond(term, input.as_ptr(), input.len(), out.as_mut_ptr(), 16);
+        assert_eq!(&out[..n as usize], b"\x1b[1;3R");
+
+        let input = b"cd";
+        assert_eq!(
+            terminal_process_and_respond(term, input.as_ptr(), input.len(), out.as_mut_ptr(), 16),
+            0
+        );
+
+        // Too small: the reply stays queued
+        let input = b"\x1b[6n";
+        assert_eq!(
+            terminal_process_and_respond(term, input.as_ptr(), input.len(), out.as_mut_ptr(), 4),
+            RESPONSE_BUFFER_TOO_SMALL
+        );
+        assert_eq!(terminal_take_responses(term, out.as_mut_ptr(), 16), 6);
+        assert_eq!(&out[..6], b"\x1b[1;5R");
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_pending_image() {
+        let term = terminal_new(2, 10);
+        let (no_info, no_buffer) = (std::ptr::null_mut(), std::ptr::null_mut());
+        assert_eq!(terminal_get_pending_image(term, no_info, no_buffer, 0), 0);
+
+        let data = b"\x1b]1337;File=width=4;height=20px;inline=1:AAEC\x07";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        let mut info = CImageInfo {
+            width: 0,
+            height: 0,
+            width_unit: 0,
+            height_unit: 0,
+            preserve_aspect_ratio: false,
+            inline_image: false,
+        };
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            terminal_get_pending_image(term, &mut info, buffer.as_mut_ptr(), 8),
+            3
+        );
+        assert_eq!(&buffer[..3], [0, 1, 2]);
+        assert_eq!((info.width, info.width_unit), (4, IMAGE_UNIT_CELLS));
+        assert_eq!((info.height, info.height_unit), (20, IMAGE_UNIT_PIXELS));
+        assert!(info.inline_image && info.preserve_aspect_ratio);
+
+        terminal_clear_pending_image(term);
+        assert_eq!(terminal_get_pending_image(term, no_info, no_buffer, 0), 0);
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_read_pty_reports_closure() {
+        let mut terminal = Terminal::new(24, 80);
+        let mut pty = Pty::new(80, 24).unwrap();
+        pty.spawn_shell(Some("/bin/sh")).unwrap();
+        let pid = pty.child_pid.unwrap();
+        terminal.pty = Some(pty);
+        let term = &mut terminal as *mut Terminal;
+
+        nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL).unwrap();
+        let mut buffer = [0u8; 256];
+        let result = loop {
+            let n = terminal_read_pty(term, buffer.as_mut_ptr(), buffer.len());
+            if n < 0 {
+                break n;
+            }
+        };
+        assert_eq!(result, PTY_CLOSED);
+        assert_eq!(terminal_pump(term), PTY_CLOSED);
+
+        let status = loop {
+            match terminal_child_exit_status(term) {
+                -1 => std::thread::sleep(std::time::Duration::from_millis(10)),
+                status => break status,
+            }
+        };
+        assert_eq!(status, 128 + libc::SIGKILL);
+    }
+
+    #[test]
+    fn test_search_reports_total() {
+        let term = terminal_new(2, 10);
+        let data = b"ab ab\r\nab";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        let needle = c"ab";
+
+        assert_eq!(
+            terminal_search(term, needle.as_ptr(), false, std::ptr::null_mut(), 0),
+            3
+        );
+        let mut buffer = [CSearchMatch {
+            start_row: 0,
+            start_col: 0,
+            end_row: 0,
+            end_col: 0,
+        }; 2];
+        assert_eq!(
+            terminal_search(term, needle.as_ptr(), false, buffer.as_mut_ptr(), 2),
+            3
+        );
+        assert_eq!(
+            (buffer[1].start_row, buffer[1].start_col, buffer[1].end_col),
+            (0, 3, 4)
+        );
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_dirty_spans_reports_total() {
+        let term = terminal_new(3, 10);
+        terminal_mark_clean(term);
+        let data = b"\x1b[2;4Hx\x1b[3;1Hyz";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+
+        assert_eq!(terminal_get_dirty_spans(term, std::ptr::null_mut(), 0), 2);
+        let mut buffer = [CDirtySpan {
+            row: 0,
+            start_col: 0,
+            end_col: 0,
+        }; 1];
+        assert_eq!(terminal_get_dirty_spans(term, buffer.as_mut_ptr(), 1), 2);
+        assert_eq!(
+            (buffer[0].row, buffer[0].start_col, buffer[0].end_col),
+            (1, 3, 3)
+        );
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_dirty_versions_reports_total() {
+        let term = terminal_new(3, 10);
+        terminal_mark_clean(term);
+        let data = b"\x1b[2;4Hx\x1b[3;1Hyz";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+
+        let total = terminal_get_dirty_versions(term, std::ptr::null_mut(), 0);
+        assert_eq!(total, 2);
+        let mut buffer = [CRowVersion { row: 0, version: 0 }; 1];
+        assert_eq!(terminal_get_dirty_versions(term, buffer.as_mut_ptr(), 1), 2);
+        assert_eq!(buffer[0].row, 1);
+
+        // Acknowledging the one row fetched leaves the other dirty
+        terminal_mark_clean_up_to(term, buffer.as_ptr(), 1);
+        assert_eq!(
+            terminal_get_dirty_versions(term, std::ptr::null_mut(), 0),
+            1
+        );
+
+        terminal_free(term);
+    }
+
+    #[test]
+    fn test_zero_size() {
+        let term = terminal_new(0, 10);
+        let data = b"a\r\nb";
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        assert_eq!(unsafe { (*term).grid.rows.len() }, 1);
+
+        // A resize to no rows or columns is ignored
+        terminal_resize(term, 3, 10);
+        terminal_resize(term, 0, 10);
+        terminal_resize(term, 3, 0);
+        assert_eq!(unsafe { ((*term).rows, (*term).cols) }, (3, 10));
+        terminal_free(term);
+
+        let term = terminal_new(3, 0);
+        terminal_process_bytes(term, data.as_ptr(), data.len());
+        assert_eq!(unsafe { (*term).grid.cols }, 1);
+
+        terminal_free(term);
+    }
+}
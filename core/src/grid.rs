@@ -1,4 +1,15 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of row versions. Shared by all grids so a version identifies one
+/// modification of one row even after rows move (scrolling, resize).
+static ROW_VERSION: AtomicU64 = AtomicU64::new(1);
+
+fn next_row_version() -> u64 {
+    ROW_VERSION.fetch_add(1, Ordering::Relaxed)
+}
 
 /// RGB color representation
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -12,6 +23,57 @@ impl Rgb {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
+
+    /// Format as an X11 `rgb:rrrr/gggg/bbbb` spec, as used in OSC query replies
+    pub fn to_spec(&self) -> String {
+        format!(
+            "rgb:{:04x}/{:04x}/{:04x}",
+            self.r as u16 * 257,
+            self.g as u16 * 257,
+            self.b as u16 * 257
+        )
+    }
+
+    /// Parse an X11 color spec as used by OSC 4/10/11: `#rgb`, `#rrggbb`
+    /// or `rgb:r/g/b` with 1-4 hex digits per channel
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        // Scale a channel of `digits` hex digits down to 8 bits
+        fn channel(hex: &str) -> Option<u8> {
+            if hex.is_empty() || hex.len() > 4 {
+                return None;
+            }
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some((value * 255 / max) as u8)
+        }
+
+        if let Some(hex) = spec.strip_prefix('#') {
+            let width = match hex.len() {
+                3 => 1,
+                6 => 2,
+                _ => return None,
+            };
+            let r = channel(&hex[0..width])?;
+            let g = channel(&hex[width..2 * width])?;
+            let b = channel(&hex[2 * width..])?;
+            return Some(Self::new(r, g, b));
+        }
+
+        let mut parts = spec.strip_prefix("rgb:")?.split('/');
+        let r = channel(parts.next()?)?;
+        let g = channel(parts.next()?)?;
+        let b = channel(parts.next()?)?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self::new(r, g, b))
+    }
+
+    /// This color darkened for dim (faint) text
+    pub fn dimmed(&self) -> Self {
+        let dim = |c: u8| (c as u16 * 2 / 3) as u8;
+        Self::new(dim(self.r), dim(self.g), dim(self.b))
+    }
 }
 
 /// Named ANSI colors (0-15)
@@ -51,23 +113,104 @@ impl Default for Color {
     }
 }
 
+/// RGB values for the 16 ANSI colors plus the default foreground and
+/// background; the rest of the 256-color range is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    pub ansi: [Rgb; 16],
+    pub foreground: Rgb,
+    pub background: Rgb,
+    /// Draw bold text in colors 0-7 with their bright variants (8-15), as
+    /// many terminals do; see `Cell::display_colors`
+    #[serde(default)]
+    pub bold_is_bright: bool,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let ansi = [
+            (0, 0, 0),       // Black
+            (205, 49, 49),   // Red
+            (13, 188, 121),  // Green
+            (229, 229, 16),  // Yellow
+            (36, 114, 200),  // Blue
+            (188, 63, 188),  // Magenta
+            (17, 168, 205),  // Cyan
+            (229, 229, 229), // White
+            (102, 102, 102), // Bright Black
+            (241, 76, 76),   // Bright Red
+            (35, 209, 139),  // Bright Green
+            (245, 245, 67),  // Bright Yellow
+            (59, 142, 234),  // Bright Blue
+            (214, 112, 214), // Bright Magenta
+            (41, 184, 219),  // Bright Cyan
+            (255, 255, 255), // Bright White
+        ]
+        .map(|(r, g, b)| Rgb::new(r, g, b));
+        Self {
+            ansi,
+            foreground: Rgb::new(200, 200, 200),
+            background: Rgb::new(20, 20, 20),
+            bold_is_bright: false,
+        }
+    }
+}
+
+impl Palette {
+    /// Resolve a color to RGB
+    pub fn resolve(&self, color: &Color) -> Rgb {
+        match *color {
+            Color::Spec(rgb) => rgb,
+            Color::Spec256(idx) if idx < 16 => self.ansi[idx as usize],
+            Color::Spec256(idx) if idx < 232 => {
+                // 216 color cube
+                let idx = idx - 16;
+                Rgb::new((idx / 36) * 51, ((idx % 36) / 6) * 51, (idx % 6) * 51)
+            }
+            Color::Spec256(idx) => {
+                // Grayscale ramp
+                let gray = (idx - 232) * 10 + 8;
+                Rgb::new(gray, gray, gray)
+            }
+            Color::Named(NamedColor::Foreground) => self.foreground,
+            Color::Named(NamedColor::Background) => self.background,
+            Color::Named(named) => self.ansi[named as usize],
+        }
+    }
+}
+
 /// Cell flags for text attributes
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub struct CellFlags(pub u8);
+pub struct CellFlags(pub u16);
 
 impl CellFlags {
-    pub const BOLD: u8 = 0b0000_0001;
-    pub const ITALIC: u8 = 0b0000_0010;
-    pub const UNDERLINE: u8 = 0b0000_0100;
-    pub const BLINK: u8 = 0b0000_1000;
-    pub const INVERSE: u8 = 0b0001_0000;
-    pub const STRIKETHROUGH: u8 = 0b0010_0000;
+    pub const BOLD: u16 = 0b0000_0000_0001;
+    pub const ITALIC: u16 = 0b0000_0000_0010;
+    pub const UNDERLINE: u16 = 0b0000_0000_0100;
+    pub const BLINK: u16 = 0b0000_0000_1000;
+    pub const INVERSE: u16 = 0b0000_0001_0000;
+    pub const STRIKETHROUGH: u16 = 0b0000_0010_0000;
+    /// Set by DECSCA; protected cells survive selective erase (DECSED/DECSEL)
+    pub const PROTECTED: u16 = 0b0000_0100_0000;
+    /// SGR 6; distinct from (slow) BLINK so renderers can vary the rate
+    pub const RAPID_BLINK: u16 = 0b0000_1000_0000;
+    /// SGR 53
+    pub const OVERLINE: u16 = 0b0001_0000_0000;
+    /// A double-width character; the next cell is its `WIDE_SPACER`
+    pub const WIDE: u16 = 0b0010_0000_0000;
+    /// Right half of a double-width character, not drawn on its own
+    pub const WIDE_SPACER: u16 = 0b0100_0000_0000;
+    /// Blank left in the last column when a wide character wrapped early;
+    /// padding rather than content, so reflow drops it
+    pub const WRAP_PAD: u16 = 0b1000_0000_0000;
+    /// SGR 2 (faint)
+    pub const DIM: u16 = 0b1_0000_0000_0000;
 
     pub fn new() -> Self {
         Self(0)
     }
 
-    pub fn set(&mut self, flag: u8, enabled: bool) {
+    pub fn set(&mut self, flag: u16, enabled: bool) {
         if enabled {
             self.0 |= flag;
         } else {
@@ -75,7 +218,7 @@ impl CellFlags {
         }
     }
 
-    pub fn contains(&self, flag: u8) -> bool {
+    pub fn contains(&self, flag: u16) -> bool {
         self.0 & flag != 0
     }
 
@@ -98,8 +241,71 @@ impl Default for CellFlags {
     }
 }
 
+/// SGR parameters selecting `color`, given the base codes for the normal,
+/// bright and extended forms (30/90/38 for foreground, 40/100/48 for background)
+fn sgr_color(color: Color, normal: u8, bright: u8, extended: u8) -> Vec<String> {
+    match color {
+        Color::Named(named) => match named as u8 {
+            n @ 0..=7 => vec![(normal + n).to_string()],
+            n @ 8..=15 => vec![(bright + n - 8).to_string()],
+            // Foreground/Background: the default color
+            _ => vec![(normal + 9).to_string()],
+        },
+        Color::Spec256(index) => vec![extended.to_string(), "5".into(), index.to_string()],
+        Color::Spec(rgb) => vec![
+            extended.to_string(),
+            "2".into(),
+            rgb.r.to_string(),
+            rgb.g.to_string(),
+            rgb.b.to_string(),
+        ],
+    }
+}
+
+/// SGR parameters, starting with a reset, that select `flags` and any colors
+/// differing from `defaults` (foreground, background)
+pub(crate) fn sgr_params(
+    flags: CellFlags,
+    fg: Color,
+    bg: Color,
+    defaults: (Color, Color),
+) -> String {
+    let mut params = vec![String::from("0")];
+    let sgr_flags = [
+        (CellFlags::BOLD, "1"),
+        (CellFlags::DIM, "2"),
+        (CellFlags::ITALIC, "3"),
+        (CellFlags::UNDERLINE, "4"),
+        (CellFlags::BLINK, "5"),
+        (CellFlags::RAPID_BLINK, "6"),
+        (CellFlags::INVERSE, "7"),
+        (CellFlags::STRIKETHROUGH, "9"),
+        (CellFlags::OVERLINE, "53"),
+    ];
+    for (flag, param) in sgr_flags {
+        if flags.contains(flag) {
+            params.push(param.to_string());
+        }
+    }
+    if fg != defaults.0 {
+        params.extend(sgr_color(fg, 30, 90, 38));
+    }
+    if bg != defaults.1 {
+        params.extend(sgr_color(bg, 40, 100, 48));
+    }
+    params.join(";")
+}
+
+/// The part of a cell's look that SGR reproduces: its colors and the flags
+/// other than protection and wide-character bookkeeping
+fn ansi_style(cell: &Cell) -> (CellFlags, Color, Color) {
+    let flags =
+        CellFlags(cell.flags.0 & !(CellFlags::PROTECTED | CellFlags::WIDE | CellFlags::WRAP_PAD));
+    (flags, cell.fg, cell.bg)
+}
+
 /// A single cell in the terminal grid
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cell {
     pub c: char,
     pub fg: Color,
@@ -117,6 +323,46 @@ impl Cell {
         }
     }
 
+    /// Resolve the colors to display, swapping the default foreground and
+    /// background when screen-wide reverse video (DECSCNM) is active.
+    /// Per-cell INVERSE is left for the renderer to apply on top.
+    pub fn resolved_colors(&self, reverse_video: bool) -> (Color, Color) {
+        if !reverse_video {
+            return (self.fg, self.bg);
+        }
+
+        let swap = |color: Color| match color {
+            Color::Named(NamedColor::Foreground) => Color::Named(NamedColor::Background),
+            Color::Named(NamedColor::Background) => Color::Named(NamedColor::Foreground),
+            other => other,
+        };
+        (swap(self.fg), swap(self.bg))
+    }
+
+    /// The RGB foreground and background this cell is drawn with: bold
+    /// brightens named colors 0-7 when `palette.bold_is_bright` is set, dim
+    /// darkens the foreground, and INVERSE swaps the two. Screen-wide reverse
+    /// video is not applied; see `resolved_colors`.
+    pub fn display_colors(&self, palette: &Palette) -> (Rgb, Rgb) {
+        let mut fg = match self.fg {
+            Color::Named(named)
+                if palette.bold_is_bright && self.flags.is_bold() && (named as usize) < 8 =>
+            {
+                palette.ansi[named as usize + 8]
+            }
+            color => palette.resolve(&color),
+        };
+        let bg = palette.resolve(&self.bg);
+        if self.flags.contains(CellFlags::DIM) {
+            fg = fg.dimmed();
+        }
+        if self.flags.contains(CellFlags::INVERSE) {
+            (bg, fg)
+        } else {
+            (fg, bg)
+        }
+    }
+
     pub fn reset(&mut self) {
         self.c = ' ';
         self.fg = Color::default();
@@ -136,36 +382,165 @@ impl Default for Cell {
 pub struct Row {
     pub cells: Vec<Cell>,
     pub dirty: bool,
+    /// Inclusive column range changed since the last `mark_clean`
+    pub dirty_span: Option<(usize, usize)>,
+    /// This row continues the previous one (soft wrap at the right margin)
+    pub wrapped: bool,
+    /// Changes on every modification; see `Grid::mark_clean_up_to`
+    #[serde(default)]
+    pub version: u64,
 }
 
 impl Row {
     pub fn new(cols: usize) -> Self {
+        Self::filled_with(cols, &Cell::default())
+    }
+
+    /// A row of copies of `template` (e.g. blanks in the default colors)
+    pub fn filled_with(cols: usize, template: &Cell) -> Self {
         Self {
-            cells: vec![Cell::default(); cols],
+            cells: vec![*template; cols],
             dirty: true,
+            dirty_span: Some((0, cols.saturating_sub(1))),
+            wrapped: false,
+            version: next_row_version(),
         }
     }
 
-    pub fn clear(&mut self) {
-        for cell in &mut self.cells {
-            cell.reset();
-        }
+    /// Mark columns `start..=end` as changed, widening any existing span
+    pub fn mark_dirty(&mut self, start: usize, end: usize) {
         self.dirty = true;
+        self.version = next_row_version();
+        self.dirty_span = Some(match self.dirty_span {
+            Some((s, e)) => (s.min(start), e.max(end)),
+            None => (start, end),
+        });
     }
 
-    pub fn resize(&mut self, cols: usize) {
-        self.cells.resize(cols, Cell::default());
-        self.dirty = true;
+    /// Mark the whole row as changed
+    pub fn mark_all_dirty(&mut self) {
+        self.mark_dirty(0, self.cells.len().saturating_sub(1));
+    }
+
+    pub fn clear(&mut self, template: &Cell) {
+        self.cells.fill(*template);
+        self.mark_all_dirty();
+        self.wrapped = false;
+    }
+
+    /// The row's characters, including trailing blanks. Control characters
+    /// (only reachable by writing cells directly) come out as spaces so
+    /// copied text is always plain.
+    pub fn text(&self) -> String {
+        self.cells
+            .iter()
+            .filter(|cell| !cell.flags.contains(CellFlags::WIDE_SPACER))
+            .map(|cell| if cell.c.is_control() { ' ' } else { cell.c })
+            .collect()
+    }
+
+    /// Column where character `char_index` of `text()` starts. Wide
+    /// characters span two columns and tabs were expanded to blanks when
+    /// printed, so this differs from the index once either appears. Indices
+    /// past the end map to the row width.
+    pub fn visual_col_of_char(&self, char_index: usize) -> usize {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.flags.contains(CellFlags::WIDE_SPACER))
+            .nth(char_index)
+            .map_or(self.cells.len(), |(col, _)| col)
+    }
+
+    /// Index into `text()` of the character covering column `col`; the right
+    /// half of a wide character maps to that character
+    pub fn char_at_visual_col(&self, col: usize) -> usize {
+        let col = col.min(self.cells.len());
+        let spacers = self.cells[..col]
+            .iter()
+            .filter(|cell| cell.flags.contains(CellFlags::WIDE_SPACER))
+            .count();
+        let on_spacer = self
+            .cells
+            .get(col)
+            .is_some_and(|cell| cell.flags.contains(CellFlags::WIDE_SPACER));
+        col - spacers - usize::from(on_spacer)
+    }
+
+    pub fn resize(&mut self, cols: usize, template: &Cell) {
+        self.cells.resize(cols, *template);
+        self.mark_all_dirty();
     }
 }
 
+/// A search hit. Rows are absolute line numbers (see `Grid::absolute_line`),
+/// which stay valid as older lines scroll off. `end_row`/`end_col`
+/// address the last matching cell, which may sit on a later row when the
+/// match crosses a soft wrap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start_row: usize,
+    pub start_col: usize,
+    pub end_row: usize,
+    pub end_col: usize,
+}
+
 /// The terminal grid
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub struct Grid {
     pub rows: Vec<Row>,
     pub cols: usize,
-    pub scrollback: Vec<Row>,
+    /// Rows scrolled off the top, oldest first. Rows may share storage when
+    /// `compress_scrollback` is set; screen rows never do. Each row is an
+    /// `Arc` whether or not compression is on (read through `Arc::as_ref`
+    /// or deref); the serialized form is a plain list of rows.
+    pub scrollback: VecDeque<Arc<Row>>,
     pub max_scrollback: usize,
+    /// Store a scrollback row identical to the newest one, or blank, as
+    /// another reference to the same row instead of a copy, so runs of
+    /// blank or repeated lines cost a pointer each
+    #[serde(default)]
+    pub compress_scrollback: bool,
+    /// The blank row most recently shared into scrollback
+    #[serde(skip)]
+    shared_blank: Option<Arc<Row>>,
+    /// Absolute line number of the oldest scrollback row. Lines are numbered
+    /// from the first one ever produced (scrollback, then screen), so a
+    /// number keeps naming the same line until it is dropped.
+    #[serde(default)]
+    pub first_line: usize,
+    /// Blank cell used for new and erased cells
+    pub template: Cell,
+    /// How many lines the view is scrolled back into history (0: live screen)
+    #[serde(default)]
+    pub view_offset: usize,
+    /// Running count of lines scrolled up off a region, for damage reporting
+    #[serde(skip)]
+    pub lines_scrolled: u64,
+    /// Changes whenever screen rows are edited, scrolled or redrawn. Taken
+    /// from the row version counter, so it also differs between grids.
+    #[serde(skip)]
+    pub generation: u64,
+    /// Changes whenever every row must be redrawn (`mark_all_dirty`), so
+    /// caches of converted rows, scrollback ones included, know to drop them
+    #[serde(skip)]
+    pub repaint_generation: u64,
+}
+
+impl Serialize for Grid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Grid::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Grid {
+    /// Rows are deserialized one by one, so sharing is re-established here
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut grid = Grid::deserialize(deserializer)?;
+        grid.reshare_scrollback();
+        Ok(grid)
+    }
 }
 
 impl Grid {
@@ -173,11 +548,106 @@ impl Grid {
         Self {
             rows: (0..rows).map(|_| Row::new(cols)).collect(),
             cols,
-            scrollback: Vec::new(),
+            scrollback: VecDeque::new(),
             max_scrollback,
+            compress_scrollback: false,
+            shared_blank: None,
+            first_line: 0,
+            template: Cell::default(),
+            view_offset: 0,
+            lines_scrolled: 0,
+            generation: 0,
+            repaint_generation: 0,
+        }
+    }
+
+    /// Screen row `row` for editing; the caller marks what it changes dirty
+    pub fn row_mut(&mut self, row: usize) -> Option<&mut Row> {
+        if row < self.rows.len() {
+            self.generation = next_row_version();
+        }
+        self.rows.get_mut(row)
+    }
+
+    /// Row shown at visible position `row`, taking the view offset into account
+    pub fn visible_row(&self, row: usize) -> Option<&Row> {
+        if row < self.view_offset {
+            self.scrollback
+                .get(self.scrollback.len() - self.view_offset + row)
+                .map(Arc::as_ref)
+        } else {
+            self.rows.get(row - self.view_offset)
         }
     }
 
+    /// The visible rows as Unicode scalar values, one entry per column:
+    /// 0x20 for blanks (and stray control characters), 0 for the second
+    /// column of a wide character
+    pub fn to_codepoint_matrix(&self) -> Vec<Vec<u32>> {
+        (0..self.rows.len())
+            .filter_map(|row| self.visible_row(row))
+            .map(|row| {
+                row.cells
+                    .iter()
+                    .map(|cell| match cell.c {
+                        _ if cell.flags.contains(CellFlags::WIDE_SPACER) => 0,
+                        c if c.is_control() => 0x20,
+                        c => c as u32,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Scroll the view `delta` lines back into history (negative: towards the
+    /// live screen), clamped to the available scrollback
+    pub fn scroll_view(&mut self, delta: isize) {
+        let offset = self.view_offset.saturating_add_signed(delta);
+        let offset = offset.min(self.scrollback.len());
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Row with absolute line number `line`, whether in scrollback or on
+    /// screen; `None` once it has been dropped from scrollback
+    pub fn absolute_line(&self, line: usize) -> Option<&Row> {
+        let idx = line.checked_sub(self.first_line)?;
+        match idx.checked_sub(self.scrollback.len()) {
+            Some(row) => self.rows.get(row),
+            None => self.scrollback.get(idx).map(Arc::as_ref),
+        }
+    }
+
+    /// Absolute line number of the oldest line still kept
+    pub fn oldest_line(&self) -> usize {
+        self.first_line
+    }
+
+    /// Absolute line number of the bottom screen row
+    pub fn newest_line(&self) -> usize {
+        (self.first_line + self.scrollback.len() + self.rows.len()).saturating_sub(1)
+    }
+
+    /// Absolute line number of screen row `row`
+    pub fn screen_line(&self, row: usize) -> usize {
+        self.first_line + self.scrollback.len() + row
+    }
+
+    /// Absolute line number of visible row `row`, following the scrollback
+    /// view (the same as `screen_line` when the view is live)
+    pub fn visible_to_absolute(&self, row: usize) -> usize {
+        self.screen_line(row) - self.view_offset
+    }
+
+    /// Visible row showing absolute line `line`, or `None` when it is
+    /// scrolled out of the view or dropped
+    pub fn absolute_to_visible(&self, line: usize) -> Option<usize> {
+        line.checked_sub(self.visible_to_absolute(0))
+            .filter(|&row| row < self.rows.len())
+    }
+
     /// Get a cell at the specified position
     pub fn get_cell(&self, row: usize, col: usize) -> Option<&Cell> {
         self.rows.get(row).and_then(|r| r.cells.get(col))
@@ -185,8 +655,10 @@ impl Grid {
 
     /// Get a mutable cell at the specified position
     pub fn get_cell_mut(&mut self, row: usize, col: usize) -> Option<&mut Cell> {
-        if let Some(r) = self.rows.get_mut(row) {
-            r.dirty = true;
+        if let Some(r) = self.row_mut(row) {
+            if col < r.cells.len() {
+                r.mark_dirty(col, col);
+            }
             r.cells.get_mut(col)
         } else {
             None
@@ -195,103 +667,773 @@ impl Grid {
 
     /// Scroll up by one line (move top line to scrollback)
     pub fn scroll_up(&mut self) {
-        if let Some(row) = self.rows.first() {
-            // Add to scrollback
-            self.scrollback.push(row.clone());
+        self.scroll_region_up(0, self.rows.len().saturating_sub(1));
+    }
 
-            // Limit scrollback size
-            if self.scrollback.len() > self.max_scrollback {
-                self.scrollback.remove(0);
-            }
+    /// Scroll rows `top..=bottom` up by one line, blanking the bottom row.
+    /// The top line goes to scrollback only when the region starts at the
+    /// top of the screen.
+    pub fn scroll_region_up(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows.len().saturating_sub(1));
+        if top > bottom || self.rows.is_empty() {
+            return;
         }
 
-        // Shift all rows up
-        self.rows.remove(0);
-        self.rows.push(Row::new(self.cols));
+        self.lines_scrolled += 1;
+        self.generation = next_row_version();
+        let row = self.rows.remove(top);
+        if top == 0 {
+            self.push_scrollback(row);
+        }
+
+        // Every row in the region now shows different content
+        self.rows
+            .insert(bottom, Row::filled_with(self.cols, &self.template));
+        for row in &mut self.rows[top..=bottom] {
+            row.mark_all_dirty();
+        }
+    }
+
+    /// Move a row that left the top of the screen into scrollback; a
+    /// scrolled-back view stays on its lines
+    fn push_scrollback(&mut self, row: Row) {
+        let row = self.scrollback_row(row);
+        self.scrollback.push_back(row);
+        if self.view_offset > 0 {
+            self.view_offset += 1;
+        }
+        self.trim_scrollback();
+    }
+
+    /// Wrap a row for scrollback, sharing an identical newest or blank row
+    /// when `compress_scrollback` is set
+    fn scrollback_row(&mut self, row: Row) -> Arc<Row> {
+        if !self.compress_scrollback {
+            return Arc::new(row);
+        }
+        let same = |other: &Row| other.wrapped == row.wrapped && other.cells == row.cells;
+        if let Some(newest) = self.scrollback.back()
+            && same(newest)
+        {
+            return Arc::clone(newest);
+        }
+        if row.wrapped || row.cells.iter().any(|cell| *cell != self.template) {
+            return Arc::new(row);
+        }
+        match &self.shared_blank {
+            Some(blank) if same(blank) => Arc::clone(blank),
+            _ => Arc::clone(self.shared_blank.insert(Arc::new(row))),
+        }
+    }
+
+    /// Share identical and blank scrollback rows again after they were
+    /// stored separately (deserializing), when `compress_scrollback` is set
+    fn reshare_scrollback(&mut self) {
+        if !self.compress_scrollback {
+            return;
+        }
+        for row in std::mem::take(&mut self.scrollback) {
+            let row = self.scrollback_row(Arc::unwrap_or_clone(row));
+            self.scrollback.push_back(row);
+        }
+    }
+
+    /// Approximate heap memory held by scrollback, in bytes; shared rows
+    /// count once
+    pub fn scrollback_memory(&self) -> usize {
+        let mut seen = HashSet::new();
+        let rows: usize = self
+            .scrollback
+            .iter()
+            .filter(|row| seen.insert(Arc::as_ptr(row)))
+            .map(|row| {
+                std::mem::size_of::<Row>() + row.cells.capacity() * std::mem::size_of::<Cell>()
+            })
+            .sum();
+        rows + self.scrollback.capacity() * std::mem::size_of::<Arc<Row>>()
+    }
+
+    /// Drop the oldest scrollback rows beyond `max_scrollback` (all of them
+    /// when it is 0), even if the limit was lowered since they were pushed
+    fn trim_scrollback(&mut self) {
+        if self.scrollback.len() > self.max_scrollback {
+            let excess = self.scrollback.len() - self.max_scrollback;
+            self.scrollback.drain(..excess);
+            self.first_line += excess;
+        }
+        self.view_offset = self.view_offset.min(self.scrollback.len());
+    }
+
+    /// Scroll rows `top..=bottom` down by one line, blanking the top row.
+    /// The bottom line is discarded.
+    pub fn scroll_region_down(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows.len().saturating_sub(1));
+        if top > bottom || self.rows.is_empty() {
+            return;
+        }
+
+        self.generation = next_row_version();
+        self.rows.remove(bottom);
+        self.rows
+            .insert(top, Row::filled_with(self.cols, &self.template));
+        for row in &mut self.rows[top..=bottom] {
+            row.mark_all_dirty();
+        }
+    }
+
+    /// Shift the band of rows `top..=bottom`, columns `left..=right` left by
+    /// `count` columns, filling the right edge with `blank`. Cells outside
+    /// the band don't move.
+    pub fn scroll_region_left(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        left: usize,
+        right: usize,
+        count: usize,
+        blank: &Cell,
+    ) {
+        let bottom = bottom.min(self.rows.len().saturating_sub(1));
+        if top > bottom || self.rows.is_empty() {
+            return;
+        }
+        self.generation = next_row_version();
+        for row in top..=bottom {
+            self.delete_cells(row, left, right + 1, count, blank);
+        }
+    }
+
+    /// Shift the band of rows `top..=bottom`, columns `left..=right` right
+    /// by `count` columns, filling the left edge with `blank`. Cells outside
+    /// the band don't move.
+    pub fn scroll_region_right(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        left: usize,
+        right: usize,
+        count: usize,
+        blank: &Cell,
+    ) {
+        let bottom = bottom.min(self.rows.len().saturating_sub(1));
+        if top > bottom || self.rows.is_empty() {
+            return;
+        }
+        self.generation = next_row_version();
+        for row in top..=bottom {
+            self.insert_cells(row, left, right + 1, count, blank);
+        }
     }
 
     /// Scroll down by one line
     pub fn scroll_down(&mut self) {
-        if let Some(row) = self.scrollback.pop() {
-            self.rows.insert(0, row);
+        if let Some(row) = self.scrollback.pop_back() {
+            self.rows.insert(0, Arc::unwrap_or_clone(row));
             self.rows.pop();
+            self.mark_all_dirty();
+        }
+    }
+
+    /// Drop all scrollback, leaving the screen as it is. The view returns to
+    /// the live screen; absolute line numbers of screen rows are unchanged.
+    pub fn clear_scrollback(&mut self) {
+        self.first_line += self.scrollback.len();
+        self.scrollback.clear();
+        self.shared_blank = None;
+        if self.view_offset > 0 {
+            self.view_offset = 0;
+            self.mark_all_dirty();
         }
     }
 
     /// Clear the entire grid
     pub fn clear(&mut self) {
-        for row in &mut self.rows {
-            row.clear();
+        for row in 0..self.rows.len() {
+            self.blank_row(row);
         }
     }
 
-    /// Clear from cursor to end of screen
-    pub fn clear_to_end(&mut self, start_row: usize, start_col: usize) {
-        // Clear from cursor to end of current row
-        if let Some(row) = self.rows.get_mut(start_row) {
-            for col in start_col..self.cols {
-                if let Some(cell) = row.cells.get_mut(col) {
-                    cell.reset();
-                }
+    /// Reset `start_col..end_col` of a row to the blank template
+    pub fn clear_cells(&mut self, row: usize, start_col: usize, end_col: usize) {
+        let blank = self.template;
+        self.fill_cells(row, start_col, end_col, &blank);
+    }
+
+    /// Set `start_col..end_col` of a row to copies of `blank`
+    pub fn fill_cells(&mut self, row: usize, start_col: usize, end_col: usize, blank: &Cell) {
+        if let Some(row) = self.row_mut(row) {
+            let end_col = end_col.min(row.cells.len());
+            if start_col < end_col {
+                row.cells[start_col..end_col].fill(*blank);
+                row.mark_dirty(start_col, end_col - 1);
             }
-            row.dirty = true;
         }
+    }
+
+    /// Shift cells from `col` right by `count` up to (not including)
+    /// `end_col`, filling the gap with `blank`; cells pushed past `end_col`
+    /// are lost
+    pub fn insert_cells(
+        &mut self,
+        row: usize,
+        col: usize,
+        end_col: usize,
+        count: usize,
+        blank: &Cell,
+    ) {
+        if let Some(row) = self.row_mut(row) {
+            let end_col = end_col.min(row.cells.len());
+            if col >= end_col {
+                return;
+            }
+            let count = count.min(end_col - col);
+            row.cells[col..end_col].rotate_right(count);
+            row.cells[col..col + count].fill(*blank);
+            row.mark_dirty(col, end_col - 1);
+        }
+    }
+
+    /// Remove `count` cells at `col`, shifting the rest of `col..end_col`
+    /// left and filling the vacated cells before `end_col` with `blank`
+    pub fn delete_cells(
+        &mut self,
+        row: usize,
+        col: usize,
+        end_col: usize,
+        count: usize,
+        blank: &Cell,
+    ) {
+        if let Some(row) = self.row_mut(row) {
+            let end_col = end_col.min(row.cells.len());
+            if col >= end_col {
+                return;
+            }
+            let count = count.min(end_col - col);
+            row.cells[col..end_col].rotate_left(count);
+            row.cells[end_col - count..end_col].fill(*blank);
+            row.mark_dirty(col, end_col - 1);
+        }
+    }
+
+    /// Reset a whole row in place to the blank template, marking it dirty
+    /// across its full width and clearing its soft-wrap flag
+    pub fn blank_row(&mut self, row: usize) {
+        let blank = self.template;
+        self.fill_row(row, &blank);
+    }
+
+    /// Clear a whole row to the blank template
+    #[deprecated(note = "use `blank_row`")]
+    pub fn clear_row(&mut self, row: usize) {
+        self.blank_row(row);
+    }
+
+    /// Like `blank_row`, but with copies of `blank` (e.g. erasing with BCE)
+    pub fn fill_row(&mut self, row: usize, blank: &Cell) {
+        if let Some(row) = self.row_mut(row) {
+            row.clear(blank);
+        }
+    }
+
+    /// Clear from cursor to end of screen, filling with `blank`
+    pub fn clear_to_end(&mut self, start_row: usize, start_col: usize, blank: &Cell) {
+        // Clear from cursor to end of current row
+        self.fill_cells(start_row, start_col, self.cols, blank);
 
         // Clear all rows below
         for row_idx in (start_row + 1)..self.rows.len() {
-            self.rows[row_idx].clear();
+            self.fill_row(row_idx, blank);
         }
     }
 
-    /// Clear from beginning of screen to cursor
-    pub fn clear_from_start(&mut self, end_row: usize, end_col: usize) {
+    /// Clear from beginning of screen to cursor, filling with `blank`
+    pub fn clear_from_start(&mut self, end_row: usize, end_col: usize, blank: &Cell) {
         // Clear all rows before
         for row_idx in 0..end_row {
-            self.rows[row_idx].clear();
+            self.fill_row(row_idx, blank);
         }
 
         // Clear from start of current row to cursor
-        if let Some(row) = self.rows.get_mut(end_row) {
-            for col in 0..=end_col.min(self.cols - 1) {
-                if let Some(cell) = row.cells.get_mut(col) {
-                    cell.reset();
+        self.fill_cells(end_row, 0, end_col + 1, blank);
+    }
+
+    /// Selectively erase `start_col..end_col` of a row, leaving protected cells untouched
+    pub fn selective_erase(&mut self, row: usize, start_col: usize, end_col: usize) {
+        let blank = self.template;
+        if let Some(row) = self.row_mut(row) {
+            let end_col = end_col.min(row.cells.len());
+            for cell in row.cells.iter_mut().take(end_col).skip(start_col) {
+                if !cell.flags.contains(CellFlags::PROTECTED) {
+                    *cell = blank;
                 }
             }
-            row.dirty = true;
+            if start_col < end_col {
+                row.mark_dirty(start_col, end_col - 1);
+            }
         }
     }
 
-    /// Resize the grid
-    pub fn resize(&mut self, new_rows: usize, new_cols: usize) {
-        // Resize columns first
+    /// Resize the grid, keeping the content around the cursor anchored, and
+    /// return the cursor's new `(row, col)`. A width change reflows soft-wrapped
+    /// lines (scrollback included). Growing pulls history back from scrollback
+    /// into the top; shrinking first drops blank rows below the cursor, then
+    /// pushes top rows into scrollback. A scrolled-back view keeps the same
+    /// line at its top.
+    pub fn resize(
+        &mut self,
+        new_rows: usize,
+        new_cols: usize,
+        cursor: (usize, usize),
+    ) -> (usize, usize) {
+        self.resize_tracking(new_rows, new_cols, cursor, &mut [])
+    }
+
+    /// `resize`, also moving the absolute `(line, col)` positions in `points`
+    /// (e.g. prompt marks) to where reflow puts their cells
+    pub fn resize_tracking(
+        &mut self,
+        new_rows: usize,
+        new_cols: usize,
+        cursor: (usize, usize),
+        points: &mut [(usize, usize)],
+    ) -> (usize, usize) {
+        let (mut cursor_row, mut cursor_col) = cursor;
+
         if new_cols != self.cols {
-            for row in &mut self.rows {
-                row.resize(new_cols);
+            // Reflow works in row indices, scrollback first
+            let first_line = self.first_line;
+            let lines = first_line..first_line + self.scrollback.len() + self.rows.len();
+            let inside: Vec<&mut (usize, usize)> = points
+                .iter_mut()
+                .filter(|(line, _)| lines.contains(line))
+                .collect();
+            let mut tracked: Vec<(usize, usize)> = inside
+                .iter()
+                .map(|&&mut (line, col)| (line - first_line, col))
+                .collect();
+            (cursor_row, cursor_col) =
+                self.reflow(new_cols, (cursor_row, cursor_col), &mut tracked);
+            for (point, (row, col)) in inside.into_iter().zip(tracked) {
+                *point = (first_line + row, col);
             }
-            self.cols = new_cols;
         }
 
         // Resize rows
         if new_rows > self.rows.len() {
-            // Add rows
+            // Bring history back first, then pad the bottom
+            while self.rows.len() < new_rows {
+                match self.scrollback.pop_back() {
+                    Some(row) => {
+                        self.rows.insert(0, Arc::unwrap_or_clone(row));
+                        cursor_row += 1;
+                        self.view_offset = self.view_offset.saturating_sub(1);
+                    }
+                    None => break,
+                }
+            }
             while self.rows.len() < new_rows {
-                self.rows.push(Row::new(self.cols));
+                self.rows.push(Row::filled_with(self.cols, &self.template));
             }
         } else if new_rows < self.rows.len() {
-            // Remove rows (move to scrollback)
             while self.rows.len() > new_rows {
-                if let Some(row) = self.rows.first() {
-                    self.scrollback.push(row.clone());
+                let last = self.rows.len() - 1;
+                if last > cursor_row && self.rows[last].text().trim_end().is_empty() {
+                    self.rows.pop();
+                } else {
+                    let row = self.rows.remove(0);
+                    self.push_scrollback(row);
+                    cursor_row = cursor_row.saturating_sub(1);
+                }
+            }
+        }
+
+        self.trim_scrollback();
+
+        self.mark_all_dirty();
+        (
+            cursor_row.min(new_rows.saturating_sub(1)),
+            cursor_col.min(new_cols.saturating_sub(1)),
+        )
+    }
+
+    /// Replace the screen with `rows`, all of one width, fitting them to the
+    /// current size as `resize` would (reflowing soft-wrapped lines), and
+    /// return the cursor's new `(row, col)`. Rows that don't fit go to
+    /// scrollback; the view returns to the live screen.
+    pub fn load_rows(&mut self, rows: Vec<Row>, cursor: (usize, usize)) -> (usize, usize) {
+        let width = rows.first().map_or(self.cols, |row| row.cells.len());
+        let mut loaded = Grid::new(0, width, self.max_scrollback);
+        loaded.template = self.template;
+        loaded.rows = rows;
+        let cursor = loaded.resize(self.rows.len(), self.cols, cursor);
+
+        self.view_offset = 0;
+        for row in loaded.scrollback {
+            self.push_scrollback(Arc::unwrap_or_clone(row));
+        }
+        self.rows = loaded.rows;
+        self.mark_all_dirty();
+        cursor
+    }
+
+    /// Lay a logical line's cells out in rows of `cols`. A wide character
+    /// that would straddle the edge moves to the next row whole, leaving a
+    /// `WRAP_PAD` blank in the last column. Returns the rows (at least one)
+    /// and the cell offset each row starts at.
+    fn wrap_cells(&self, cells: &[Cell], cols: usize) -> (Vec<Row>, Vec<usize>) {
+        let mut rows = Vec::new();
+        let mut starts = Vec::new();
+        let mut start = 0;
+        loop {
+            let mut end = (start + cols).min(cells.len());
+            if end < cells.len()
+                && end - start > 1
+                && cells[end - 1].flags.contains(CellFlags::WIDE)
+            {
+                end -= 1;
+            }
+            let mut row = Row::filled_with(cols, &self.template);
+            row.cells[..end - start].copy_from_slice(&cells[start..end]);
+            if end - start < cols && end < cells.len() {
+                row.cells[cols - 1].flags.set(CellFlags::WRAP_PAD, true);
+            }
+            row.wrapped = !rows.is_empty();
+            rows.push(row);
+            starts.push(start);
+            if end >= cells.len() {
+                return (rows, starts);
+            }
+            start = end;
+        }
+    }
+
+    /// Rewrap every logical line (rows joined by soft wraps) to `new_cols`,
+    /// keeping the screen height. Returns the cursor's new position and
+    /// moves each of `points` (row index, scrollback first, and column) to
+    /// its cell's new row and column; the view offset follows the line at
+    /// the top of the view.
+    fn reflow(
+        &mut self,
+        new_cols: usize,
+        cursor: (usize, usize),
+        points: &mut [(usize, usize)],
+    ) -> (usize, usize) {
+        let height = self.rows.len();
+        let cursor_abs = self.scrollback.len() + cursor.0;
+        let view_top = (self.view_offset > 0).then(|| self.scrollback.len() - self.view_offset);
+
+        let mut old: Vec<Row> = std::mem::take(&mut self.scrollback)
+            .into_iter()
+            .map(Arc::unwrap_or_clone)
+            .collect();
+        old.append(&mut self.rows);
+
+        // Join rows into logical lines: (cells, minimum length). The cursor's
+        // line keeps at least enough cells to hold the cursor.
+        let mut lines: Vec<(Vec<Cell>, usize)> = Vec::new();
+        let mut cursor_pos = (0, 0);
+        let mut view_pos = None;
+        // (line, offset) where each old row starts
+        let mut row_pos = Vec::new();
+        for (abs, row) in old.into_iter().enumerate() {
+            if !row.wrapped || lines.is_empty() {
+                lines.push((Vec::new(), 0));
+            }
+            let idx = lines.len() - 1;
+            let line = &mut lines[idx];
+            let offset = line.0.len();
+            if abs == cursor_abs {
+                cursor_pos = (idx, offset + cursor.1);
+                line.1 = offset + cursor.1 + 1;
+            }
+            if view_top == Some(abs) {
+                view_pos = Some((idx, offset));
+            }
+            row_pos.push((idx, offset));
+            line.0.extend(
+                row.cells
+                    .into_iter()
+                    .filter(|cell| !cell.flags.contains(CellFlags::WRAP_PAD)),
+            );
+        }
+
+        // (line, offset) of each point's cell, visited in line order
+        let targets: Vec<(usize, usize)> = points
+            .iter()
+            .map(|&(row, col)| (row_pos[row].0, row_pos[row].1 + col))
+            .collect();
+        let mut order: Vec<usize> = (0..targets.len()).collect();
+        order.sort_by_key(|&i| targets[i].0);
+        let mut next = 0;
+
+        let mut rows = Vec::new();
+        let mut new_cursor = (0, 0);
+        let mut new_view_top = None;
+        for (idx, (mut cells, min_len)) in lines.into_iter().enumerate() {
+            let len = cells
+                .iter()
+                .rposition(|cell| *cell != self.template)
+                .map_or(0, |i| i + 1)
+                .max(min_len);
+            cells.resize(len, self.template);
+
+            let start = rows.len();
+            let (line_rows, starts) = self.wrap_cells(&cells, new_cols);
+            // Row and column of a cell offset within the line
+            let locate = |offset: usize| {
+                let row = starts.partition_point(|&s| s <= offset) - 1;
+                (start + row, offset - starts[row])
+            };
+            if idx == cursor_pos.0 {
+                new_cursor = locate(cursor_pos.1);
+            }
+            if let Some((line, offset)) = view_pos
+                && line == idx
+            {
+                new_view_top = Some(locate(offset).0);
+            }
+            while let Some(&i) = order.get(next)
+                && targets[i].0 == idx
+            {
+                let (row, col) = locate(targets[i].1);
+                points[i] = (row, col.min(new_cols.saturating_sub(1)));
+                next += 1;
+            }
+            rows.extend(line_rows);
+        }
+
+        // Blank rows below the cursor aren't content; padding replaces them
+        while rows.len() > new_cursor.0 + 1
+            && rows
+                .last()
+                .is_some_and(|row| row.text().trim_end().is_empty())
+        {
+            rows.pop();
+        }
+
+        // Keep the bottom of the content on screen, but never the cursor above it
+        let screen_start = rows.len().saturating_sub(height).min(new_cursor.0);
+        let mut screen = rows.split_off(screen_start);
+        screen.truncate(height);
+        while screen.len() < height {
+            screen.push(Row::filled_with(new_cols, &self.template));
+        }
+
+        for row in rows {
+            let row = self.scrollback_row(row);
+            self.scrollback.push_back(row);
+        }
+        self.rows = screen;
+        self.cols = new_cols;
+        self.view_offset = new_view_top.map_or(0, |top| screen_start.saturating_sub(top));
+        (new_cursor.0 - screen_start, new_cursor.1)
+    }
+
+    /// Serialize the visible rows as text plus SGR sequences. Feeding the
+    /// result to a fresh terminal of the same size reproduces the screen,
+    /// including soft wraps; the stream ends with a reset.
+    pub fn to_ansi(&self) -> String {
+        let blank = self.template;
+        let defaults = (blank.fg, blank.bg);
+        let mut out = String::new();
+        let mut style = (CellFlags::new(), blank.fg, blank.bg);
+
+        for (idx, row) in self.rows.iter().enumerate() {
+            let continues = self.rows.get(idx + 1).is_some_and(|next| next.wrapped);
+            // Trailing default blanks are implied, except before a soft wrap
+            let len = if continues {
+                row.cells.len()
+            } else {
+                row.cells
+                    .iter()
+                    .rposition(|cell| *cell != blank)
+                    .map_or(0, |i| i + 1)
+            };
+
+            for cell in &row.cells[..len] {
+                if cell.flags.contains(CellFlags::WIDE_SPACER) {
+                    continue;
                 }
-                self.rows.remove(0);
+                if ansi_style(cell) != style {
+                    style = ansi_style(cell);
+                    out.push_str(&format!(
+                        "\x1b[{}m",
+                        sgr_params(style.0, style.1, style.2, defaults)
+                    ));
+                }
+                out.push(cell.c);
+            }
+            if !continues && idx + 1 < self.rows.len() {
+                out.push_str("\r\n");
             }
         }
+        out.push_str("\x1b[0m");
+        out
+    }
+
+    /// Escape sequences that turn the screen of `self` into that of `other`,
+    /// e.g. to mirror a terminal over a socket: each run of differing cells
+    /// is rewritten behind a cursor position, widened to whole wide
+    /// characters. Both grids should be the same size; soft-wrap flags
+    /// aren't carried. As with `to_ansi`, the receiver is assumed to be in
+    /// the default modes, and a non-empty diff ends with a reset.
+    pub fn diff_to_ansi(&self, other: &Grid) -> String {
+        let defaults = (other.template.fg, other.template.bg);
+        let spacer = |cells: &[Cell], col: usize| {
+            cells
+                .get(col)
+                .is_some_and(|cell| cell.flags.contains(CellFlags::WIDE_SPACER))
+        };
+        let mut out = String::new();
+        let mut style = None;
+
+        for (idx, row) in other.rows.iter().enumerate() {
+            let new = row.cells.as_slice();
+            let old = self
+                .rows
+                .get(idx)
+                .map_or(&[][..], |row| row.cells.as_slice());
+            let changed = |col: usize| old.get(col) != Some(&new[col]);
+
+            let mut col = 0;
+            while col < new.len() {
+                if !changed(col) {
+                    col += 1;
+                    continue;
+                }
+                let mut start = col;
+                if start > 0 && (spacer(old, start) || spacer(new, start)) {
+                    start -= 1;
+                }
+                let mut end = col + 1;
+                while end < new.len() && (changed(end) || spacer(old, end) || spacer(new, end)) {
+                    end += 1;
+                }
+
+                out.push_str(&format!("\x1b[{};{}H", idx + 1, start + 1));
+                for cell in &new[start..end] {
+                    if cell.flags.contains(CellFlags::WIDE_SPACER) {
+                        continue;
+                    }
+                    if style != Some(ansi_style(cell)) {
+                        let (flags, fg, bg) = ansi_style(cell);
+                        style = Some((flags, fg, bg));
+                        out.push_str(&format!("\x1b[{}m", sgr_params(flags, fg, bg, defaults)));
+                    }
+                    out.push(cell.c);
+                }
+                col = end;
+            }
+        }
+        if !out.is_empty() {
+            out.push_str("\x1b[0m");
+        }
+        out
+    }
+
+    /// Find every occurrence of `needle` in scrollback and on screen, oldest first.
+    /// Soft-wrapped rows are joined so matches can span the wrap point.
+    pub fn search(&self, needle: &str, case_insensitive: bool) -> Vec<Match> {
+        let fold = |c: char| {
+            if case_insensitive {
+                c.to_lowercase().next().unwrap_or(c)
+            } else {
+                c
+            }
+        };
+        let needle: Vec<char> = needle.chars().map(fold).collect();
+        let mut matches = Vec::new();
+        if needle.is_empty() {
+            return matches;
+        }
+
+        let rows: Vec<&Row> = self
+            .scrollback
+            .iter()
+            .map(Arc::as_ref)
+            .chain(self.rows.iter())
+            .collect();
+        let mut start = 0;
+        while start < rows.len() {
+            // Gather the rows making up one logical line
+            let mut end = start + 1;
+            while end < rows.len() && rows[end].wrapped {
+                end += 1;
+            }
+
+            let mut text = Vec::new();
+            let mut positions = Vec::new();
+            for (offset, row) in rows[start..end].iter().enumerate() {
+                for (col, cell) in row.cells.iter().enumerate() {
+                    if cell.flags.contains(CellFlags::WIDE_SPACER) {
+                        continue;
+                    }
+                    text.push(fold(cell.c));
+                    positions.push((self.first_line + start + offset, col));
+                }
+            }
+
+            let mut i = 0;
+            while i + needle.len() <= text.len() {
+                if text[i..i + needle.len()] == needle[..] {
+                    let (start_row, start_col) = positions[i];
+                    let (end_row, end_col) = positions[i + needle.len() - 1];
+                    matches.push(Match {
+                        start_row,
+                        start_col,
+                        end_row,
+                        end_col,
+                    });
+                    i += needle.len();
+                } else {
+                    i += 1;
+                }
+            }
+
+            start = end;
+        }
+
+        matches
     }
 
     /// Mark all cells as clean (not dirty)
     pub fn mark_clean(&mut self) {
         for row in &mut self.rows {
             row.dirty = false;
+            row.dirty_span = None;
+        }
+    }
+
+    /// Dirty rows with their current version, for hosts that render
+    /// asynchronously and acknowledge with `mark_clean_up_to`
+    pub fn dirty_versions(&self) -> Vec<(usize, u64)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.dirty)
+            .map(|(idx, row)| (idx, row.version))
+            .collect()
+    }
+
+    /// Mark rows clean only if they haven't changed since the host observed
+    /// `(row, version)`; rows modified in between stay dirty
+    pub fn mark_clean_up_to(&mut self, observed: &[(usize, u64)]) {
+        for &(idx, version) in observed {
+            if let Some(row) = self.rows.get_mut(idx)
+                && row.version == version
+            {
+                row.dirty = false;
+                row.dirty_span = None;
+            }
+        }
+    }
+
+    /// Mark every visible row as dirty (e.g. after a screen-wide display change)
+    pub fn mark_all_dirty(&mut self) {
+        self.generation = next_row_version();
+        self.repaint_generation = self.generation;
+        for row in &mut self.rows {
+            row.mark_all_dirty();
         }
     }
 
@@ -303,6 +1445,33 @@ impl Grid {
             .filter_map(|(idx, row)| if row.dirty { Some(idx) } else { None })
             .collect()
     }
+
+    /// Get dirty column spans as `(row, start_col, end_col)`, end inclusive
+    pub fn dirty_spans(&self) -> Vec<(usize, usize, usize)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, row)| row.dirty_span.map(|(start, end)| (idx, start, end)))
+            .collect()
+    }
+
+    /// Cells inside the dirty spans as `(row, col, cell)`, in row-major
+    /// order. Tracking is per span, so cells between two changes on the
+    /// same row are included too.
+    pub fn dirty_cells(&self) -> impl Iterator<Item = (usize, usize, &Cell)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, row)| row.dirty_span.map(|span| (idx, row, span)))
+            .flat_map(|(idx, row, (start, end))| {
+                row.cells
+                    .iter()
+                    .enumerate()
+                    .take(end + 1)
+                    .skip(start)
+                    .map(move |(col, cell)| (idx, col, cell))
+            })
+    }
 }
 
 #[cfg(test)]
@@ -337,4 +1506,448 @@ mod tests {
         assert_eq!(grid.scrollback.len(), 1);
         assert_eq!(grid.scrollback[0].cells[0].c, 'X');
     }
+
+    #[test]
+    fn test_codepoint_matrix() {
+        let mut grid = Grid::new(2, 4, 0);
+        write_row(&mut grid, 0, "ab");
+        let wide = grid.get_cell_mut(1, 1).unwrap();
+        wide.c = '字';
+        wide.flags = CellFlags(CellFlags::WIDE);
+        grid.get_cell_mut(1, 2).unwrap().flags = CellFlags(CellFlags::WIDE_SPACER);
+
+        assert_eq!(
+            grid.to_codepoint_matrix(),
+            vec![
+                vec![0x61, 0x62, 0x20, 0x20],
+                vec![0x20, '字' as u32, 0, 0x20]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clear_resets_cells_and_marks_dirty() {
+        let mut grid = Grid::new(3, 6, 0);
+        write_row(&mut grid, 1, "abc");
+        let cell = grid.get_cell_mut(2, 5).unwrap();
+        cell.bg = Color::Spec256(2);
+        cell.flags = CellFlags(CellFlags::BOLD);
+        grid.rows[1].wrapped = true;
+        grid.mark_clean();
+
+        grid.clear();
+        for row in &grid.rows {
+            assert!(row.cells.iter().all(|cell| *cell == Cell::default()));
+            assert_eq!(row.dirty_span, Some((0, 5)));
+            assert!(!row.wrapped);
+        }
+    }
+
+    #[test]
+    fn test_dirty_cells() {
+        let mut grid = Grid::new(3, 80, 0);
+        grid.mark_clean();
+        assert_eq!(grid.dirty_cells().count(), 0);
+
+        grid.get_cell_mut(2, 3).unwrap().c = 'y';
+        grid.get_cell_mut(0, 40).unwrap().c = 'x';
+        let cells: Vec<_> = grid
+            .dirty_cells()
+            .map(|(row, col, cell)| (row, col, cell.c))
+            .collect();
+        assert_eq!(cells, vec![(0, 40, 'x'), (2, 3, 'y')]);
+    }
+
+    #[test]
+    fn test_dirty_span_single_cell() {
+        let mut grid = Grid::new(3, 80, 0);
+        grid.mark_clean();
+        assert!(grid.dirty_spans().is_empty());
+
+        grid.get_cell_mut(1, 70).unwrap().c = 'x';
+        assert_eq!(grid.dirty_spans(), vec![(1, 70, 70)]);
+
+        grid.get_cell_mut(1, 72).unwrap().c = 'y';
+        grid.clear_cells(2, 5, 10);
+        assert_eq!(grid.dirty_spans(), vec![(1, 70, 72), (2, 5, 9)]);
+
+        grid.mark_clean();
+        grid.blank_row(0);
+        assert_eq!(grid.dirty_spans(), vec![(0, 0, 79)]);
+    }
+
+    #[test]
+    fn test_mark_clean_up_to_keeps_later_writes() {
+        let mut grid = Grid::new(3, 10, 100);
+        grid.mark_clean();
+        grid.get_cell_mut(0, 1).unwrap().c = 'a';
+        grid.get_cell_mut(2, 1).unwrap().c = 'b';
+
+        // The host fetches versions, then row 2 changes before it acks
+        let observed = grid.dirty_versions();
+        assert_eq!(
+            observed.iter().map(|&(row, _)| row).collect::<Vec<_>>(),
+            [0, 2]
+        );
+        grid.get_cell_mut(2, 2).unwrap().c = 'c';
+        grid.mark_clean_up_to(&observed);
+        assert_eq!(grid.dirty_rows(), vec![2]);
+
+        // A scroll moves a different row under an observed index
+        let observed = grid.dirty_versions();
+        grid.scroll_up();
+        grid.mark_clean_up_to(&observed);
+        assert_eq!(grid.dirty_rows(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_generation_follows_screen_changes() {
+        let mut grid = Grid::new(3, 10, 100);
+        let changed = |grid: &mut Grid, edit: &dyn Fn(&mut Grid)| {
+            let before = grid.generation;
+            edit(grid);
+            grid.generation != before
+        };
+        assert!(changed(&mut grid, &|g| g.get_cell_mut(1, 1).unwrap().c = 'a'));
+        assert!(changed(&mut grid, &|g| g.scroll_up()));
+        assert!(changed(&mut grid, &|g| g.scroll_region_down(0, 1)));
+        assert!(changed(&mut grid, &|g| g.clear_cells(0, 0, 5)));
+        let blank = Cell::default();
+        assert!(changed(&mut grid, &|g| g.insert_cells(0, 2, 10, 1, &blank)));
+        assert!(changed(&mut grid, &|g| g.delete_cells(0, 2, 10, 1, &blank)));
+        assert!(!changed(&mut grid, &|g| g.mark_clean()));
+        assert!(!changed(&mut grid, &|g| g.clear_cells(7, 0, 5)));
+    }
+
+    #[test]
+    fn test_color_spec_parsing() {
+        assert_eq!(Rgb::from_spec("#ff8000"), Some(Rgb::new(255, 128, 0)));
+        assert_eq!(Rgb::from_spec("#f80"), Some(Rgb::new(255, 136, 0)));
+        assert_eq!(
+            Rgb::from_spec("rgb:ffff/0000/8080"),
+            Some(Rgb::new(255, 0, 128))
+        );
+        assert_eq!(Rgb::from_spec("rgb:f/0/8"), Some(Rgb::new(255, 0, 136)));
+        assert_eq!(Rgb::from_spec("rgb:ff/00"), None);
+        assert_eq!(Rgb::from_spec("red"), None);
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut grid = Grid::new(4, 2, 100);
+        for (row, c) in "abcd".chars().enumerate() {
+            grid.get_cell_mut(row, 0).unwrap().c = c;
+        }
+
+        // Inner region: nothing reaches scrollback, rows outside untouched
+        grid.scroll_region_up(1, 2);
+        let column: String = grid.rows.iter().map(|row| row.cells[0].c).collect();
+        assert_eq!(column, "ac d");
+        assert!(grid.scrollback.is_empty());
+
+        grid.scroll_region_down(0, 2);
+        let column: String = grid.rows.iter().map(|row| row.cells[0].c).collect();
+        assert_eq!(column, " acd");
+
+        // A region anchored at the top feeds scrollback
+        grid.scroll_region_up(0, 1);
+        assert_eq!(grid.scrollback.len(), 1);
+        let column: String = grid.rows.iter().map(|row| row.cells[0].c).collect();
+        assert_eq!(column, "a cd");
+    }
+
+    #[test]
+    fn test_selective_erase_skips_protected() {
+        let mut grid = Grid::new(2, 4, 0);
+        for (col, c) in "abcd".chars().enumerate() {
+            let cell = grid.get_cell_mut(0, col).unwrap();
+            cell.c = c;
+            cell.flags.set(CellFlags::PROTECTED, col % 2 == 0);
+        }
+
+        grid.selective_erase(0, 0, 4);
+
+        let text: String = grid.rows[0].cells.iter().map(|cell| cell.c).collect();
+        assert_eq!(text, "a c ");
+    }
+
+    fn write_row(grid: &mut Grid, row: usize, text: &str) {
+        for (col, c) in text.chars().enumerate() {
+            grid.get_cell_mut(row, col).unwrap().c = c;
+        }
+    }
+
+    #[test]
+    fn test_resize_anchors_cursor_row() {
+        let mut grid = Grid::new(3, 4, 100);
+        for (i, text) in ["one", "two", "six"].iter().enumerate() {
+            for (col, c) in text.chars().enumerate() {
+                grid.get_cell_mut(i, col).unwrap().c = c;
+            }
+        }
+        grid.scroll_up();
+        grid.get_cell_mut(2, 0).unwrap().c = '$';
+
+        // Growing pulls "one" back from scrollback; the prompt moves down
+        assert_eq!(grid.resize(5, 4, (2, 1)), (3, 1));
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.rows[0].text(), "one ");
+        assert_eq!(grid.rows[3].text(), "$   ");
+        assert_eq!(grid.rows[4].text(), "    ");
+
+        // Shrinking drops the blank row below the prompt, then pushes history
+        assert_eq!(grid.resize(2, 4, (3, 1)), (1, 1));
+        assert_eq!(grid.scrollback.len(), 2);
+        assert_eq!(grid.rows[0].text(), "six ");
+        assert_eq!(grid.rows[1].text(), "$   ");
+    }
+
+    #[test]
+    fn test_resize_without_scrollback() {
+        let mut grid = Grid::new(4, 4, 0);
+        for (i, text) in ["one", "two", "six", "$"].iter().enumerate() {
+            write_row(&mut grid, i, text);
+        }
+
+        // Rows leaving the top are discarded, not kept in scrollback
+        assert_eq!(grid.resize(2, 4, (3, 1)), (1, 1));
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.first_line, 2);
+        assert_eq!(grid.rows.len(), 2);
+        assert_eq!(grid.rows[0].text(), "six ");
+        assert_eq!(grid.rows[1].text(), "$   ");
+
+        // Growing has no history to pull back, so it pads the bottom
+        assert_eq!(grid.resize(3, 4, (1, 1)), (1, 1));
+        assert_eq!(grid.rows[2].text(), "    ");
+        grid.scroll_up();
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.first_line, 3);
+
+        // Lowering the limit at runtime drops the excess on the next scroll
+        let mut grid = Grid::new(2, 4, 100);
+        for _ in 0..5 {
+            grid.scroll_up();
+        }
+        grid.view_offset = 3;
+        grid.max_scrollback = 0;
+        grid.scroll_up();
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.view_offset, 0);
+        assert_eq!(grid.first_line, 6);
+    }
+
+    #[test]
+    fn test_scroll_region_left_and_right() {
+        let mut grid = Grid::new(3, 8, 0);
+        for row in 0..3 {
+            write_row(&mut grid, row, "abcdefgh");
+        }
+        let blank = Cell::new('.');
+
+        // Rows 0-1, columns 2-5 move left by 2; the rest stays put
+        grid.scroll_region_left(0, 1, 2, 5, 2, &blank);
+        assert_eq!(grid.rows[0].text(), "abef..gh");
+        assert_eq!(grid.rows[1].text(), "abef..gh");
+        assert_eq!(grid.rows[2].text(), "abcdefgh");
+
+        grid.scroll_region_right(1, 2, 2, 5, 1, &blank);
+        assert_eq!(grid.rows[0].text(), "abef..gh");
+        assert_eq!(grid.rows[1].text(), "ab.ef.gh");
+        assert_eq!(grid.rows[2].text(), "ab.cdegh");
+
+        // A count wider than the band blanks it
+        grid.scroll_region_left(2, 2, 2, 5, 9, &blank);
+        assert_eq!(grid.rows[2].text(), "ab....gh");
+    }
+
+    #[test]
+    fn test_reflow_rewraps_lines() {
+        let mut grid = Grid::new(3, 6, 100);
+        write_row(&mut grid, 0, "abcdef");
+        write_row(&mut grid, 1, "gh");
+        grid.rows[1].wrapped = true;
+        write_row(&mut grid, 2, "$");
+
+        // Narrower: "abcdefgh" takes three rows and pushes one into scrollback
+        assert_eq!(grid.resize(3, 3, (2, 1)), (2, 1));
+        assert_eq!(grid.scrollback.len(), 1);
+        assert_eq!(grid.scrollback[0].text(), "abc");
+        assert_eq!(grid.rows[0].text(), "def");
+        assert!(grid.rows[0].wrapped && grid.rows[1].wrapped);
+        assert_eq!(grid.rows[1].text(), "gh ");
+        assert_eq!(grid.rows[2].text(), "$  ");
+
+        // Wider: the line rejoins and history comes back
+        assert_eq!(grid.resize(3, 10, (2, 1)), (1, 1));
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.rows[0].text(), "abcdefgh  ");
+        assert!(!grid.rows[1].wrapped);
+    }
+
+    #[test]
+    fn test_reflow_keeps_text_over_blank_rows() {
+        // The cursor past "hello" wraps onto a second row; the blank rows
+        // below make room for it instead of the text going to scrollback
+        let mut grid = Grid::new(3, 10, 100);
+        write_row(&mut grid, 0, "hello");
+        assert_eq!(grid.resize(3, 5, (0, 5)), (1, 0));
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.rows[0].text(), "hello");
+
+        let mut grid = Grid::new(4, 10, 100);
+        write_row(&mut grid, 0, "$ echo hi");
+        write_row(&mut grid, 1, "hi");
+        write_row(&mut grid, 2, "$ ");
+        assert_eq!(grid.resize(4, 6, (2, 2)), (3, 2));
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.rows[0].text(), "$ echo");
+        assert_eq!(grid.rows[1].text(), " hi   ");
+        assert_eq!(grid.rows[3].text(), "$     ");
+    }
+
+    #[test]
+    fn test_absolute_lines_survive_trimming() {
+        let mut grid = Grid::new(2, 4, 3);
+        for i in 0..4 {
+            write_row(&mut grid, 1, &format!("l{}", i + 1));
+            grid.scroll_up();
+        }
+        // The initial blank row was dropped; l1 keeps absolute line 1
+        assert_eq!((grid.oldest_line(), grid.newest_line()), (1, 5));
+        assert!(grid.absolute_line(0).is_none());
+        assert_eq!(grid.absolute_line(1).unwrap().text(), "l1  ");
+
+        write_row(&mut grid, 1, "l5");
+        grid.scroll_up();
+        write_row(&mut grid, 1, "l6");
+        assert_eq!(grid.oldest_line(), 2);
+        assert!(grid.absolute_line(1).is_none());
+        assert_eq!(grid.absolute_line(3).unwrap().text(), "l3  ");
+        assert_eq!(grid.absolute_line(6).unwrap().text(), "l6  ");
+        assert!(grid.absolute_line(7).is_none());
+
+        let matches = grid.search("l3", false);
+        assert_eq!((matches[0].start_row, matches[0].start_col), (3, 0));
+    }
+
+    #[test]
+    fn test_visible_absolute_conversions() {
+        let mut grid = Grid::new(3, 4, 2);
+        assert_eq!(grid.visible_to_absolute(2), 2);
+        assert_eq!(grid.absolute_to_visible(2), Some(2));
+        assert_eq!(grid.absolute_to_visible(3), None);
+
+        for i in 0..4 {
+            write_row(&mut grid, 2, &format!("l{i}"));
+            grid.scroll_up();
+        }
+        // Two lines were dropped from the 2-line scrollback
+        assert_eq!(grid.oldest_line(), 2);
+        assert_eq!(grid.visible_to_absolute(0), 4);
+        assert_eq!(grid.absolute_to_visible(3), None);
+        assert_eq!(grid.absolute_to_visible(5), Some(1));
+        let line = grid.visible_to_absolute(1);
+        assert_eq!(grid.absolute_line(line).unwrap().text(), "l3  ");
+
+        // Scrolled back, visible rows map to older lines and back
+        grid.scroll_view(2);
+        assert_eq!(grid.visible_to_absolute(0), 2);
+        assert_eq!(grid.absolute_to_visible(2), Some(0));
+        assert_eq!(grid.absolute_to_visible(5), None);
+        for row in 0..3 {
+            let line = grid.visible_to_absolute(row);
+            assert_eq!(grid.absolute_to_visible(line), Some(row));
+            assert_eq!(
+                grid.absolute_line(line).unwrap().text(),
+                grid.visible_row(row).unwrap().text()
+            );
+        }
+    }
+
+    #[test]
+    fn test_clear_scrollback_keeps_screen() {
+        let mut grid = Grid::new(2, 4, 100);
+        for i in 0..5 {
+            write_row(&mut grid, 1, &format!("l{}", i));
+            grid.scroll_up();
+        }
+        write_row(&mut grid, 0, "top");
+        grid.scroll_view(3);
+        let screen: Vec<String> = grid.rows.iter().map(|row| row.text()).collect();
+        let newest = grid.newest_line();
+
+        grid.clear_scrollback();
+        assert!(grid.scrollback.is_empty());
+        assert_eq!(grid.view_offset, 0);
+        assert_eq!(
+            grid.rows.iter().map(|row| row.text()).collect::<Vec<_>>(),
+            screen
+        );
+        assert_eq!(grid.newest_line(), newest);
+        assert_eq!(grid.absolute_line(newest - 1).unwrap().text(), "top ");
+    }
+
+    #[test]
+    fn test_search_scrollback_and_screen() {
+        let mut grid = Grid::new(2, 8, 100);
+        write_row(&mut grid, 0, "Foo bar");
+        grid.scroll_up();
+        write_row(&mut grid, 0, "baz foo");
+
+        let matches = grid.search("foo", false);
+        assert_eq!(
+            matches,
+            vec![Match {
+                start_row: 1,
+                start_col: 4,
+                end_row: 1,
+                end_col: 6,
+            }]
+        );
+
+        let matches = grid.search("foo", true);
+        assert_eq!(matches.len(), 2);
+        assert_eq!((matches[0].start_row, matches[0].start_col), (0, 0));
+        assert_eq!((matches[1].start_row, matches[1].start_col), (1, 4));
+    }
+
+    #[test]
+    fn test_search_across_wrapped_rows() {
+        let mut grid = Grid::new(3, 4, 100);
+        write_row(&mut grid, 0, "abne");
+        write_row(&mut grid, 1, "edle");
+        grid.rows[1].wrapped = true;
+        write_row(&mut grid, 2, "need");
+
+        let matches = grid.search("needle", false);
+        assert_eq!(
+            matches,
+            vec![Match {
+                start_row: 0,
+                start_col: 2,
+                end_row: 1,
+                end_col: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_scrollback_sharing_survives_serde() {
+        let mut grid = Grid::new(2, 4, 100);
+        grid.compress_scrollback = true;
+        for _ in 0..4 {
+            grid.scroll_up();
+        }
+        assert!(Arc::ptr_eq(&grid.scrollback[0], &grid.scrollback[3]));
+
+        let json = serde_json::to_string(&grid).unwrap();
+        let restored: Grid = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.scrollback.len(), 4);
+        assert!(Arc::ptr_eq(
+            &restored.scrollback[0],
+            &restored.scrollback[3]
+        ));
+        assert_eq!(restored.scrollback_memory(), grid.scrollback_memory());
+    }
 }
@@ -0,0 +1,407 @@
+//! Keyboard input encoding: turns key presses into the bytes an application
+//! expects to read from the PTY, honoring the terminal's input modes.
+
+/// Keys on the numeric keypad, which encode differently in application mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeypadKey {
+    Digit(u8),
+    Enter,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Decimal,
+    Equal,
+}
+
+/// A key press to encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function keys F1-F12
+    F(u8),
+    Keypad(KeypadKey),
+}
+
+/// Modifier keys held during a key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub const SHIFT: u8 = 0b0001;
+    pub const ALT: u8 = 0b0010;
+    pub const CTRL: u8 = 0b0100;
+
+    pub fn contains(&self, modifier: u8) -> bool {
+        self.0 & modifier != 0
+    }
+
+    /// xterm modifier parameter (1 + bitmask), or None without modifiers
+    fn param(&self) -> Option<u8> {
+        let bits = self.0 & (Self::SHIFT | Self::ALT | Self::CTRL);
+        (bits != 0).then_some(bits + 1)
+    }
+}
+
+/// Kitty keyboard protocol flag: report Escape and keys with Ctrl/Alt as
+/// `CSI code ; mods u`, so they can't be confused with escape sequences
+pub const KITTY_DISAMBIGUATE: u8 = 0b0_0001;
+/// Kitty keyboard protocol flag: report every key, plain text included, as
+/// an escape sequence
+pub const KITTY_ALL_KEYS_AS_ESCAPES: u8 = 0b0_1000;
+
+/// Terminal modes that change how keys are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyModes {
+    /// DECCKM: cursor keys send SS3 (`ESC O x`) instead of CSI
+    pub application_cursor: bool,
+    /// DECKPAM: keypad keys send SS3 sequences instead of their characters
+    pub application_keypad: bool,
+    /// Mode 1034 reset: Alt sets the eighth bit of an ASCII character
+    /// (sent UTF-8 encoded) instead of prefixing ESC
+    pub meta_eight_bit: bool,
+    /// Kitty keyboard protocol flags pushed by the application (0: legacy
+    /// encoding). Only `KITTY_DISAMBIGUATE` and `KITTY_ALL_KEYS_AS_ESCAPES`
+    /// change the encoding; presses are the only events reported.
+    pub kitty_flags: u8,
+}
+
+/// Encode a key press as the bytes to send to the PTY
+pub fn encode_key(key: Key, mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    if modes.kitty_flags & (KITTY_DISAMBIGUATE | KITTY_ALL_KEYS_AS_ESCAPES) != 0 {
+        return encode_kitty(key, mods, modes);
+    }
+
+    match key {
+        Key::Char(c) => encode_char(c, mods, modes),
+        Key::Enter => alt_prefixed(b"\r", mods, modes),
+        Key::Tab if mods.contains(Modifiers::SHIFT) => b"\x1b[Z".to_vec(),
+        Key::Tab => alt_prefixed(b"\t", mods, modes),
+        Key::Backspace if mods.contains(Modifiers::CTRL) => alt_prefixed(b"\x08", mods, modes),
+        Key::Backspace => alt_prefixed(b"\x7f", mods, modes),
+        Key::Escape => alt_prefixed(b"\x1b", mods, modes),
+        Key::Up => cursor_key(b'A', mods, modes),
+        Key::Down => cursor_key(b'B', mods, modes),
+        Key::Right => cursor_key(b'C', mods, modes),
+        Key::Left => cursor_key(b'D', mods, modes),
+        Key::Home => cursor_key(b'H', mods, modes),
+        Key::End => cursor_key(b'F', mods, modes),
+        Key::Insert => tilde_key(2, mods),
+        Key::Delete => tilde_key(3, mods),
+        Key::PageUp => tilde_key(5, mods),
+        Key::PageDown => tilde_key(6, mods),
+        Key::F(n @ 1..=4) => ss3_key(b'P' + n - 1, mods),
+        Key::F(n @ 5..=12) => {
+            let code = [15, 17, 18, 19, 20, 21, 23, 24][(n - 5) as usize];
+            tilde_key(code, mods)
+        }
+        Key::F(_) => Vec::new(),
+        Key::Keypad(key) => encode_keypad(key, mods, modes),
+    }
+}
+
+/// `CSI code u`, or `CSI code ; m u` when modified
+fn csi_u(code: u32, mods: Modifiers) -> Vec<u8> {
+    match mods.param() {
+        Some(m) => format!("\x1b[{};{}u", code, m).into_bytes(),
+        None => format!("\x1b[{}u", code).into_bytes(),
+    }
+}
+
+/// Kitty keyboard protocol encoding. Keys the legacy encoding already sends
+/// unambiguously keep it, except that cursor keys always use CSI and F3
+/// uses `CSI 13 ~` (`CSI 1 ; m R` would look like a cursor position report).
+fn encode_kitty(key: Key, mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    let all_keys = modes.kitty_flags & KITTY_ALL_KEYS_AS_ESCAPES != 0;
+    let modified = mods.contains(Modifiers::CTRL) || mods.contains(Modifiers::ALT);
+    let legacy = KeyModes {
+        application_cursor: false,
+        kitty_flags: 0,
+        ..*modes
+    };
+    match key {
+        // Keys are reported by their unshifted code where that's known
+        Key::Char(c) if all_keys || modified => {
+            csi_u(c.to_lowercase().next().unwrap_or(c) as u32, mods)
+        }
+        Key::Enter if all_keys || mods.param().is_some() => csi_u(13, mods),
+        Key::Tab if all_keys || mods.param().is_some() => csi_u(9, mods),
+        Key::Backspace if all_keys || mods.param().is_some() => csi_u(127, mods),
+        Key::Escape => csi_u(27, mods),
+        Key::F(3) => tilde_key(13, mods),
+        Key::Keypad(key) if all_keys => {
+            let code = match key {
+                KeypadKey::Digit(d) => 57399 + d.min(9) as u32,
+                KeypadKey::Decimal => 57409,
+                KeypadKey::Divide => 57410,
+                KeypadKey::Multiply => 57411,
+                KeypadKey::Minus => 57412,
+                KeypadKey::Plus => 57413,
+                KeypadKey::Enter => 57414,
+                KeypadKey::Equal => 57415,
+            };
+            csi_u(code, mods)
+        }
+        _ => encode_key(key, mods, &legacy),
+    }
+}
+
+/// Printable characters; Ctrl maps to C0 controls, Alt prefixes ESC
+fn encode_char(c: char, mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    if mods.contains(Modifiers::CTRL) {
+        let control = match c {
+            'a'..='z' | 'A'..='Z' => Some(c.to_ascii_uppercase() as u8 - b'@'),
+            '@' | ' ' | '2' => Some(0x00),
+            '[' | '3' => Some(0x1b),
+            '\\' | '4' => Some(0x1c),
+            ']' | '5' => Some(0x1d),
+            '^' | '6' => Some(0x1e),
+            '_' | '/' | '7' => Some(0x1f),
+            '?' | '8' => Some(0x7f),
+            _ => None,
+        };
+        if let Some(control) = control {
+            return alt_prefixed(&[control], mods, modes);
+        }
+    }
+
+    let mut buf = [0u8; 4];
+    alt_prefixed(c.encode_utf8(&mut buf).as_bytes(), mods, modes)
+}
+
+/// Apply Alt to a key's bytes: an ESC prefix, or with `meta_eight_bit` the
+/// eighth bit set on a single ASCII byte (other input is sent unchanged)
+fn alt_prefixed(bytes: &[u8], mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    if !mods.contains(Modifiers::ALT) {
+        return bytes.to_vec();
+    }
+    if modes.meta_eight_bit {
+        return match bytes {
+            &[byte] if byte.is_ascii() => {
+                let mut buf = [0u8; 4];
+                char::from(byte | 0x80)
+                    .encode_utf8(&mut buf)
+                    .as_bytes()
+                    .to_vec()
+            }
+            _ => bytes.to_vec(),
+        };
+    }
+    let mut out = Vec::with_capacity(bytes.len() + 1);
+    out.push(0x1b);
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Arrows, Home and End: `CSI x`, `SS3 x` in application cursor mode, or
+/// `CSI 1 ; m x` when modified
+fn cursor_key(final_byte: u8, mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    match mods.param() {
+        Some(m) => format!("\x1b[1;{}{}", m, final_byte as char).into_bytes(),
+        None if modes.application_cursor => vec![0x1b, b'O', final_byte],
+        None => vec![0x1b, b'[', final_byte],
+    }
+}
+
+/// `SS3 x`, or `CSI 1 ; m x` when modified (F1-F4)
+fn ss3_key(final_byte: u8, mods: Modifiers) -> Vec<u8> {
+    match mods.param() {
+        Some(m) => format!("\x1b[1;{}{}", m, final_byte as char).into_bytes(),
+        None => vec![0x1b, b'O', final_byte],
+    }
+}
+
+/// `CSI n ~`, or `CSI n ; m ~` when modified
+fn tilde_key(code: u8, mods: Modifiers) -> Vec<u8> {
+    match mods.param() {
+        Some(m) => format!("\x1b[{};{}~", code, m).into_bytes(),
+        None => format!("\x1b[{}~", code).into_bytes(),
+    }
+}
+
+/// Keypad keys send `SS3 x` in application mode and their plain character
+/// (CR for Enter) in numeric mode
+fn encode_keypad(key: KeypadKey, mods: Modifiers, modes: &KeyModes) -> Vec<u8> {
+    if modes.application_keypad {
+        let final_byte = match key {
+            KeypadKey::Digit(d) => b'p' + d.min(9),
+            KeypadKey::Enter => b'M',
+            KeypadKey::Plus => b'k',
+            KeypadKey::Minus => b'm',
+            KeypadKey::Multiply => b'j',
+            KeypadKey::Divide => b'o',
+            KeypadKey::Decimal => b'n',
+            KeypadKey::Equal => b'X',
+        };
+        return vec![0x1b, b'O', final_byte];
+    }
+
+    match key {
+        KeypadKey::Digit(d) => encode_char((b'0' + d.min(9)) as char, mods, modes),
+        KeypadKey::Enter => alt_prefixed(b"\r", mods, modes),
+        KeypadKey::Plus => encode_char('+', mods, modes),
+        KeypadKey::Minus => encode_char('-', mods, modes),
+        KeypadKey::Multiply => encode_char('*', mods, modes),
+        KeypadKey::Divide => encode_char('/', mods, modes),
+        KeypadKey::Decimal => encode_char('.', mods, modes),
+        KeypadKey::Equal => encode_char('=', mods, modes),
+    }
+}
+
+/// Translate mouse wheel notches into key presses, for alternate scroll mode
+/// where the application gets arrows instead of the view scrolling. Positive
+/// `notches` scroll up. Each notch sends `lines_per_notch` arrows, or a
+/// single Page Up/Down when `page` is set; at most `max_presses` keys are
+/// sent in all, so a runaway notch count can't produce unbounded output.
+pub fn encode_wheel(
+    notches: i32,
+    lines_per_notch: u16,
+    page: bool,
+    max_presses: usize,
+    modes: &KeyModes,
+) -> Vec<u8> {
+    let none = Modifiers::default();
+    let (key, per_notch) = match (notches > 0, page) {
+        (true, true) => (Key::PageUp, 1),
+        (false, true) => (Key::PageDown, 1),
+        (true, false) => (Key::Up, lines_per_notch as usize),
+        (false, false) => (Key::Down, lines_per_notch as usize),
+    };
+    let presses = per_notch
+        .saturating_mul(notches.unsigned_abs() as usize)
+        .min(max_presses);
+    encode_key(key, none, modes).repeat(presses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypad_enter() {
+        let mut modes = KeyModes::default();
+        let enter = Key::Keypad(KeypadKey::Enter);
+        assert_eq!(encode_key(enter, Modifiers::default(), &modes), b"\r");
+
+        modes.application_keypad = true;
+        assert_eq!(encode_key(enter, Modifiers::default(), &modes), b"\x1bOM");
+        assert_eq!(
+            encode_key(
+                Key::Keypad(KeypadKey::Digit(7)),
+                Modifiers::default(),
+                &modes
+            ),
+            b"\x1bOw"
+        );
+    }
+
+    #[test]
+    fn test_cursor_and_modified_keys() {
+        let mut modes = KeyModes::default();
+        let none = Modifiers::default();
+        assert_eq!(encode_key(Key::Up, none, &modes), b"\x1b[A");
+        modes.application_cursor = true;
+        assert_eq!(encode_key(Key::Up, none, &modes), b"\x1bOA");
+
+        let ctrl = Modifiers(Modifiers::CTRL);
+        assert_eq!(encode_key(Key::Left, ctrl, &modes), b"\x1b[1;5D");
+        assert_eq!(encode_key(Key::Char('c'), ctrl, &modes), b"\x03");
+        assert_eq!(encode_key(Key::Delete, ctrl, &modes), b"\x1b[3;5~");
+        assert_eq!(encode_key(Key::F(5), none, &modes), b"\x1b[15~");
+        assert_eq!(
+            encode_key(Key::Char('x'), Modifiers(Modifiers::ALT), &modes),
+            b"\x1bx"
+        );
+    }
+
+    #[test]
+    fn test_alt_meta_eight_bit() {
+        let mut modes = KeyModes::default();
+        let alt = Modifiers(Modifiers::ALT);
+        assert_eq!(encode_key(Key::Char('a'), alt, &modes), b"\x1ba");
+
+        modes.meta_eight_bit = true;
+        assert_eq!(encode_key(Key::Char('a'), alt, &modes), "\u{e1}".as_bytes());
+        assert_eq!(encode_key(Key::Backspace, alt, &modes), "\u{ff}".as_bytes());
+        // Non-ASCII characters can't carry the bit and go out unchanged
+        assert_eq!(encode_key(Key::Char('é'), alt, &modes), "é".as_bytes());
+        assert_eq!(
+            encode_key(Key::Char('a'), Modifiers::default(), &modes),
+            b"a"
+        );
+    }
+
+    #[test]
+    fn test_kitty_keyboard_encoding() {
+        let mut modes = KeyModes::default();
+        let none = Modifiers::default();
+        let ctrl = Modifiers(Modifiers::CTRL);
+        let shift = Modifiers(Modifiers::SHIFT);
+        assert_eq!(encode_key(Key::Escape, none, &modes), b"\x1b");
+        assert_eq!(encode_key(Key::Char('c'), ctrl, &modes), b"\x03");
+
+        // Disambiguate: Escape and Ctrl/Alt keys become CSI u; text and
+        // unmodified Enter stay as they were
+        modes.kitty_flags = KITTY_DISAMBIGUATE;
+        modes.application_cursor = true;
+        assert_eq!(encode_key(Key::Escape, none, &modes), b"\x1b[27u");
+        assert_eq!(encode_key(Key::Char('c'), ctrl, &modes), b"\x1b[99;5u");
+        assert_eq!(
+            encode_key(
+                Key::Char('X'),
+                Modifiers(Modifiers::CTRL | Modifiers::SHIFT),
+                &modes
+            ),
+            b"\x1b[120;6u"
+        );
+        assert_eq!(encode_key(Key::Char('A'), shift, &modes), b"A");
+        assert_eq!(encode_key(Key::Enter, none, &modes), b"\r");
+        assert_eq!(encode_key(Key::Tab, shift, &modes), b"\x1b[9;2u");
+        assert_eq!(encode_key(Key::Up, none, &modes), b"\x1b[A");
+        assert_eq!(encode_key(Key::F(3), ctrl, &modes), b"\x1b[13;5~");
+
+        // All keys as escapes: plain text and the keypad too
+        modes.kitty_flags |= KITTY_ALL_KEYS_AS_ESCAPES;
+        assert_eq!(encode_key(Key::Char('a'), none, &modes), b"\x1b[97u");
+        assert_eq!(encode_key(Key::Enter, none, &modes), b"\x1b[13u");
+        assert_eq!(
+            encode_key(Key::Keypad(KeypadKey::Digit(1)), none, &modes),
+            b"\x1b[57400u"
+        );
+    }
+
+    #[test]
+    fn test_wheel_translation() {
+        let mut modes = KeyModes::default();
+        assert_eq!(encode_wheel(1, 3, false, 24, &modes), b"\x1b[A\x1b[A\x1b[A");
+        assert_eq!(encode_wheel(-1, 1, false, 24, &modes), b"\x1b[B");
+        modes.application_cursor = true;
+        assert_eq!(encode_wheel(-2, 1, false, 24, &modes), b"\x1bOB\x1bOB");
+
+        // Page scrolling sends one Page key per notch regardless of the multiplier
+        assert_eq!(encode_wheel(1, 3, true, 24, &modes), b"\x1b[5~");
+        assert_eq!(encode_wheel(-2, 3, true, 24, &modes), b"\x1b[6~\x1b[6~");
+        assert!(encode_wheel(0, 3, false, 24, &modes).is_empty());
+
+        // A huge notch count is capped rather than allocating without bound
+        assert_eq!(encode_wheel(i32::MIN, 3, false, 24, &modes).len(), 24 * 3);
+        assert_eq!(
+            encode_wheel(i32::MAX, 3, true, 2, &modes),
+            b"\x1b[5~\x1b[5~"
+        );
+    }
+}
@@ -1,9 +1,18 @@
+pub mod charset;
 pub mod ffi;
 pub mod grid;
+pub mod input;
 pub mod parser;
 pub mod pty;
+pub mod render;
+pub mod session;
 pub mod terminal;
 
 // Re-export main types for convenience
-pub use grid::{Cell, Color, Grid, NamedColor, Rgb};
-pub use terminal::Terminal;
+pub use grid::{Cell, Color, Grid, NamedColor, Palette, Rgb};
+pub use session::{Screen, TerminalSession};
+pub use terminal::{
+    CursorShape, Damage, ImageDimension, NormalizationForm, Notification, PendingImage, PromptMark,
+    PromptMarkKind, SeqKind, SequenceHandler, SgrState, Terminal, TerminalBuilder, UnhandledSeq,
+    WideCharWrap,
+};
@@ -1,14 +1,23 @@
 use vte::{Params, Parser, Perform};
 
+/// Size of the parser's OSC buffer, counting all parameters but not the `;`
+/// separators. vte silently cuts longer strings to this length (its default
+/// is 1024 bytes, too little for OSC 52 clipboard payloads), so the terminal
+/// drops any OSC string that fills it; the longest one supported is one
+/// byte shorter. This also caps OSC 1337 inline images at about 48 KiB of
+/// file data, so bigger ones are never shown.
+pub const MAX_OSC_PAYLOAD: usize = 1 << 16;
+
 /// Parser handler that processes ANSI escape sequences
 pub struct AnsiParser {
-    parser: Parser,
+    // Boxed: the OSC buffer is stored inline
+    parser: Box<Parser<MAX_OSC_PAYLOAD>>,
 }
 
 impl AnsiParser {
     pub fn new() -> Self {
         Self {
-            parser: Parser::new(),
+            parser: Box::new(Parser::new_with_size()),
         }
     }
 
@@ -71,13 +80,7 @@ mod tests {
 
         fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {}
 
-        fn csi_dispatch(
-            &mut self,
-            params: &Params,
-            _intermediates: &[u8],
-            _ignore: bool,
-            c: char,
-        ) {
+        fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], _ignore: bool, c: char) {
             let params_vec = params_to_vec(params);
             self.csi_commands.push((params_vec, c));
         }
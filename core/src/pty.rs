@@ -1,7 +1,10 @@
-use nix::pty::{openpty, Winsize};
-use nix::unistd::{fork, setsid, ForkResult};
-use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use nix::pty::{Winsize, openpty};
+use nix::sys::signal::{self, Signal};
+use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::{ForkResult, fork, setsid, tcgetpgrp};
 use std::io::{self, Read, Write};
+use std::os::fd::{BorrowedFd, FromRawFd, IntoRawFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::process::Command;
 
@@ -9,6 +12,14 @@ pub struct Pty {
     pub master: RawFd,
     pub slave: RawFd,
     pub child_pid: Option<nix::unistd::Pid>,
+    /// Exit code of the reaped child (128 + signal if it was killed)
+    pub exit_status: Option<i32>,
+}
+
+/// Whether an error from `Pty::read` means the child side has closed
+/// (the shell exited) rather than a real I/O failure
+pub fn is_closed(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::UnexpectedEof
 }
 
 impl Pty {
@@ -21,13 +32,14 @@ impl Pty {
             ws_ypixel: 0,
         };
 
-        let pty_result = openpty(Some(&winsize), None)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let pty_result = openpty(Some(&winsize), None).map_err(io::Error::other)?;
 
+        // Take ownership of the raw fds; `Drop` closes them
         Ok(Pty {
-            master: pty_result.master.as_raw_fd(),
-            slave: pty_result.slave.as_raw_fd(),
+            master: pty_result.master.into_raw_fd(),
+            slave: pty_result.slave.into_raw_fd(),
             child_pid: None,
+            exit_status: None,
         })
     }
 
@@ -41,6 +53,7 @@ impl Pty {
                 self.child_pid = Some(child);
                 // Close slave in parent
                 unsafe { libc::close(self.slave) };
+                self.slave = -1;
                 Ok(())
             }
             Ok(ForkResult::Child) => {
@@ -50,6 +63,10 @@ impl Pty {
 
                 // Redirect stdin/stdout/stderr to slave PTY
                 unsafe {
+                    // Make the slave our controlling terminal so the shell
+                    // gets job control and a foreground process group
+                    libc::ioctl(self.slave, libc::TIOCSCTTY, 0);
+
                     libc::dup2(self.slave, libc::STDIN_FILENO);
                     libc::dup2(self.slave, libc::STDOUT_FILENO);
                     libc::dup2(self.slave, libc::STDERR_FILENO);
@@ -68,16 +85,68 @@ impl Pty {
                 eprintln!("Failed to execute shell: {}", err);
                 std::process::exit(1);
             }
-            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+            Err(e) => Err(io::Error::other(e)),
         }
     }
 
-    /// Read data from the PTY master
+    /// Read data from the PTY master. Once every slave fd is closed Linux
+    /// reports EIO (others report EOF); both surface as an `UnexpectedEof`
+    /// error, see `is_closed`.
     pub fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
         let mut file = unsafe { std::fs::File::from_raw_fd(self.master) };
         let result = file.read(buffer);
         std::mem::forget(file); // Don't close the fd
-        result
+
+        match result {
+            Ok(0) if !buffer.is_empty() => Err(closed_error()),
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => Err(closed_error()),
+            result => result,
+        }
+    }
+
+    /// Read everything currently available from the master, up to `max` bytes.
+    /// Stops as soon as a read would block, so this never waits for output.
+    pub fn read_all(&self, max: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut chunk = [0u8; 4096];
+
+        while data.len() < max {
+            let mut pollfd = libc::pollfd {
+                fd: self.master,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                break;
+            }
+            if pollfd.revents & libc::POLLIN == 0 {
+                // POLLHUP without data: the slave side is gone
+                if pollfd.revents & libc::POLLHUP != 0 && data.is_empty() {
+                    return Err(closed_error());
+                }
+                break;
+            }
+
+            let want = chunk.len().min(max - data.len());
+            match self.read(&mut chunk[..want]) {
+                Ok(n) => data.extend_from_slice(&chunk[..n]),
+                // Hand back what was read; the closure is reported next call
+                Err(e) if is_closed(&e) && !data.is_empty() => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(data)
     }
 
     /// Write data to the PTY master
@@ -90,11 +159,23 @@ impl Pty {
 
     /// Resize the PTY
     pub fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        self.resize_with_pixels(cols, rows, 0, 0)
+    }
+
+    /// Resize the PTY, also reporting the text area's size in pixels to
+    /// programs that ask (`TIOCGWINSZ`), e.g. to size sixel images
+    pub fn resize_with_pixels(
+        &self,
+        cols: u16,
+        rows: u16,
+        xpixel: u16,
+        ypixel: u16,
+    ) -> io::Result<()> {
         let winsize = Winsize {
             ws_row: rows,
             ws_col: cols,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
+            ws_xpixel: xpixel,
+            ws_ypixel: ypixel,
         };
 
         unsafe {
@@ -106,6 +187,96 @@ impl Pty {
         Ok(())
     }
 
+    /// Current window size of the PTY
+    pub fn window_size(&self) -> io::Result<Winsize> {
+        let mut winsize = Winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        unsafe {
+            if libc::ioctl(self.master, libc::TIOCGWINSZ, &mut winsize) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(winsize)
+    }
+
+    /// Current terminal attributes of the PTY
+    pub fn get_termios(&self) -> io::Result<Termios> {
+        termios::tcgetattr(self.master_borrowed()).map_err(io::Error::other)
+    }
+
+    /// Apply terminal attributes immediately (`TCSANOW`)
+    pub fn set_termios(&self, attrs: &Termios) -> io::Result<()> {
+        termios::tcsetattr(self.master_borrowed(), SetArg::TCSANOW, attrs).map_err(io::Error::other)
+    }
+
+    /// Turn input echo on or off, e.g. around password prompts
+    pub fn set_echo(&self, enabled: bool) -> io::Result<()> {
+        self.set_local_flag(LocalFlags::ECHO, enabled)
+    }
+
+    /// Switch between canonical (line-buffered) and non-canonical input
+    pub fn set_canonical(&self, enabled: bool) -> io::Result<()> {
+        self.set_local_flag(LocalFlags::ICANON, enabled)
+    }
+
+    fn set_local_flag(&self, flag: LocalFlags, enabled: bool) -> io::Result<()> {
+        let mut attrs = self.get_termios()?;
+        attrs.local_flags.set(flag, enabled);
+        self.set_termios(&attrs)
+    }
+
+    fn master_borrowed(&self) -> BorrowedFd<'_> {
+        // The master stays open for as long as `self` lives
+        unsafe { BorrowedFd::borrow_raw(self.master) }
+    }
+
+    /// Reap the child if it has exited, returning its exit code
+    pub fn try_wait(&mut self) -> io::Result<Option<i32>> {
+        if self.exit_status.is_some() {
+            return Ok(self.exit_status);
+        }
+        let Some(pid) = self.child_pid else {
+            return Ok(None);
+        };
+
+        let status = match waitpid(pid, Some(WaitPidFlag::WNOHANG)).map_err(io::Error::other)? {
+            WaitStatus::Exited(_, code) => code,
+            WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+            _ => return Ok(None),
+        };
+        self.child_pid = None;
+        self.exit_status = Some(status);
+        Ok(self.exit_status)
+    }
+
+    /// Send a signal to the child process
+    pub fn kill(&self, sig: Signal) -> io::Result<()> {
+        let Some(pid) = self.child_pid else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no child process"));
+        };
+        signal::kill(pid, sig).map_err(io::Error::other)
+    }
+
+    /// Send a signal to the foreground process group of the PTY, the way
+    /// the line discipline delivers SIGINT for Ctrl-C
+    pub fn signal_foreground(&self, sig: Signal) -> io::Result<()> {
+        let pgid = tcgetpgrp(self.master_borrowed()).map_err(io::Error::other)?;
+        // 0 means no foreground group; killpg(0) would signal our own group
+        if pgid.as_raw() <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no foreground process group",
+            ));
+        }
+        signal::killpg(pgid, sig).map_err(io::Error::other)
+    }
+
     /// Get the master file descriptor
     pub fn master_fd(&self) -> RawFd {
         self.master
@@ -116,22 +287,136 @@ impl Drop for Pty {
     fn drop(&mut self) {
         unsafe {
             libc::close(self.master);
+            // The slave is only still open here if no child was spawned
+            if self.slave >= 0 {
+                libc::close(self.slave);
+            }
         }
 
         // Send SIGHUP to child process if it exists
-        if let Some(pid) = self.child_pid {
-            let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGHUP);
-        }
+        let _ = self.kill(Signal::SIGHUP);
     }
 }
 
+fn closed_error() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "pty closed")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Poll `check` every 10ms until it yields a value, failing the test
+    /// after five seconds
+    fn wait_for<T>(what: &str, mut check: impl FnMut() -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(value) = check() {
+                return value;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
 
     #[test]
     fn test_pty_creation() {
         let pty = Pty::new(80, 24);
         assert!(pty.is_ok());
     }
+
+    #[test]
+    fn test_read_all_respects_max() {
+        let pty = Pty::new(80, 24).unwrap();
+        let data = b"0123456789";
+        let written = unsafe { libc::write(pty.slave, data.as_ptr().cast(), data.len()) };
+        assert_eq!(written, data.len() as isize);
+
+        assert_eq!(pty.read_all(4).unwrap(), b"0123");
+        assert_eq!(pty.read_all(1024).unwrap(), b"456789");
+        // Nothing left: returns immediately instead of blocking
+        assert!(pty.read_all(1024).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_resize_with_pixels() {
+        let pty = Pty::new(80, 24).unwrap();
+        pty.resize_with_pixels(100, 30, 800, 480).unwrap();
+        let winsize = pty.window_size().unwrap();
+        assert_eq!((winsize.ws_col, winsize.ws_row), (100, 30));
+        assert_eq!((winsize.ws_xpixel, winsize.ws_ypixel), (800, 480));
+
+        pty.resize(90, 20).unwrap();
+        assert_eq!(pty.window_size().unwrap().ws_xpixel, 0);
+    }
+
+    #[test]
+    fn test_toggle_echo_and_canonical() {
+        let pty = Pty::new(80, 24).unwrap();
+        assert!(
+            pty.get_termios()
+                .unwrap()
+                .local_flags
+                .contains(LocalFlags::ECHO)
+        );
+
+        pty.set_echo(false).unwrap();
+        pty.set_canonical(false).unwrap();
+        let flags = pty.get_termios().unwrap().local_flags;
+        assert!(!flags.contains(LocalFlags::ECHO));
+        assert!(!flags.contains(LocalFlags::ICANON));
+
+        pty.set_echo(true).unwrap();
+        assert!(
+            pty.get_termios()
+                .unwrap()
+                .local_flags
+                .contains(LocalFlags::ECHO)
+        );
+    }
+
+    #[test]
+    fn test_signal_foreground_terminates_sleep() {
+        let mut pty = Pty::new(80, 24).unwrap();
+        pty.spawn_shell(Some("/bin/sh")).unwrap();
+        // The interactive shell ignores SIGTERM; a non-interactive one
+        // doesn't, so once it prints the marker the foreground process dies
+        // of the signal whether or not it has exec'd sleep yet. The quotes
+        // keep the terminal's echo of the command from matching.
+        pty.write(b"exec sh -c 'echo sig\"\"ready; exec sleep 30'\n")
+            .unwrap();
+        let mut output = Vec::new();
+        wait_for("the ready marker", || {
+            output.extend(pty.read_all(4096).unwrap());
+            output.windows(8).any(|w| w == b"sigready").then_some(())
+        });
+
+        pty.signal_foreground(Signal::SIGTERM).unwrap();
+        let status = wait_for("the child to exit", || pty.try_wait().unwrap());
+        assert_eq!(status, 128 + libc::SIGTERM);
+        assert!(pty.kill(Signal::SIGTERM).is_err());
+    }
+
+    #[test]
+    fn test_read_after_child_exit_reports_closed() {
+        let mut pty = Pty::new(80, 24).unwrap();
+        pty.spawn_shell(Some("/bin/sh")).unwrap();
+        let pid = pty.child_pid.unwrap();
+        nix::sys::signal::kill(pid, Signal::SIGKILL).unwrap();
+
+        // Drain whatever the shell printed before dying
+        let mut buffer = [0u8; 1024];
+        let err = loop {
+            match pty.read(&mut buffer) {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        };
+        assert!(is_closed(&err));
+
+        let status = wait_for("the child to exit", || pty.try_wait().unwrap());
+        assert_eq!(status, 128 + libc::SIGKILL);
+        assert_eq!(pty.child_pid, None);
+    }
 }
@@ -0,0 +1,106 @@
+//! Conversion of grid cells into the renderer's `CCell` layout, and the
+//! cache of converted visible rows behind `terminal_get_row_ptr`
+
+use crate::grid::{Cell, Grid, Palette, Row};
+
+/// C-compatible cell structure for FFI
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CCell {
+    pub ch: u32, // Unicode codepoint
+    pub fg_r: u8,
+    pub fg_g: u8,
+    pub fg_b: u8,
+    pub bg_r: u8,
+    pub bg_g: u8,
+    pub bg_b: u8,
+    pub flags: u16,
+}
+
+/// Set in `CCell::flags` of column 0 when the row continues the previous
+/// one (soft wrap); not a cell attribute, only reported in row buffers
+pub const CELL_FLAG_ROW_WRAPPED: u16 = 0x8000;
+
+/// Convert a row for the renderer, tagging a soft-wrapped row's first cell
+pub(crate) fn convert_row(row: &Row, reverse_video: bool, palette: &Palette) -> Vec<CCell> {
+    let mut cells: Vec<CCell> = row
+        .cells
+        .iter()
+        .map(|cell| CCell::from_cell(cell, reverse_video, palette))
+        .collect();
+    if row.wrapped
+        && let Some(first) = cells.first_mut()
+    {
+        first.flags |= CELL_FLAG_ROW_WRAPPED;
+    }
+    cells
+}
+
+impl CCell {
+    /// Convert a cell using the terminal's screen-wide display state
+    pub(crate) fn from_cell(cell: &Cell, reverse_video: bool, palette: &Palette) -> Self {
+        let (fg, bg) = cell.resolved_colors(reverse_video);
+        let fg = palette.resolve(&fg);
+        let bg = palette.resolve(&bg);
+
+        CCell {
+            ch: cell.c as u32,
+            fg_r: fg.r,
+            fg_g: fg.g,
+            fg_b: fg.b,
+            bg_r: bg.r,
+            bg_g: bg.g,
+            bg_b: bg.b,
+            flags: cell.flags.0,
+        }
+    }
+}
+
+impl From<&Cell> for CCell {
+    fn from(cell: &Cell) -> Self {
+        CCell::from_cell(cell, false, &Palette::default())
+    }
+}
+
+/// Renderer-ready `CCell` copies of the visible rows, reconverted only when
+/// a row's version changes. Screen-wide changes (palette, default colors,
+/// reverse video) go through `Grid::mark_all_dirty`, whose repaint
+/// generation drops every cached row; a host editing `Terminal::palette`
+/// directly must call it too.
+#[derive(Default)]
+pub(crate) struct RowCache {
+    /// `Grid::repaint_generation` the cached rows were converted under
+    repaint_generation: Option<u64>,
+    /// Per visible row: the converted row's version and its cells
+    rows: Vec<Option<(u64, Vec<CCell>)>>,
+}
+
+impl RowCache {
+    /// The converted cells of visible row `idx`, refreshed if stale
+    pub(crate) fn row(
+        &mut self,
+        grid: &Grid,
+        reverse_video: bool,
+        palette: &Palette,
+        idx: usize,
+    ) -> Option<&[CCell]> {
+        let grid_row = grid.visible_row(idx)?;
+        if self.repaint_generation != Some(grid.repaint_generation) {
+            self.repaint_generation = Some(grid.repaint_generation);
+            self.rows.clear();
+        }
+        if self.rows.len() <= idx {
+            self.rows.resize(idx + 1, None);
+        }
+
+        let entry = &mut self.rows[idx];
+        if entry
+            .as_ref()
+            .is_none_or(|(version, _)| *version != grid_row.version)
+        {
+            let cells = convert_row(grid_row, reverse_video, palette);
+            *entry = Some((grid_row.version, cells));
+        }
+        entry.as_ref().map(|(_, cells)| cells.as_slice())
+    }
+}
@@ -0,0 +1,184 @@
+//! Safe Rust API for embedders that don't go through the C FFI
+//! (e.g. a ratatui backend)
+
+use crate::grid::{Cell, Grid};
+use crate::input::{Key, Modifiers};
+use crate::terminal::{Terminal, TerminalBuilder};
+use std::io;
+
+/// A terminal connected to a shell, driven without any `unsafe`
+pub struct TerminalSession {
+    terminal: Terminal,
+}
+
+/// Read-only view of the visible screen, borrowed from a session
+#[derive(Clone, Copy)]
+pub struct Screen<'a> {
+    grid: &'a Grid,
+}
+
+impl<'a> Screen<'a> {
+    pub fn rows(&self) -> usize {
+        self.grid.rows.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.grid.cols
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&'a Cell> {
+        self.grid.get_cell(row, col)
+    }
+
+    /// Cells of a visible row
+    pub fn row(&self, row: usize) -> Option<&'a [Cell]> {
+        self.grid.rows.get(row).map(|r| r.cells.as_slice())
+    }
+
+    /// Text of a visible row, trailing blanks trimmed
+    pub fn line(&self, row: usize) -> Option<String> {
+        self.grid
+            .rows
+            .get(row)
+            .map(|r| r.text().trim_end().to_string())
+    }
+
+    /// Every visible row as text, trailing blanks trimmed
+    pub fn lines(&self) -> Vec<String> {
+        (0..self.rows()).filter_map(|row| self.line(row)).collect()
+    }
+}
+
+impl TerminalSession {
+    /// Spawn the default shell in a `rows` x `cols` terminal
+    pub fn spawn(rows: usize, cols: usize) -> io::Result<Self> {
+        Self::with_builder(Terminal::builder().rows(rows).cols(cols))
+    }
+
+    /// Spawn a session from a fully configured builder
+    pub fn with_builder(builder: TerminalBuilder) -> io::Result<Self> {
+        Ok(Self {
+            terminal: builder.build_with_pty()?,
+        })
+    }
+
+    /// Process all output the shell has produced so far, returning the number
+    /// of bytes consumed. Fails with `UnexpectedEof` once the shell has exited.
+    pub fn pump(&mut self) -> io::Result<usize> {
+        self.terminal.pump()
+    }
+
+    /// Send text to the shell as typed input
+    pub fn send(&mut self, text: &str) -> io::Result<()> {
+        self.terminal.send_input(text.as_bytes())
+    }
+
+    /// Send a key press, encoded for the current input modes
+    pub fn send_key(&mut self, key: Key, mods: Modifiers) -> io::Result<()> {
+        let bytes = self.terminal.encode_key(key, mods);
+        self.terminal.send_input(&bytes)
+    }
+
+    pub fn screen(&self) -> Screen<'_> {
+        Screen {
+            grid: &self.terminal.grid,
+        }
+    }
+
+    /// Changed spans since the last `mark_clean`, as `(row, start_col, end_col)`
+    /// with the end inclusive
+    pub fn dirty(&self) -> Vec<(usize, usize, usize)> {
+        self.terminal.grid.dirty_spans()
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.terminal.grid.mark_clean();
+    }
+
+    /// Resize the terminal and the shell's PTY
+    pub fn resize(&mut self, rows: usize, cols: usize) -> io::Result<()> {
+        if rows == 0 || cols == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "terminal size must be at least 1x1",
+            ));
+        }
+        self.terminal.resize(rows, cols);
+        Ok(())
+    }
+
+    /// Cursor position as `(row, col)`
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.terminal.cursor.row, self.terminal.cursor.col)
+    }
+
+    /// Exit code of the shell once it has exited (128 + signal if killed)
+    pub fn exit_status(&mut self) -> io::Result<Option<i32>> {
+        match self.terminal.pty {
+            Some(ref mut pty) => pty.try_wait(),
+            None => Ok(None),
+        }
+    }
+
+    pub fn terminal(&self) -> &Terminal {
+        &self.terminal
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut Terminal {
+        &mut self.terminal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Pump until `done` holds for the screen, or give up after a few seconds
+    fn pump_until(session: &mut TerminalSession, done: impl Fn(&Screen) -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            session.pump().unwrap();
+            if done(&session.screen()) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut session =
+            TerminalSession::with_builder(Terminal::builder().rows(10).cols(40).shell("/bin/sh"))
+                .unwrap();
+
+        session.send("echo session-$((6 * 7))\n").unwrap();
+        // The typed command is echoed unexpanded, so only the output matches
+        assert!(pump_until(&mut session, |screen| {
+            screen
+                .lines()
+                .iter()
+                .any(|line| line.contains("session-42"))
+        }));
+        assert!(!session.dirty().is_empty());
+        session.mark_clean();
+        assert!(session.dirty().is_empty());
+
+        session.resize(12, 50).unwrap();
+        assert_eq!((session.screen().rows(), session.screen().cols()), (12, 50));
+        assert!(session.resize(0, 50).is_err());
+
+        session.send("exit 3\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            let _ = session.pump();
+            if let Some(status) = session.exit_status().unwrap() {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "shell did not exit");
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(status, 3);
+    }
+}
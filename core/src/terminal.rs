@@ -1,8 +1,30 @@
-use crate::grid::{CellFlags, Color, Grid, NamedColor, Rgb};
-use crate::parser::{params_to_vec, AnsiParser};
+use crate::charset::Charset;
+use crate::grid::{Cell, CellFlags, Color, Grid, NamedColor, Palette, Rgb, Row, sgr_params};
+use crate::input::{Key, KeyModes, Modifiers, encode_key, encode_wheel};
+use crate::parser::{AnsiParser, MAX_OSC_PAYLOAD, params_to_vec};
 use crate::pty::Pty;
+use crate::render::RowCache;
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{compose, is_combining_mark};
+use unicode_width::UnicodeWidthChar;
 use vte::{Params, Perform};
 
+/// Everything DECSC / `CSI s` saves and DECRC / `CSI u` restores
+#[derive(Debug, Clone)]
+pub struct SavedCursor {
+    /// Position and SGR state
+    pub cursor: Cursor,
+    pub pending_wrap: bool,
+    pub origin_mode: bool,
+    pub charsets: [Charset; 2],
+    pub active_charset: usize,
+}
+
 /// Cursor position and style
 #[derive(Debug, Clone)]
 pub struct Cursor {
@@ -27,7 +49,10 @@ impl Cursor {
     pub fn reset_style(&mut self) {
         self.fg = Color::Named(NamedColor::Foreground);
         self.bg = Color::Named(NamedColor::Background);
+        // SGR 0 does not undo DECSCA protection
+        let protected = self.flags.contains(CellFlags::PROTECTED);
         self.flags = CellFlags::new();
+        self.flags.set(CellFlags::PROTECTED, protected);
     }
 }
 
@@ -37,27 +62,580 @@ impl Default for Cursor {
     }
 }
 
+/// Snapshot of the style new text is written with (see `Terminal::current_sgr`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SgrState {
+    pub fg: Color,
+    pub bg: Color,
+    /// SGR attributes; DECSCA protection isn't part of SGR and is left out
+    pub flags: CellFlags,
+}
+
+impl SgrState {
+    /// SGR parameters that select this style, starting with a reset
+    fn params(&self) -> String {
+        sgr_params(
+            self.flags,
+            self.fg,
+            self.bg,
+            (
+                Color::Named(NamedColor::Foreground),
+                Color::Named(NamedColor::Background),
+            ),
+        )
+    }
+
+    /// The escape sequence (`CSI ... m`) that sets exactly this style
+    pub fn to_ansi(&self) -> String {
+        format!("\x1b[{}m", self.params())
+    }
+}
+
+/// Upper bound on bytes consumed by one `pump` call, so a flood of output
+/// can't starve the host's render loop
+const PUMP_LIMIT: usize = 1 << 20;
+
+/// Tab stop spacing used until the host picks another
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// Lines per wheel notch sent in alternate scroll mode, as xterm
+const DEFAULT_SCROLL_MULTIPLIER: u16 = 3;
+
+/// Titles kept by XTWINOPS 22; the oldest is dropped beyond this (as xterm)
+const MAX_TITLE_STACK: usize = 10;
+
+/// Kitty keyboard flag entries kept per screen; the oldest is dropped beyond this
+const MAX_KITTY_KEYBOARD_STACK: usize = 16;
+
+/// Tab stops every `width` columns (never at column 0)
+fn default_tab_stops(cols: usize, width: usize) -> Vec<bool> {
+    (0..cols).map(|col| col > 0 && col % width == 0).collect()
+}
+
+/// The Unicode control picture for a C0 control or DEL (U+2400-U+2421)
+fn control_picture(byte: u8) -> char {
+    match byte {
+        0x7f => '\u{2421}',
+        byte => char::from_u32(0x2400 + byte as u32).unwrap_or('\u{fffd}'),
+    }
+}
+
+/// Largest DCS payload buffered; anything beyond is dropped
+const MAX_DCS_PAYLOAD: usize = 1 << 24;
+
+/// Device control strings we capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dcs {
+    /// `ESC P <params> q <sixel data> ESC \`
+    Sixel,
+    /// `ESC P $ q <setting> ESC \` (DECRQSS)
+    Decrqss,
+}
+
+/// Unicode normalization applied to printed text. Cells hold a single
+/// `char`, so only the composing forms are offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationForm {
+    /// Canonical composition: "e" + U+0301 becomes "é"
+    Nfc,
+    /// Compatibility composition: also folds ligatures, full-width forms, ...
+    Nfkc,
+}
+
+/// What printing a wide character in the last column does when autowrap is
+/// on (without autowrap it always overwrites the last two columns)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WideCharWrap {
+    /// Pad the last column with a blank and print on the next row, as xterm
+    #[default]
+    Wrap,
+    /// Drop the character, leaving the row and cursor as they were
+    Truncate,
+}
+
+/// Cursor shape selected by DECSCUSR (`CSI Ps SP q`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CursorShape {
+    #[default]
+    Block,
+    Underline,
+    Bar,
+}
+
+/// Path of an OSC 7 `file://host/path` URL, percent-decoded. The host part
+/// is not checked against the local hostname.
+fn parse_file_url(url: &[u8]) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        if path[i] == b'%'
+            && let (Some(hi), Some(lo)) = (
+                path.get(i + 1).copied().and_then(hex),
+                path.get(i + 2).copied().and_then(hex),
+            )
+        {
+            decoded.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            decoded.push(path[i]);
+            i += 1;
+        }
+    }
+    Some(PathBuf::from(std::ffi::OsString::from_vec(decoded)))
+}
+
+/// Base64 as sent in OSC 1337, where padding is optional
+const INLINE_IMAGE_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Parse an OSC 1337 `File=<args>:<base64 data>` payload (everything after
+/// `1337;`); `None` for other subcommands or undecodable data
+fn parse_inline_image(payload: &[u8]) -> Option<PendingImage> {
+    let rest = payload.strip_prefix(b"File=")?;
+    let colon = rest.iter().position(|&b| b == b':')?;
+    let (args, data) = (&rest[..colon], &rest[colon + 1..]);
+
+    let mut image = PendingImage {
+        preserve_aspect_ratio: true,
+        ..Default::default()
+    };
+    for arg in args.split(|&b| b == b';') {
+        let arg = String::from_utf8_lossy(arg);
+        let Some((key, value)) = arg.split_once('=') else {
+            continue;
+        };
+        match key {
+            "name" => {
+                let name = INLINE_IMAGE_BASE64.decode(value).ok();
+                image.name = name.map(|name| String::from_utf8_lossy(&name).into_owned());
+            }
+            "width" => image.width = ImageDimension::parse(value),
+            "height" => image.height = ImageDimension::parse(value),
+            "preserveAspectRatio" => image.preserve_aspect_ratio = value != "0",
+            "inline" => image.inline = value == "1",
+            _ => {}
+        }
+    }
+
+    let data: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    image.data = INLINE_IMAGE_BASE64.decode(data).ok()?;
+    Some(image)
+}
+
+/// Requested size of an inline image along one axis (OSC 1337 `width=` /
+/// `height=`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageDimension {
+    /// The image's own size, or scaled to match the other axis
+    #[default]
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+    /// Percentage of the terminal's width or height
+    Percent(u32),
+}
+
+impl ImageDimension {
+    /// Parse `N`, `Npx`, `N%` or `auto`; anything else means `Auto`
+    fn parse(value: &str) -> Self {
+        let number = |s: &str| s.parse::<u32>().ok();
+        if let Some(px) = value.strip_suffix("px").and_then(number) {
+            Self::Pixels(px)
+        } else if let Some(percent) = value.strip_suffix('%').and_then(number) {
+            Self::Percent(percent)
+        } else {
+            number(value).map_or(Self::Auto, Self::Cells)
+        }
+    }
+}
+
+/// An iTerm2 inline image (OSC 1337 `File=`), waiting for the host to
+/// render it. The whole sequence must fit in `MAX_OSC_PAYLOAD`, which
+/// after base64 leaves room for about 48 KiB of file data; larger images
+/// are dropped and counted in `truncated_osc_count`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PendingImage {
+    /// File name from the base64 `name=` argument
+    pub name: Option<String>,
+    pub width: ImageDimension,
+    pub height: ImageDimension,
+    /// `preserveAspectRatio=0` turns this off
+    pub preserve_aspect_ratio: bool,
+    /// `inline=1`: show the image rather than offering it as a download
+    pub inline: bool,
+    /// The decoded file contents (PNG, JPEG, ...)
+    pub data: Vec<u8>,
+}
+
+/// A desktop notification requested by the application (OSC 9 / OSC 777)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Empty for OSC 9, which carries only a message
+    pub title: String,
+    pub body: String,
+}
+
+/// Boundary in a shell's prompt/command/output cycle, reported via OSC 133
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptMarkKind {
+    /// `A` - the prompt starts
+    PromptStart,
+    /// `B` - the prompt ends and the typed command starts
+    CommandStart,
+    /// `C` - the command was submitted; its output starts
+    OutputStart,
+    /// `D` - the command finished
+    CommandEnd,
+}
+
+/// A semantic prompt mark at an absolute line (see `Grid::absolute_line`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptMark {
+    pub kind: PromptMarkKind,
+    pub line: usize,
+    pub col: usize,
+    /// Exit status from `OSC 133 ; D ; <status>`
+    pub exit_code: Option<i32>,
+}
+
+/// Summary of what a single `process_and_damage` feed changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Damage {
+    /// Rows needing a redraw (dirty since the last `mark_clean`)
+    pub dirty_rows: Vec<usize>,
+    /// Lines scrolled up during the feed
+    pub scrolled_lines: usize,
+    pub title_changed: bool,
+    /// Whether the bell rang during the feed
+    pub bell: bool,
+}
+
+/// Most unhandled sequences kept before older ones are dropped
+const MAX_UNHANDLED: usize = 256;
+
+/// Most notifications kept in `Terminal::notifications` before older ones
+/// are dropped
+pub const MAX_NOTIFICATIONS: usize = 64;
+
+/// Most unrecognized OSC strings kept in `Terminal::recent_osc`; each can be
+/// up to `MAX_OSC_PAYLOAD` bytes
+pub const MAX_RECENT_OSC: usize = 8;
+
+/// Kind of control sequence recorded in `Terminal::unhandled`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqKind {
+    Csi,
+    Esc,
+    Osc,
+    Dcs,
+}
+
+/// A control sequence that reached a no-op arm, kept for debugging
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnhandledSeq {
+    pub kind: SeqKind,
+    pub intermediates: Vec<u8>,
+    /// Numeric parameters; for OSC, just the command number
+    pub params: Vec<i64>,
+    /// Final byte (none for OSC)
+    pub action: Option<char>,
+}
+
+impl std::fmt::Display for UnhandledSeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            SeqKind::Csi => "CSI",
+            SeqKind::Esc => "ESC",
+            SeqKind::Osc => "OSC",
+            SeqKind::Dcs => "DCS",
+        };
+        let params: Vec<String> = self.params.iter().map(|p| p.to_string()).collect();
+        write!(
+            f,
+            "{} {}{}",
+            kind,
+            String::from_utf8_lossy(&self.intermediates),
+            params.join(";")
+        )?;
+        if let Some(action) = self.action {
+            write!(f, "{}", action)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hook that sees CSI, OSC and ESC sequences before the terminal handles
+/// them, e.g. for an application's private OSC protocol. Each method returns
+/// true to consume the sequence; false passes it on to the default handling.
+pub trait SequenceHandler: Send {
+    fn csi(
+        &mut self,
+        _term: &mut Terminal,
+        _params: &[i64],
+        _intermediates: &[u8],
+        _action: char,
+    ) -> bool {
+        false
+    }
+
+    fn osc(&mut self, _term: &mut Terminal, _params: &[&[u8]]) -> bool {
+        false
+    }
+
+    fn esc(&mut self, _term: &mut Terminal, _intermediates: &[u8], _byte: u8) -> bool {
+        false
+    }
+}
+
 /// Terminal emulator state
 pub struct Terminal {
+    /// The active screen: the primary grid, or the alternate one while
+    /// `alt_screen` is set
     pub grid: Grid,
+    /// The screen not being shown (swapped with `grid` on 47/1047/1049)
+    pub inactive_grid: Grid,
+    /// Whether the alternate screen is active
+    pub alt_screen: bool,
     pub cursor: Cursor,
-    pub saved_cursor: Option<Cursor>,
-    parser: AnsiParser,
+    pub saved_cursor: Option<SavedCursor>,
+    /// Taken out while processing; see `process_bytes`
+    parser: Option<AnsiParser>,
     pub pty: Option<Pty>,
     pub rows: usize,
     pub cols: usize,
+    /// DECSCNM (mode 5): swap default foreground and background screen-wide
+    pub reverse_video: bool,
+    /// Set after printing in the last column; the wrap happens on the next print
+    pub pending_wrap: bool,
+    /// RGB values used when handing colors to the host
+    pub palette: Palette,
+    /// Spacing of the default tab stops; change via `set_tab_width`
+    pub tab_width: usize,
+    /// Tab stop per column, set by default every `tab_width` columns and by HTS
+    pub tab_stops: Vec<bool>,
+    /// Set by TBC 3 (clear all stops) until `set_tab_width`; while set,
+    /// columns added by a resize get no default stops
+    pub tab_stops_cleared: bool,
+    /// Scroll region (DECSTBM), inclusive 0-based rows
+    pub scroll_top: usize,
+    pub scroll_bottom: usize,
+    /// DECLRMM (mode 69): enables DECSLRM, taking `CSI s` over from SCOSC
+    pub lr_margin_mode: bool,
+    /// Left/right margins (DECSLRM), inclusive 0-based columns. Only column
+    /// insertion and deletion (DECIC/DECDC) are confined to them so far.
+    pub left_margin: usize,
+    pub right_margin: usize,
+    /// Window title set by OSC 0/2
+    pub title: String,
+    /// Titles saved by XTWINOPS 22, restored by 23
+    pub title_stack: Vec<String>,
+    /// Kitty keyboard protocol flags pushed by `CSI > flags u`, innermost
+    /// last; the top entry is in effect. The hidden screen's stack is kept
+    /// in `inactive_kitty_keyboard`, as each screen has its own.
+    pub kitty_keyboard: Vec<u8>,
+    inactive_kitty_keyboard: Vec<u8>,
+    /// Audible bells (standalone BEL) since the host last took them
+    pub bell_count: usize,
+    /// OSC strings dropped for exceeding `MAX_OSC_PAYLOAD` since the host
+    /// last took them
+    pub truncated_osc_count: usize,
+    /// Payload of the last complete sixel DCS, waiting for the host to render it
+    pub pending_dcs: Vec<u8>,
+    /// Last OSC 1337 inline image, waiting for the host to render it
+    pub pending_image: Option<PendingImage>,
+    /// DCS currently being received, selected in `hook`
+    dcs: Option<Dcs>,
+    dcs_buffer: Vec<u8>,
+    /// Replies to queries (DECRQSS, ...) waiting to be written back to the PTY
+    pub responses: Vec<u8>,
+    /// Sent in reply to ENQ (0x05); empty (the default) ignores ENQ
+    pub answerback: String,
+    /// Normalize printed text before storing it (`None` keeps it as received)
+    pub normalize: Option<NormalizationForm>,
+    /// Palette restored by OSC 104 (and OSC 110/111 for the default colors):
+    /// the one configured at build time or last set with `set_palette`,
+    /// `set_palette_color` or `set_default_colors`
+    pub base_palette: Palette,
+    /// DECCKM (mode 1): cursor keys send SS3 sequences
+    pub application_cursor: bool,
+    /// DECKPAM/DECKPNM (`ESC =` / `ESC >`): keypad keys send SS3 sequences
+    pub application_keypad: bool,
+    /// Bracketed paste (mode 2004): the application wants pastes wrapped in
+    /// `CSI 200~` / `CSI 201~` and handles their contents itself
+    pub bracketed_paste: bool,
+    /// Alternate scroll (mode 1007): the host should send the wheel as
+    /// arrow keys (see `wheel_to_keys`) rather than scroll the view
+    pub alternate_scroll: bool,
+    /// Lines per wheel notch when translating the wheel to arrow keys
+    pub scroll_multiplier: u16,
+    /// Record sequences we ignore into `unhandled` (off by default)
+    pub record_unhandled: bool,
+    /// Ignored sequences, oldest first, while `record_unhandled` is set
+    pub unhandled: Vec<UnhandledSeq>,
+    /// OSC strings with unrecognized numbers as (number, payload after the
+    /// first `;`), oldest first; at most `MAX_RECENT_OSC`
+    recent_osc: Vec<(u32, Vec<u8>)>,
+    /// Gets first pick of dispatched sequences, see `set_sequence_interceptor`
+    interceptor: Option<Box<dyn SequenceHandler>>,
+    /// Working directory reported by the shell via OSC 7
+    pub cwd: Option<PathBuf>,
+    /// Size of one cell in pixels (width, height), used to report the text
+    /// area's pixel size to the PTY; (0, 0) when unknown
+    pub cell_pixel_size: (u16, u16),
+    /// IRM (mode 4): printing shifts the rest of the line right instead of
+    /// overwriting it
+    pub insert_mode: bool,
+    /// DECTCEM (mode 25): whether the host should draw the cursor
+    pub cursor_visible: bool,
+    /// Cursor shape set by DECSCUSR
+    pub cursor_shape: CursorShape,
+    /// Whether the DECSCUSR style blinks (0 and the odd styles do)
+    pub cursor_style_blink: bool,
+    /// Mode 12: start (set) or stop (reset) blinking; `None` until the
+    /// application uses it, and again after DECSCUSR. See `cursor_blinks`.
+    pub cursor_blink_mode: Option<bool>,
+    /// DECOM (mode 6): cursor positions are relative to the scroll region,
+    /// and the cursor stays inside it
+    pub origin_mode: bool,
+    /// DECAWM (mode 7): wrap at the right margin; when off, the last column
+    /// is overwritten in place. Change via `set_autowrap`
+    pub autowrap: bool,
+    /// Handling of a wide character that doesn't fit in the last column
+    pub wide_char_wrap: WideCharWrap,
+    /// Mode 1034: Alt-modified keys are sent ESC-prefixed; when reset, Alt
+    /// sets the eighth bit instead
+    pub meta_sends_escape: bool,
+    /// Reverse wraparound (mode 45): BS at the left margin of a soft-wrapped
+    /// row moves to the last column of the row above
+    pub reverse_wrap: bool,
+    /// Conformance level set by DECSCL: 61 (VT100) to 65 (VT500). Recorded
+    /// for the host; all levels behave alike, as only 7-bit controls are sent
+    pub conformance_level: u8,
+    /// Last printed character, repeated by REP
+    last_printed: Option<char>,
+    /// Treat C1 controls (U+0080-U+009F, e.g. U+009B as CSI) as their
+    /// `ESC x` equivalents; off by default, when they are ignored. This
+    /// covers both the UTF-8 encoded form and raw 8-bit bytes (a lone 0x9B)
+    /// that are not continuation bytes of a UTF-8 character.
+    pub c1_as_control: bool,
+    /// A UTF-8 lead byte 0xC2 held back at the end of a chunk, since it may
+    /// start a C1 control
+    c1_lead: bool,
+    /// Continuation bytes still expected by the UTF-8 character being
+    /// translated, so they aren't taken for raw C1 bytes
+    utf8_remaining: u8,
+    /// Show C0 controls that have no action (and DEL) as their Unicode
+    /// control pictures, e.g. 0x01 as `␁`, instead of ignoring them
+    pub print_control_chars: bool,
+    /// Cursor color set via OSC 12 (`None`: the host's default)
+    pub cursor_color: Option<Color>,
+    /// Background color erase: ED/EL fill with the current SGR background
+    /// (on by default, as in xterm)
+    pub bce: bool,
+    /// Character sets designated into G0 and G1
+    pub charsets: [Charset; 2],
+    /// Which of G0/G1 is invoked for printing (switched by SI/SO)
+    pub active_charset: usize,
+    /// Converted rows handed out by `terminal_get_row_ptr`
+    pub(crate) row_cache: RowCache,
+    /// When output last changed the screen (`None` until it first does);
+    /// replies to queries, mode changes and the like don't count
+    pub last_output: Option<Instant>,
+    /// Notifications waiting for the host, oldest first; at most
+    /// `MAX_NOTIFICATIONS`
+    pub notifications: Vec<Notification>,
+    /// OSC 133 marks, oldest first; marks on lines dropped from scrollback
+    /// are pruned as new ones arrive
+    pub prompt_marks: Vec<PromptMark>,
 }
 
 impl Terminal {
+    /// A size of 0 in either dimension is raised to 1
     pub fn new(rows: usize, cols: usize) -> Self {
+        let (rows, cols) = (rows.max(1), cols.max(1));
         Self {
             grid: Grid::new(rows, cols, 10000),
+            // The alternate screen keeps no history
+            inactive_grid: Grid::new(rows, cols, 0),
+            alt_screen: false,
             cursor: Cursor::new(),
             saved_cursor: None,
-            parser: AnsiParser::new(),
+            parser: Some(AnsiParser::new()),
             pty: None,
             rows,
             cols,
+            reverse_video: false,
+            pending_wrap: false,
+            palette: Palette::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            tab_stops: default_tab_stops(cols, DEFAULT_TAB_WIDTH),
+            tab_stops_cleared: false,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            lr_margin_mode: false,
+            left_margin: 0,
+            right_margin: cols - 1,
+            title: String::new(),
+            title_stack: Vec::new(),
+            kitty_keyboard: Vec::new(),
+            inactive_kitty_keyboard: Vec::new(),
+            bell_count: 0,
+            truncated_osc_count: 0,
+            pending_dcs: Vec::new(),
+            pending_image: None,
+            dcs: None,
+            dcs_buffer: Vec::new(),
+            responses: Vec::new(),
+            answerback: String::new(),
+            normalize: None,
+            base_palette: Palette::default(),
+            application_cursor: false,
+            application_keypad: false,
+            bracketed_paste: false,
+            alternate_scroll: false,
+            scroll_multiplier: DEFAULT_SCROLL_MULTIPLIER,
+            record_unhandled: false,
+            unhandled: Vec::new(),
+            recent_osc: Vec::new(),
+            interceptor: None,
+            cwd: None,
+            cell_pixel_size: (0, 0),
+            autowrap: true,
+            wide_char_wrap: WideCharWrap::Wrap,
+            reverse_wrap: false,
+            conformance_level: 65,
+            meta_sends_escape: true,
+            last_printed: None,
+            origin_mode: false,
+            insert_mode: false,
+            cursor_visible: true,
+            cursor_shape: CursorShape::Block,
+            cursor_style_blink: true,
+            cursor_blink_mode: None,
+            c1_as_control: false,
+            c1_lead: false,
+            utf8_remaining: 0,
+            print_control_chars: false,
+            cursor_color: None,
+            bce: true,
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+            row_cache: RowCache::default(),
+            last_output: None,
+            notifications: Vec::new(),
+            prompt_marks: Vec::new(),
         }
     }
 
@@ -66,83 +644,543 @@ impl Terminal {
         let mut pty = Pty::new(cols as u16, rows as u16)?;
         pty.spawn_shell(None)?;
 
-        Ok(Self {
-            grid: Grid::new(rows, cols, 10000),
-            cursor: Cursor::new(),
-            saved_cursor: None,
-            parser: AnsiParser::new(),
-            pty: Some(pty),
-            rows,
-            cols,
-        })
+        let mut terminal = Self::new(rows, cols);
+        terminal.pty = Some(pty);
+        Ok(terminal)
+    }
+
+    /// Start configuring a terminal; see `TerminalBuilder`
+    pub fn builder() -> TerminalBuilder {
+        TerminalBuilder::default()
     }
 
     /// Process incoming bytes from PTY
     pub fn process_bytes(&mut self, bytes: &[u8]) {
-        let mut parser = std::mem::replace(&mut self.parser, AnsiParser::new());
+        if bytes.is_empty() {
+            return;
+        }
+        let generation = self.grid.generation;
+
+        let translated;
+        let bytes = if self.c1_as_control || self.c1_lead {
+            translated = self.translate_c1(bytes);
+            &translated[..]
+        } else {
+            bytes
+        };
+
+        // A sequence interceptor feeding bytes back in gets a fresh parser
+        let mut parser = self.parser.take().unwrap_or_default();
         for &byte in bytes {
             parser.advance(self, byte);
         }
-        self.parser = parser;
+        self.parser = Some(parser);
+
+        if self.grid.generation != generation {
+            self.last_output = Some(Instant::now());
+        }
     }
 
-    /// Write a character at the current cursor position
-    fn write_char(&mut self, c: char) {
-        // Handle special characters
-        match c {
-            '\r' => {
-                self.cursor.col = 0;
-                return;
+    /// Rewrite C1 controls, UTF-8 encoded (0xC2 0x80-0x9F) or raw 8-bit
+    /// (0x80-0x9F outside a UTF-8 character), as `ESC x` so the parser
+    /// handles them like their 7-bit forms
+    fn translate_c1(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes.len() + 1);
+        for &byte in bytes {
+            if std::mem::take(&mut self.c1_lead) {
+                if (0x80..=0x9f).contains(&byte) {
+                    out.extend_from_slice(&[0x1b, byte - 0x40]);
+                    continue;
+                }
+                out.push(0xc2);
+                self.utf8_remaining = 1;
             }
-            '\n' => {
-                self.newline();
-                return;
+            if self.utf8_remaining > 0 && (0x80..=0xbf).contains(&byte) {
+                self.utf8_remaining -= 1;
+                out.push(byte);
+                continue;
+            }
+            self.utf8_remaining = 0;
+            if byte == 0xc2 && self.c1_as_control {
+                self.c1_lead = true;
+            } else if (0x80..=0x9f).contains(&byte) && self.c1_as_control {
+                out.extend_from_slice(&[0x1b, byte - 0x40]);
+            } else {
+                self.utf8_remaining = match byte {
+                    0xc3..=0xdf => 1,
+                    0xe0..=0xef => 2,
+                    0xf0..=0xf4 => 3,
+                    _ => 0,
+                };
+                out.push(byte);
             }
-            '\t' => {
-                // Tab to next 8-column boundary
-                self.cursor.col = ((self.cursor.col / 8) + 1) * 8;
-                if self.cursor.col >= self.cols {
-                    self.cursor.col = self.cols - 1;
+        }
+        out
+    }
+
+    /// Print a character, applying the normalization setting first
+    fn print_normalized(&mut self, c: char) {
+        let Some(form) = self.normalize else {
+            self.write_char(c);
+            return;
+        };
+
+        // Compose a combining mark into the character printed before it
+        if is_combining_mark(c) && self.compose_with_previous(c) {
+            return;
+        }
+        match form {
+            NormalizationForm::Nfc => self.write_char(c),
+            NormalizationForm::Nfkc => {
+                for c in c.to_string().nfkc() {
+                    self.write_char(c);
                 }
-                return;
             }
-            '\x08' => {
-                // Backspace
-                if self.cursor.col > 0 {
-                    self.cursor.col -= 1;
+        }
+    }
+
+    /// Replace the last printed character with its composition with `mark`,
+    /// returning false when the pair has no precomposed form
+    fn compose_with_previous(&mut self, mark: char) -> bool {
+        let col = if self.pending_wrap {
+            self.cursor.col
+        } else if self.cursor.col > 0 {
+            self.cursor.col - 1
+        } else {
+            return false;
+        };
+        let row = self.cursor.row;
+        let Some(base) = self.grid.get_cell(row, col).map(|cell| cell.c) else {
+            return false;
+        };
+        match compose(base, mark) {
+            Some(composed) => {
+                if let Some(cell) = self.grid.get_cell_mut(row, col) {
+                    cell.c = composed;
                 }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Columns a printed character occupies: 2 for wide characters (when
+    /// there's room for them at all), otherwise 1
+    fn char_width(&self, c: char) -> usize {
+        if self.cols >= 2 && c.width() == Some(2) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Columns `s` would take up printed from column 0, with the same rules
+    /// as the grid: wide characters take 2, tabs advance to the next multiple
+    /// of `tab_width`, and other controls and zero-width characters
+    /// (combining marks, ZWJ) take none. Line width isn't applied, so the
+    /// result can exceed `cols`.
+    pub fn measure(&self, s: &str) -> usize {
+        s.chars().fold(0, |col, c| match c {
+            '\t' => (col / self.tab_width + 1) * self.tab_width,
+            c if c.is_control() || c.width() == Some(0) => col,
+            c => col + self.char_width(c),
+        })
+    }
+
+    /// Write a printable character at the current cursor position.
+    /// Control characters never reach here; `execute` owns them.
+    fn write_char(&mut self, c: char) {
+        let wide = self.char_width(c) == 2;
+
+        // A cursor left out of range (e.g. set directly by an embedder) would
+        // drop the character; like xterm, print at the nearest cell instead
+        if self.cursor.row >= self.rows || self.cursor.col >= self.cols {
+            self.cursor.row = self.cursor.row.min(self.rows - 1);
+            self.cursor.col = self.cursor.col.min(self.cols - 1);
+            self.pending_wrap = false;
+        }
+
+        // Perform a deferred wrap before printing past the last column
+        if self.pending_wrap && self.autowrap {
+            self.wrap_line();
+        }
+
+        // A wide character doesn't fit in the last column: pad it and wrap
+        // early (or drop the character, as configured), or without autowrap
+        // overwrite the last two columns
+        if wide && self.cursor.col + 1 >= self.cols {
+            if self.autowrap && self.wide_char_wrap == WideCharWrap::Truncate {
                 return;
+            } else if self.autowrap {
+                let mut blank = self.erase_cell();
+                blank.flags.set(CellFlags::WRAP_PAD, true);
+                self.split_wide_pair(self.cursor.row, self.cursor.col);
+                self.grid
+                    .fill_cells(self.cursor.row, self.cursor.col, self.cols, &blank);
+                self.wrap_line();
+            } else {
+                self.cursor.col = self.cols - 2;
             }
-            _ => {}
         }
 
         // Write printable character
-        if let Some(cell) = self.grid.get_cell_mut(self.cursor.row, self.cursor.col) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        if self.insert_mode {
+            let blank = self.erase_cell();
+            self.split_wide_pair(row, col);
+            self.grid
+                .insert_cells(row, col, self.cols, if wide { 2 } else { 1 }, &blank);
+            // A wide character pushed off the end loses its right half
+            if let Some(last) = self.grid.rows.get(row).and_then(|r| r.cells.last())
+                && last.flags.contains(CellFlags::WIDE)
+            {
+                self.grid.fill_cells(row, self.cols - 1, self.cols, &blank);
+            }
+        }
+        self.split_wide_pair(row, col);
+        if wide {
+            self.split_wide_pair(row, col + 1);
+        }
+        let mut flags = self.cursor.flags;
+        flags.set(CellFlags::WIDE, wide);
+        if let Some(cell) = self.grid.get_cell_mut(row, col) {
             cell.c = c;
             cell.fg = self.cursor.fg;
             cell.bg = self.cursor.bg;
-            cell.flags = self.cursor.flags;
+            cell.flags = flags;
+        }
+        if wide && let Some(cell) = self.grid.get_cell_mut(row, col + 1) {
+            cell.c = ' ';
+            cell.fg = self.cursor.fg;
+            cell.bg = self.cursor.bg;
+            cell.flags = CellFlags(self.cursor.flags.0 | CellFlags::WIDE_SPACER);
+        }
+
+        // Advance cursor, deferring the wrap until the next printable character
+        self.last_printed = Some(c);
+        let width = if wide { 2 } else { 1 };
+        if self.cursor.col + width < self.cols {
+            self.cursor.col += width;
+        } else {
+            self.cursor.col = self.cols - 1;
+            if self.autowrap {
+                self.pending_wrap = true;
+            }
+        }
+    }
+
+    /// Move to the start of the next row, marking it as a soft-wrapped
+    /// continuation
+    fn wrap_line(&mut self) {
+        self.newline();
+        if let Some(row) = self.grid.rows.get_mut(self.cursor.row) {
+            row.wrapped = true;
+        }
+    }
+
+    /// Before overwriting the cell at `col`, blank the other half of a wide
+    /// character it belongs to so no orphaned half is left behind
+    fn split_wide_pair(&mut self, row: usize, col: usize) {
+        let Some(flags) = self.grid.get_cell(row, col).map(|cell| cell.flags) else {
+            return;
+        };
+        let other = if flags.contains(CellFlags::WIDE) {
+            col + 1
+        } else if flags.contains(CellFlags::WIDE_SPACER) && col > 0 {
+            col - 1
+        } else {
+            return;
+        };
+        let blank = self.erase_cell();
+        self.grid.fill_cells(row, other, other + 1, &blank);
+        self.grid.fill_cells(row, col, col + 1, &blank);
+    }
+
+    /// Move to the next tab stop, stopping at the last column. Like xterm,
+    /// the cells passed over become spaces carrying the current background,
+    /// so selecting across a tab copies spaces rather than stale text.
+    fn tab(&mut self) {
+        self.pending_wrap = false;
+        let target = ((self.cursor.col + 1)..self.cols)
+            .find(|&col| self.tab_stops.get(col).copied().unwrap_or(false))
+            .unwrap_or(self.cols - 1);
+
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        if col < target {
+            self.split_wide_pair(row, col);
+            self.split_wide_pair(row, target - 1);
+            let mut blank = self.grid.template;
+            blank.bg = self.cursor.bg;
+            self.grid.fill_cells(row, col, target, &blank);
+        }
+        self.cursor.col = target;
+    }
+
+    /// Set the default tab stop spacing (minimum 1), replacing all tab stops
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.max(1);
+        self.tab_stops = default_tab_stops(self.cols, self.tab_width);
+        self.tab_stops_cleared = false;
+    }
+
+    /// Whether printing past the right margin wraps (DECAWM)
+    pub fn autowrap(&self) -> bool {
+        self.autowrap
+    }
+
+    /// Turn autowrap on or off, as `CSI ? 7 h` / `CSI ? 7 l` do; a wrap
+    /// pending when it goes off is dropped
+    pub fn set_autowrap(&mut self, enabled: bool) {
+        self.autowrap = enabled;
+        if !enabled {
+            self.pending_wrap = false;
+        }
+    }
+
+    /// Whether the cursor should blink. Mode 12 and DECSCUSR both decide
+    /// this and the one used last wins: mode 12 overrides the blink of the
+    /// current DECSCUSR style, and DECSCUSR drops an earlier mode 12 setting.
+    pub fn cursor_blinks(&self) -> bool {
+        self.cursor_blink_mode.unwrap_or(self.cursor_style_blink)
+    }
+
+    /// DECSCUSR - select the cursor shape and whether it blinks; 0 restores
+    /// the default blinking block
+    fn set_cursor_style(&mut self, style: i64) {
+        let (shape, blink) = match style {
+            0 | 1 => (CursorShape::Block, true),
+            2 => (CursorShape::Block, false),
+            3 => (CursorShape::Underline, true),
+            4 => (CursorShape::Underline, false),
+            5 => (CursorShape::Bar, true),
+            6 => (CursorShape::Bar, false),
+            _ => {
+                self.note_unhandled(SeqKind::Csi, b" ", &[style], Some('q'));
+                return;
+            }
+        };
+        self.cursor_shape = shape;
+        self.cursor_style_blink = blink;
+        self.cursor_blink_mode = None;
+    }
+
+    /// Move one column left. A pending wrap is cancelled rather than undone,
+    /// so BS after filling the last column lands on the second-to-last one (as xterm).
+    /// With reverse wraparound, BS at column 0 of a soft-wrapped row goes
+    /// back to the end of the row it continues.
+    fn backspace(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor.col == 0 && self.reverse_wrap && self.cursor.row > 0 {
+            let wrapped = self
+                .grid
+                .rows
+                .get(self.cursor.row)
+                .is_some_and(|row| row.wrapped);
+            if wrapped {
+                self.cursor.row -= 1;
+                self.cursor.col = self.cols.saturating_sub(1);
+            }
+            return;
         }
+        self.cursor.col = self.cursor.col.saturating_sub(1);
+    }
+
+    /// DECSC - save the cursor along with its wrap, origin and charset state
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            cursor: self.cursor.clone(),
+            pending_wrap: self.pending_wrap,
+            origin_mode: self.origin_mode,
+            charsets: self.charsets,
+            active_charset: self.active_charset,
+        });
+    }
+
+    /// DECRC - restore what `save_cursor` saved; nothing happens if nothing was saved
+    fn restore_cursor(&mut self) {
+        let Some(saved) = self.saved_cursor.clone() else {
+            return;
+        };
+        self.cursor = saved.cursor;
+        self.cursor.row = self.cursor.row.min(self.rows - 1);
+        self.cursor.col = self.cursor.col.min(self.cols - 1);
+        self.pending_wrap = saved.pending_wrap;
+        self.origin_mode = saved.origin_mode;
+        self.charsets = saved.charsets;
+        self.active_charset = saved.active_charset;
+    }
 
-        // Advance cursor
-        self.cursor.col += 1;
+    /// DECSTR - soft reset: modes, margins, SGR, charsets and the saved
+    /// cursor go back to their defaults; the screen and cursor position stay
+    fn soft_reset(&mut self) {
+        self.cursor_visible = true;
+        self.insert_mode = false;
+        self.origin_mode = false;
+        self.set_autowrap(true);
+        self.application_cursor = false;
+        self.application_keypad = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.left_margin = 0;
+        self.right_margin = self.cols - 1;
+        self.cursor.reset_style();
+        self.cursor.flags.set(CellFlags::PROTECTED, false);
+        self.charsets = [Charset::Ascii; 2];
+        self.active_charset = 0;
+        self.pending_wrap = false;
+        self.saved_cursor = Some(SavedCursor {
+            cursor: Cursor::new(),
+            pending_wrap: false,
+            origin_mode: false,
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+        });
+    }
 
-        // Wrap to next line if needed
-        if self.cursor.col >= self.cols {
-            self.newline();
+    /// Move the cursor to a position given relative to the origin: the
+    /// screen's top-left, or the scroll region's top in origin mode (where
+    /// the cursor can't leave the region)
+    fn goto_origin(&mut self, row: usize, col: usize) {
+        if self.origin_mode {
+            let row = (self.scroll_top + row).min(self.scroll_bottom);
+            self.goto(row, col);
+        } else {
+            self.goto(row, col);
         }
     }
 
-    /// Move to new line
+    /// Move the cursor to an absolute position, clamped to the screen
+    fn goto(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.pending_wrap = false;
+    }
+
+    /// Move to the start of the next line (CR + index)
     fn newline(&mut self) {
+        self.carriage_return();
+        self.index();
+    }
+
+    /// Move to column 0 of the current line
+    fn carriage_return(&mut self) {
         self.cursor.col = 0;
-        self.cursor.row += 1;
+        self.pending_wrap = false;
+    }
+
+    /// IND - move down one line keeping the column, scrolling the region
+    /// only if exactly at its bottom margin. Elsewhere (even below the
+    /// region) the cursor just moves down, stopping at the last row.
+    fn index(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor.row == self.scroll_bottom {
+            self.grid
+                .scroll_region_up(self.scroll_top, self.scroll_bottom);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+    }
+
+    /// RI - move up one line keeping the column, scrolling the region down
+    /// only if exactly at its top margin
+    fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor.row == self.scroll_top {
+            self.grid
+                .scroll_region_down(self.scroll_top, self.scroll_bottom);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    /// Whether the cursor row lies within the scroll region
+    pub fn in_scroll_region(&self) -> bool {
+        (self.scroll_top..=self.scroll_bottom).contains(&self.cursor.row)
+    }
+
+    /// Clamp the cursor row into the scroll region
+    pub fn clamp_to_region(&mut self) {
+        self.cursor.row = self.cursor.row.clamp(self.scroll_top, self.scroll_bottom);
+    }
+
+    /// DECSTBM - set the scroll region from 1-based margins and home the cursor
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let top = top.max(1) - 1;
+        let bottom = if bottom == 0 {
+            self.rows
+        } else {
+            bottom.min(self.rows)
+        } - 1;
+        if top > bottom {
+            return;
+        }
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+        self.goto_origin(0, 0);
+    }
+
+    /// DECSLRM - set the left/right margins from 1-based columns and home
+    /// the cursor
+    fn set_lr_margins(&mut self, left: usize, right: usize) {
+        let left = left.max(1) - 1;
+        let right = if right == 0 {
+            self.cols
+        } else {
+            right.min(self.cols)
+        } - 1;
+        if left >= right {
+            return;
+        }
+        self.left_margin = left;
+        self.right_margin = right;
+        self.goto_origin(0, 0);
+    }
+
+    /// DECIC/DECDC - insert or delete columns at the cursor column in every
+    /// row of the scroll region, shifting cells up to the right margin. Does
+    /// nothing with the cursor outside the margins.
+    fn insert_or_delete_columns(&mut self, count: usize, insert: bool) {
+        let col = self.cursor.col;
+        if !self.in_scroll_region() || !(self.left_margin..=self.right_margin).contains(&col) {
+            return;
+        }
+        self.shift_columns(col, count, insert);
+    }
+
+    /// Shift the scroll region's columns `left..=right_margin` right (or
+    /// left) by `count`, blanking what is uncovered. Wide characters cut by
+    /// the band's edges are blanked first.
+    fn shift_columns(&mut self, left: usize, count: usize, right: bool) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        for row in top..=bottom {
+            self.split_wide_pair(row, left);
+            self.split_wide_pair(row, self.right_margin);
+        }
+        let blank = self.erase_cell();
+        if right {
+            self.grid
+                .scroll_region_right(top, bottom, left, self.right_margin, count, &blank);
+        } else {
+            self.grid
+                .scroll_region_left(top, bottom, left, self.right_margin, count, &blank);
+        }
+    }
 
-        // Scroll if at bottom
-        if self.cursor.row >= self.rows {
-            self.grid.scroll_up();
-            self.cursor.row = self.rows - 1;
+    /// IL/DL - insert or delete lines at the cursor, within the scroll region
+    fn insert_or_delete_lines(&mut self, count: usize, insert: bool) {
+        if !self.in_scroll_region() {
+            return;
+        }
+        let (top, bottom) = (self.cursor.row, self.scroll_bottom);
+        for _ in 0..count.min(bottom - top + 1) {
+            if insert {
+                self.grid.scroll_region_down(top, bottom);
+            } else {
+                self.grid.scroll_region_up(top, bottom);
+            }
         }
+        self.cursor.col = 0;
+        self.pending_wrap = false;
     }
 
     /// Handle SGR (Select Graphic Rendition) parameters
@@ -158,17 +1196,27 @@ impl Terminal {
             match params[i] {
                 0 => self.cursor.reset_style(),
                 1 => self.cursor.flags.set(CellFlags::BOLD, true),
+                2 => self.cursor.flags.set(CellFlags::DIM, true),
                 3 => self.cursor.flags.set(CellFlags::ITALIC, true),
                 4 => self.cursor.flags.set(CellFlags::UNDERLINE, true),
                 5 => self.cursor.flags.set(CellFlags::BLINK, true),
+                6 => self.cursor.flags.set(CellFlags::RAPID_BLINK, true),
                 7 => self.cursor.flags.set(CellFlags::INVERSE, true),
                 9 => self.cursor.flags.set(CellFlags::STRIKETHROUGH, true),
-                22 => self.cursor.flags.set(CellFlags::BOLD, false),
+                22 => {
+                    self.cursor.flags.set(CellFlags::BOLD, false);
+                    self.cursor.flags.set(CellFlags::DIM, false);
+                }
                 23 => self.cursor.flags.set(CellFlags::ITALIC, false),
                 24 => self.cursor.flags.set(CellFlags::UNDERLINE, false),
-                25 => self.cursor.flags.set(CellFlags::BLINK, false),
+                25 => {
+                    self.cursor.flags.set(CellFlags::BLINK, false);
+                    self.cursor.flags.set(CellFlags::RAPID_BLINK, false);
+                }
                 27 => self.cursor.flags.set(CellFlags::INVERSE, false),
                 29 => self.cursor.flags.set(CellFlags::STRIKETHROUGH, false),
+                53 => self.cursor.flags.set(CellFlags::OVERLINE, true),
+                55 => self.cursor.flags.set(CellFlags::OVERLINE, false),
                 // Foreground colors (30-37)
                 30..=37 => {
                     let color = match params[i] - 30 {
@@ -230,6 +1278,9 @@ impl Terminal {
                         i += 4;
                     }
                 }
+                // Default foreground / background, same as SGR 0 uses
+                39 => self.cursor.fg = Color::Named(NamedColor::Foreground),
+                49 => self.cursor.bg = Color::Named(NamedColor::Background),
                 // Bright foreground colors (90-97)
                 90..=97 => {
                     let color = match params[i] - 90 {
@@ -268,200 +1319,3557 @@ impl Terminal {
         }
     }
 
-    /// Resize the terminal
-    pub fn resize(&mut self, rows: usize, cols: usize) {
-        self.rows = rows;
-        self.cols = cols;
-        self.grid.resize(rows, cols);
+    /// Set the default colors, i.e. the palette's foreground and background.
+    /// Cells in the default colors, on screen or not, repaint in the new
+    /// ones; OSC 110/111 restore these after an OSC 10/11 override.
+    pub fn set_default_colors(&mut self, fg: Rgb, bg: Rgb) {
+        self.palette.foreground = fg;
+        self.palette.background = bg;
+        self.base_palette.foreground = fg;
+        self.base_palette.background = bg;
+        self.grid.mark_all_dirty();
+    }
 
-        // Resize PTY if present
-        if let Some(ref pty) = self.pty {
-            let _ = pty.resize(cols as u16, rows as u16);
-        }
+    /// Replace the palette, including what OSC 104 restores
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.base_palette = palette;
+        self.grid.mark_all_dirty();
+    }
 
-        // Ensure cursor is in bounds
-        if self.cursor.row >= rows {
-            self.cursor.row = rows - 1;
-        }
-        if self.cursor.col >= cols {
-            self.cursor.col = cols - 1;
+    /// Set one of the 16 ANSI colors, including what OSC 104 restores;
+    /// other indices are ignored
+    pub fn set_palette_color(&mut self, index: usize, rgb: Rgb) {
+        if index < self.palette.ansi.len() {
+            self.palette.ansi[index] = rgb;
+            self.base_palette.ansi[index] = rgb;
+            self.grid.mark_all_dirty();
         }
     }
 
-    /// Get the current grid state
-    pub fn get_grid(&self) -> &Grid {
-        &self.grid
+    /// Handle OSC 10/11/12 (and following params): set or query (`?`) the
+    /// default foreground, background and cursor color
+    fn set_dynamic_colors(&mut self, first: u32, specs: &[&[u8]], bell_terminated: bool) {
+        for (offset, spec) in specs.iter().enumerate() {
+            let command = first as usize + offset;
+            if *spec == b"?" {
+                let rgb = match command {
+                    10 => self.palette.foreground,
+                    11 => self.palette.background,
+                    12 => match self.cursor_color {
+                        Some(color) => self.palette.resolve(&color),
+                        None => self.palette.foreground,
+                    },
+                    _ => continue,
+                };
+                let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+                let reply = format!("\x1b]{};{}{}", command, rgb.to_spec(), terminator);
+                self.responses.extend_from_slice(reply.as_bytes());
+                continue;
+            }
+            let Some(rgb) = std::str::from_utf8(spec).ok().and_then(Rgb::from_spec) else {
+                continue;
+            };
+            match command {
+                10 => self.palette.foreground = rgb,
+                11 => self.palette.background = rgb,
+                12 => {
+                    self.cursor_color = Some(Color::Spec(rgb));
+                    continue;
+                }
+                _ => continue,
+            }
+            // Every cell in a default color repaints
+            self.grid.mark_all_dirty();
+        }
     }
 
-    /// Send input to the PTY
-    pub fn send_input(&self, data: &[u8]) -> std::io::Result<()> {
-        if let Some(ref pty) = self.pty {
-            pty.write(data)?;
+    /// OSC 104: restore the listed palette indices, or all of them when none
+    /// are given
+    fn reset_palette(&mut self, indices: &[&[u8]]) {
+        let mut any = false;
+        for index in indices.iter().filter(|index| !index.is_empty()) {
+            any = true;
+            let index = std::str::from_utf8(index)
+                .ok()
+                .and_then(|i| i.parse::<usize>().ok());
+            if let Some(index) = index.filter(|&i| i < self.palette.ansi.len()) {
+                self.palette.ansi[index] = self.base_palette.ansi[index];
+            }
         }
-        Ok(())
+        if !any {
+            self.palette.ansi = self.base_palette.ansi;
+        }
+        self.grid.mark_all_dirty();
     }
-}
 
-impl Perform for Terminal {
-    fn print(&mut self, c: char) {
-        self.write_char(c);
+    /// Blank cell for erase operations: with BCE, erased cells take the
+    /// current SGR background instead of the default one
+    fn erase_cell(&self) -> Cell {
+        let mut blank = self.grid.template;
+        if self.bce {
+            blank.bg = self.cursor.bg;
+        }
+        blank
     }
 
-    fn execute(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.newline(),
-            b'\r' => self.cursor.col = 0,
-            b'\t' => self.write_char('\t'),
-            b'\x08' => self.write_char('\x08'),
+    /// DECSED - erase in display, skipping protected cells
+    fn selective_erase_display(&mut self, mode: i64) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => {
+                self.grid.selective_erase(row, col, self.cols);
+                for r in (row + 1)..self.rows {
+                    self.grid.selective_erase(r, 0, self.cols);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.grid.selective_erase(r, 0, self.cols);
+                }
+                self.grid.selective_erase(row, 0, col + 1);
+            }
+            2 => {
+                for r in 0..self.rows {
+                    self.grid.selective_erase(r, 0, self.cols);
+                }
+            }
             _ => {}
         }
     }
 
-    fn hook(&mut self, _params: &Params, _intermediates: &[u8], _ignore: bool, _c: char) {}
+    /// DECSEL - erase in line, skipping protected cells
+    fn selective_erase_line(&mut self, mode: i64) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => self.grid.selective_erase(row, col, self.cols),
+            1 => self.grid.selective_erase(row, 0, col + 1),
+            2 => self.grid.selective_erase(row, 0, self.cols),
+            _ => {}
+        }
+    }
 
-    fn put(&mut self, _byte: u8) {}
+    /// Handle DECSET/DECRST private modes (`ESC[?<n>h` / `ESC[?<n>l`)
+    fn set_private_mode(&mut self, mode: i64, enabled: bool) {
+        match mode {
+            1 => self.application_cursor = enabled,
+            1007 => self.alternate_scroll = enabled,
+            2004 => self.bracketed_paste = enabled,
+            // Alternate screen: 47 just switches, 1047 also clears the
+            // alternate screen on leaving it, 1049 saves/restores the cursor
+            // and clears the alternate screen on entering it
+            47 => self.switch_screen(enabled),
+            1047 => {
+                if !enabled && self.alt_screen {
+                    self.clear_alt_screen();
+                }
+                self.switch_screen(enabled);
+            }
+            1049 => {
+                if enabled {
+                    if !self.alt_screen {
+                        self.save_cursor();
+                    }
+                    self.switch_screen(true);
+                    self.clear_alt_screen();
+                } else {
+                    self.switch_screen(false);
+                    self.restore_cursor();
+                }
+            }
+            25 => self.cursor_visible = enabled,
+            // Start / stop blinking the cursor (att610)
+            12 => self.cursor_blink_mode = Some(enabled),
+            45 => self.reverse_wrap = enabled,
+            69 => {
+                // DECLRMM - leaving it drops the margins
+                self.lr_margin_mode = enabled;
+                if !enabled {
+                    self.left_margin = 0;
+                    self.right_margin = self.cols - 1;
+                }
+            }
+            1034 => self.meta_sends_escape = enabled,
+            6 => {
+                // DECOM - positions become relative to the scroll region
+                self.origin_mode = enabled;
+                self.goto_origin(0, 0);
+            }
+            // DECAWM
+            7 => self.set_autowrap(enabled),
+            5 => {
+                // DECSCNM - reverse video; every row renders differently
+                self.reverse_video = enabled;
+                self.grid.mark_all_dirty();
+            }
+            _ => {
+                // Unsupported private mode
+                let action = if enabled { 'h' } else { 'l' };
+                self.note_unhandled(SeqKind::Csi, b"?", &[mode], Some(action));
+            }
+        }
+    }
+
+    /// Show the alternate (`true`) or primary screen. The cursor stays where
+    /// it is; the screen being hidden keeps its contents.
+    fn switch_screen(&mut self, alt: bool) {
+        if alt == self.alt_screen {
+            return;
+        }
+        std::mem::swap(&mut self.grid, &mut self.inactive_grid);
+        std::mem::swap(&mut self.kitty_keyboard, &mut self.inactive_kitty_keyboard);
+        self.alt_screen = alt;
+        self.pending_wrap = false;
+        self.grid.mark_all_dirty();
+    }
 
-    fn unhook(&mut self) {}
+    /// Blank the alternate screen, whether or not it is showing
+    fn clear_alt_screen(&mut self) {
+        let alt = if self.alt_screen {
+            &mut self.grid
+        } else {
+            &mut self.inactive_grid
+        };
+        alt.clear();
+    }
 
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {
-        // Handle OSC sequences (window title, etc.)
+    /// Set the cell size in pixels and pass the resulting pixel dimensions
+    /// on to the PTY
+    pub fn set_cell_pixel_size(&mut self, width: u16, height: u16) {
+        self.cell_pixel_size = (width, height);
+        self.resize_pty();
     }
 
-    fn csi_dispatch(&mut self, params: &Params, _intermediates: &[u8], _ignore: bool, c: char) {
-        let params = params_to_vec(params);
+    /// Push the current size to the PTY, if there is one
+    fn resize_pty(&self) {
+        let Some(ref pty) = self.pty else {
+            return;
+        };
+        let (cols, rows) = (self.cols as u16, self.rows as u16);
+        let (width, height) = self.cell_pixel_size;
+        let _ = pty.resize_with_pixels(
+            cols,
+            rows,
+            cols.saturating_mul(width),
+            rows.saturating_mul(height),
+        );
+    }
 
-        match c {
-            'A' => {
-                // Cursor Up
-                let n = params.get(0).copied().unwrap_or(1).max(1) as usize;
-                self.cursor.row = self.cursor.row.saturating_sub(n);
+    /// Resize the terminal; a size of 0 in either dimension is raised to 1
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let (rows, cols) = (rows.max(1), cols.max(1));
+        let old_rows = self.rows;
+        self.rows = rows;
+        self.cols = cols;
+        // Prompt marks belong to the primary screen and follow its reflow
+        let mut marks: Vec<(usize, usize)> = self
+            .prompt_marks
+            .iter()
+            .map(|mark| (mark.line, mark.col))
+            .collect();
+        let (primary_marks, alt_marks) = if self.alt_screen {
+            (&mut [][..], &mut marks[..])
+        } else {
+            (&mut marks[..], &mut [][..])
+        };
+        let (cursor_row, cursor_col) = self.grid.resize_tracking(
+            rows,
+            cols,
+            (self.cursor.row, self.cursor.col),
+            primary_marks,
+        );
+        // On the alternate screen the saved cursor (1049) belongs to the hidden
+        // primary screen and follows its reflow; otherwise just keep it in bounds
+        let saved = self.saved_cursor.as_mut().map(|s| &mut s.cursor);
+        if self.alt_screen
+            && let Some(saved) = saved
+        {
+            (saved.row, saved.col) =
+                self.inactive_grid
+                    .resize_tracking(rows, cols, (saved.row, saved.col), alt_marks);
+        } else {
+            self.inactive_grid
+                .resize_tracking(rows, cols, (0, 0), alt_marks);
+            if let Some(saved) = saved {
+                saved.row = saved.row.min(rows - 1);
+                saved.col = saved.col.min(cols - 1);
             }
-            'B' => {
-                // Cursor Down
-                let n = params.get(0).copied().unwrap_or(1).max(1) as usize;
-                self.cursor.row = (self.cursor.row + n).min(self.rows - 1);
+        }
+        for (mark, (line, col)) in self.prompt_marks.iter_mut().zip(marks) {
+            (mark.line, mark.col) = (line, col);
+        }
+
+        // Keep existing stops; new columns get the default spacing unless
+        // all stops were cleared
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.truncate(cols);
+        for col in old_cols..cols {
+            self.tab_stops
+                .push(!self.tab_stops_cleared && col % self.tab_width == 0);
+        }
+
+        self.resize_pty();
+
+        // A full-screen region stays full-screen; a partial one is clipped to
+        // the new height, or reset to full-screen if nothing of it is left
+        let full_screen = self.scroll_top == 0 && self.scroll_bottom + 1 == old_rows;
+        if full_screen || self.scroll_top >= rows {
+            self.scroll_top = 0;
+            self.scroll_bottom = rows - 1;
+        } else {
+            self.scroll_bottom = self.scroll_bottom.min(rows - 1);
+        }
+        // Likewise for the left/right margins
+        let full_width = self.left_margin == 0 && self.right_margin + 1 == old_cols;
+        if full_width || self.left_margin + 1 >= cols {
+            self.left_margin = 0;
+            self.right_margin = cols - 1;
+        } else {
+            self.right_margin = self.right_margin.min(cols - 1);
+        }
+
+        // Follow the cursor's line and keep the cursor in bounds
+        self.pending_wrap = false;
+        self.cursor.row = cursor_row;
+        self.cursor.col = cursor_col;
+    }
+
+    /// Store a cell directly, bypassing the parser (tests, session replay).
+    /// Out-of-range positions are ignored.
+    pub fn set_cell(&mut self, row: usize, col: usize, cell: Cell) {
+        if let Some(target) = self.grid.get_cell_mut(row, col) {
+            *target = cell;
+        }
+    }
+
+    /// Install prepared rows as the screen with the cursor at `cursor`, e.g.
+    /// to restore a session or show a splash without replaying output.
+    /// Rows of another size are fitted like a resize: soft-wrapped lines
+    /// reflow and rows that don't fit go to scrollback. Returns false, and
+    /// changes nothing, when `rows` is empty, the rows differ in width, or
+    /// the cursor is outside them.
+    pub fn load_screen(&mut self, rows: Vec<Row>, cursor: (usize, usize)) -> bool {
+        let Some(width) = rows.first().map(|row| row.cells.len()) else {
+            return false;
+        };
+        if width == 0
+            || rows.iter().any(|row| row.cells.len() != width)
+            || cursor.0 >= rows.len()
+            || cursor.1 >= width
+        {
+            return false;
+        }
+
+        (self.cursor.row, self.cursor.col) = self.grid.load_rows(rows, cursor);
+        self.pending_wrap = false;
+        true
+    }
+
+    /// Reset the cursor's row to blank default-style cells, marked dirty
+    /// across its full width so hosts can repaint it without diffing.
+    /// Unlike EL 2 this ignores the current background (no BCE).
+    pub fn blank_current_row(&mut self) {
+        self.pending_wrap = false;
+        self.grid.blank_row(self.cursor.row);
+    }
+
+    /// Ctrl-U style line kill: blank the cursor's row as EL 2 does (current
+    /// background, soft-wrap flag cleared, dirty across its full width) and
+    /// return the cursor to column 0
+    pub fn kill_line(&mut self) {
+        self.pending_wrap = false;
+        let blank = self.erase_cell();
+        self.grid.fill_row(self.cursor.row, &blank);
+        self.cursor.col = 0;
+    }
+
+    /// Write `text` directly into the grid starting at `row`/`col` in the
+    /// cursor's style, bypassing the parser: control characters are stored
+    /// as-is, there is no wrapping (text past the last column is dropped)
+    /// and the cursor doesn't move
+    pub fn put_str(&mut self, row: usize, col: usize, text: &str) {
+        for (col, c) in (col..self.cols).zip(text.chars()) {
+            let mut cell = Cell::new(c);
+            cell.fg = self.cursor.fg;
+            cell.bg = self.cursor.bg;
+            cell.flags = self.cursor.flags;
+            self.set_cell(row, col, cell);
+        }
+    }
+
+    /// Handle OSC 133 semantic prompt marks. Marks are only kept for the
+    /// primary screen, where they can be navigated in scrollback.
+    fn prompt_mark(&mut self, params: &[&[u8]]) {
+        let kind = match params.first().copied() {
+            Some(b"A") => PromptMarkKind::PromptStart,
+            Some(b"B") => PromptMarkKind::CommandStart,
+            Some(b"C") => PromptMarkKind::OutputStart,
+            Some(b"D") => PromptMarkKind::CommandEnd,
+            _ => {
+                self.note_unhandled(SeqKind::Osc, &[], &[133], None);
+                return;
             }
-            'C' => {
-                // Cursor Forward
-                let n = params.get(0).copied().unwrap_or(1).max(1) as usize;
-                self.cursor.col = (self.cursor.col + n).min(self.cols - 1);
+        };
+        if self.alt_screen {
+            return;
+        }
+
+        let exit_code = match kind {
+            PromptMarkKind::CommandEnd => params
+                .get(1)
+                .and_then(|p| std::str::from_utf8(p).ok())
+                .and_then(|p| p.parse().ok()),
+            _ => None,
+        };
+        let oldest = self.grid.oldest_line();
+        self.prompt_marks.retain(|mark| mark.line >= oldest);
+        self.prompt_marks.push(PromptMark {
+            kind,
+            line: self.grid.screen_line(self.cursor.row),
+            col: self.cursor.col,
+            exit_code,
+        });
+    }
+
+    /// Text of absolute lines `start_line..end_line`, soft-wrapped rows joined
+    /// and lines separated by `\n` (no trailing newline). Lines already
+    /// dropped from scrollback are skipped, so this returns what's left; e.g.
+    /// the output of the last command, between its OSC 133 `C` and `D` marks.
+    pub fn text_between_lines(&self, start_line: usize, end_line: usize) -> String {
+        let mut text = self.export_text_range(start_line, end_line);
+        text.pop();
+        text
+    }
+
+    /// Line of the last prompt starting before `line`, for "jump to previous
+    /// prompt"
+    pub fn previous_prompt(&self, line: usize) -> Option<usize> {
+        self.prompt_marks
+            .iter()
+            .rev()
+            .find(|mark| mark.kind == PromptMarkKind::PromptStart && mark.line < line)
+            .map(|mark| mark.line)
+    }
+
+    /// OSC 133 marks, oldest first. Marks on lines dropped from scrollback are
+    /// only pruned when the next mark arrives, so check them against
+    /// `Grid::oldest_line` (or let `scroll_to_prompt` skip them).
+    pub fn prompt_marks(&self) -> &[PromptMark] {
+        &self.prompt_marks
+    }
+
+    /// Scroll the view so the line of prompt mark `index` (into
+    /// `prompt_marks`) is at the top, or as close as the live screen allows
+    /// for marks on screen. Returns false, leaving the view alone, for an
+    /// invalid index or a mark whose line has left the scrollback.
+    pub fn scroll_to_prompt(&mut self, index: usize) -> bool {
+        let Some(mark) = self.prompt_marks.get(index) else {
+            return false;
+        };
+        if mark.line < self.grid.oldest_line() {
+            return false;
+        }
+        let offset = self.grid.screen_line(0).saturating_sub(mark.line);
+        self.grid
+            .scroll_view(offset as isize - self.grid.view_offset as isize);
+        true
+    }
+
+    /// Drop the scrollback (a "clear scrollback" menu item), leaving the
+    /// screen and cursor untouched
+    pub fn clear_scrollback(&mut self) {
+        self.grid.clear_scrollback();
+    }
+
+    /// Get the current grid state
+    pub fn get_grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    /// An owned copy of the rows currently on screen (honoring the scrollback
+    /// view), for rendering on another thread without holding the terminal
+    pub fn visible_snapshot(&self) -> Vec<Row> {
+        (0..self.grid.rows.len())
+            .filter_map(|row| self.grid.visible_row(row))
+            .cloned()
+            .collect()
+    }
+
+    /// Export scrollback and screen as plain text, one logical line per output
+    /// line. With `trim_blank_lines`, runs of blank lines collapse to one and
+    /// leading and trailing blank lines are dropped; otherwise every line is kept.
+    pub fn export_text(&self, trim_blank_lines: bool) -> String {
+        let text = self.export_text_range(self.grid.oldest_line(), self.grid.newest_line() + 1);
+        if !trim_blank_lines {
+            return text;
+        }
+
+        let mut trimmed = String::with_capacity(text.len());
+        let mut blank_run = false;
+        for line in text.lines() {
+            if line.is_empty() {
+                blank_run = !trimmed.is_empty();
+                continue;
             }
-            'D' => {
-                // Cursor Backward
-                let n = params.get(0).copied().unwrap_or(1).max(1) as usize;
-                self.cursor.col = self.cursor.col.saturating_sub(n);
+            if blank_run {
+                trimmed.push('\n');
+                blank_run = false;
             }
-            'H' | 'f' => {
-                // Cursor Position
-                let row = params.get(0).copied().unwrap_or(1).max(1) as usize - 1;
-                let col = params.get(1).copied().unwrap_or(1).max(1) as usize - 1;
-                self.cursor.row = row.min(self.rows - 1);
-                self.cursor.col = col.min(self.cols - 1);
+            trimmed.push_str(line);
+            trimmed.push('\n');
+        }
+        trimmed
+    }
+
+    /// Export lines `start_line..end_line` (absolute line numbers, as in search)
+    /// as plain text. Soft-wrapped rows are joined and trailing blanks trimmed.
+    pub fn export_text_range(&self, start_line: usize, end_line: usize) -> String {
+        let mut text = String::new();
+        let mut line = String::new();
+        let start_line = start_line.max(self.grid.oldest_line());
+        let rows = (start_line..end_line).map_while(|n| Some((n, self.grid.absolute_line(n)?)));
+
+        let mut any = false;
+        for (idx, row) in rows {
+            if idx > start_line && !row.wrapped {
+                text.push_str(line.trim_end());
+                text.push('\n');
+                line.clear();
             }
-            'J' => {
-                // Erase in Display
-                let mode = params.get(0).copied().unwrap_or(0);
-                match mode {
-                    0 => {
-                        // Clear from cursor to end
-                        self.grid.clear_to_end(self.cursor.row, self.cursor.col);
-                    }
-                    1 => {
-                        // Clear from start to cursor
-                        self.grid.clear_from_start(self.cursor.row, self.cursor.col);
-                    }
-                    2 | 3 => {
-                        // Clear entire screen
-                        self.grid.clear();
-                    }
-                    _ => {}
-                }
+            line.push_str(&row.text());
+            any = true;
+        }
+        if any {
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Install a handler that sees every CSI/OSC/ESC sequence before the
+    /// default handling and may consume it. Replaces any previous one.
+    pub fn set_sequence_interceptor(&mut self, handler: Box<dyn SequenceHandler>) {
+        self.interceptor = Some(handler);
+    }
+
+    /// Remove the sequence interceptor, returning it
+    pub fn take_sequence_interceptor(&mut self) -> Option<Box<dyn SequenceHandler>> {
+        self.interceptor.take()
+    }
+
+    /// Offer a sequence to the interceptor. It is taken out for the call so
+    /// it can use the terminal; sequences it feeds back aren't intercepted.
+    fn intercept(
+        &mut self,
+        offer: impl FnOnce(&mut dyn SequenceHandler, &mut Terminal) -> bool,
+    ) -> bool {
+        let Some(mut handler) = self.interceptor.take() else {
+            return false;
+        };
+        let consumed = offer(handler.as_mut(), self);
+        // Keep a replacement installed from inside the handler
+        if self.interceptor.is_none() {
+            self.interceptor = Some(handler);
+        }
+        consumed
+    }
+
+    /// Read all PTY output that is currently available and process it in one
+    /// call, returning the number of bytes processed (0 without a PTY)
+    pub fn pump(&mut self) -> std::io::Result<usize> {
+        let data = match self.pty {
+            Some(ref pty) => pty.read_all(PUMP_LIMIT)?,
+            None => return Ok(0),
+        };
+        self.process_bytes(&data);
+        self.flush_responses()?;
+        Ok(data.len())
+    }
+
+    /// Write queued query replies to the PTY; without a PTY they stay queued
+    /// for the host to collect with `take_responses`
+    pub fn flush_responses(&mut self) -> std::io::Result<()> {
+        if let Some(ref pty) = self.pty
+            && !self.responses.is_empty()
+        {
+            pty.write(&self.responses)?;
+            self.responses.clear();
+        }
+        Ok(())
+    }
+
+    /// Return and clear the queued query replies
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// The style new text is written with: colors and SGR attributes
+    pub fn current_sgr(&self) -> SgrState {
+        let flags = CellFlags(self.cursor.flags.0 & !CellFlags::PROTECTED);
+        SgrState {
+            fg: self.cursor.fg,
+            bg: self.cursor.bg,
+            flags,
+        }
+    }
+
+    /// DSR - answer a status report (5: "OK") or cursor position report (6,
+    /// 1-based and relative to the scroll region in origin mode). The DEC
+    /// form (`CSI ? 6 n`) answers `CSI ? row ; col R`.
+    fn report_status(&mut self, query: i64, private: bool) {
+        let reply = match query {
+            5 if !private => String::from("\x1b[0n"),
+            6 => {
+                let top = if self.origin_mode { self.scroll_top } else { 0 };
+                let row = self.cursor.row.saturating_sub(top) + 1;
+                let prefix = if private { "?" } else { "" };
+                format!("\x1b[{}{};{}R", prefix, row, self.cursor.col + 1)
             }
-            'K' => {
-                // Erase in Line
-                let mode = params.get(0).copied().unwrap_or(0);
-                if let Some(row) = self.grid.rows.get_mut(self.cursor.row) {
-                    match mode {
-                        0 => {
-                            // Clear from cursor to end of line
-                            for col in self.cursor.col..self.cols {
-                                if let Some(cell) = row.cells.get_mut(col) {
-                                    cell.reset();
-                                }
-                            }
-                        }
-                        1 => {
-                            // Clear from start of line to cursor
-                            for col in 0..=self.cursor.col {
-                                if let Some(cell) = row.cells.get_mut(col) {
-                                    cell.reset();
-                                }
-                            }
-                        }
-                        2 => {
-                            // Clear entire line
-                            row.clear();
-                        }
-                        _ => {}
-                    }
-                    row.dirty = true;
-                }
+            _ => {
+                let intermediates: &[u8] = if private { b"?" } else { b"" };
+                self.note_unhandled(SeqKind::Csi, intermediates, &[query], Some('n'));
+                return;
             }
-            'm' => {
-                // SGR - Select Graphic Rendition
-                self.handle_sgr(&params);
+        };
+        self.responses.extend_from_slice(reply.as_bytes());
+    }
+
+    /// Answer a DECRQM query with `CSI [?] Ps ; Pm $ y`: Pm is 1 when the
+    /// mode is set, 2 when reset and 0 for modes we don't know
+    fn report_mode(&mut self, mode: i64, private: bool) {
+        let state = if private {
+            match mode {
+                1 => Some(self.application_cursor),
+                5 => Some(self.reverse_video),
+                6 => Some(self.origin_mode),
+                7 => Some(self.autowrap),
+                12 => Some(self.cursor_blinks()),
+                25 => Some(self.cursor_visible),
+                45 => Some(self.reverse_wrap),
+                47 | 1047 | 1049 => Some(self.alt_screen),
+                69 => Some(self.lr_margin_mode),
+                1007 => Some(self.alternate_scroll),
+                1034 => Some(self.meta_sends_escape),
+                2004 => Some(self.bracketed_paste),
+                _ => None,
+            }
+        } else {
+            match mode {
+                4 => Some(self.insert_mode),
+                _ => None,
+            }
+        };
+        let value = match state {
+            Some(true) => 1,
+            Some(false) => 2,
+            None => 0,
+        };
+        let prefix = if private { "?" } else { "" };
+        let reply = format!("\x1b[{}{};{}$y", prefix, mode, value);
+        self.responses.extend_from_slice(reply.as_bytes());
+    }
+
+    /// Answer a DECRQSS query with `DCS 1 $ r <setting> ST`, or `DCS 0 $ r ST`
+    /// for settings we don't report
+    fn reply_decrqss(&mut self, query: &[u8]) {
+        let setting = match query {
+            b"m" => Some(format!("{}m", self.current_sgr().params())),
+            b"r" => Some(format!(
+                "{};{}r",
+                self.scroll_top + 1,
+                self.scroll_bottom + 1
+            )),
+            _ => None,
+        };
+        let reply = match setting {
+            Some(setting) => format!("\x1bP1$r{}\x1b\\", setting),
+            None => String::from("\x1bP0$r\x1b\\"),
+        };
+        self.responses.extend_from_slice(reply.as_bytes());
+    }
+
+    /// Process bytes and summarize the resulting damage, saving separate
+    /// dirty-row, title and bell queries per frame
+    pub fn process_and_damage(&mut self, bytes: &[u8]) -> Damage {
+        let scrolled = self.grid.lines_scrolled;
+        let bells = self.bell_count;
+        let title = self.title.clone();
+
+        self.process_bytes(bytes);
+
+        Damage {
+            dirty_rows: self.grid.dirty_rows(),
+            scrolled_lines: (self.grid.lines_scrolled - scrolled) as usize,
+            title_changed: self.title != title,
+            bell: self.bell_count > bells,
+        }
+    }
+
+    /// How long since output last changed the screen, or `None` before it
+    /// has; hosts use this for "notify when the command finishes"
+    pub fn idle_since(&self) -> Option<Duration> {
+        self.last_output.map(|at| at.elapsed())
+    }
+
+    /// Return and clear the pending notifications
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+
+    /// Queue a notification, dropping the oldest beyond `MAX_NOTIFICATIONS`
+    fn notify(&mut self, title: String, body: String) {
+        if self.notifications.len() >= MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+        self.notifications.push(Notification { title, body });
+    }
+
+    /// Return and reset the number of oversized OSC strings dropped since the
+    /// last call
+    pub fn take_truncated_osc_count(&mut self) -> usize {
+        std::mem::take(&mut self.truncated_osc_count)
+    }
+
+    /// Return and reset the number of bells rung since the last call
+    pub fn take_bell_count(&mut self) -> usize {
+        std::mem::take(&mut self.bell_count)
+    }
+
+    /// Remember a sequence that hit a no-op arm, if recording is enabled
+    fn note_unhandled(
+        &mut self,
+        kind: SeqKind,
+        intermediates: &[u8],
+        params: &[i64],
+        action: Option<char>,
+    ) {
+        if !self.record_unhandled {
+            return;
+        }
+        if self.unhandled.len() >= MAX_UNHANDLED {
+            self.unhandled.remove(0);
+        }
+        self.unhandled.push(UnhandledSeq {
+            kind,
+            intermediates: intermediates.to_vec(),
+            params: params.to_vec(),
+            action,
+        });
+    }
+
+    /// The most recent OSC strings with numbers the terminal doesn't know, as
+    /// (number, payload), oldest first, for app-specific protocols and
+    /// debugging. Kept whether or not `record_unhandled` is set; strings an
+    /// interceptor consumed never get here.
+    pub fn recent_osc(&self) -> &[(u32, Vec<u8>)] {
+        &self.recent_osc
+    }
+
+    /// Keep an unrecognized OSC string, dropping the oldest beyond
+    /// `MAX_RECENT_OSC`
+    fn note_osc(&mut self, command: u32, params: &[&[u8]]) {
+        if self.recent_osc.len() >= MAX_RECENT_OSC {
+            self.recent_osc.remove(0);
+        }
+        self.recent_osc.push((command, params.join(&b';')));
+    }
+
+    /// Return and clear the recorded unhandled sequences
+    pub fn take_unhandled(&mut self) -> Vec<UnhandledSeq> {
+        std::mem::take(&mut self.unhandled)
+    }
+
+    /// Encode a key press for the current input modes
+    pub fn encode_key(&self, key: Key, mods: Modifiers) -> Vec<u8> {
+        encode_key(key, mods, &self.key_modes())
+    }
+
+    fn key_modes(&self) -> KeyModes {
+        KeyModes {
+            application_cursor: self.application_cursor,
+            application_keypad: self.application_keypad,
+            meta_eight_bit: !self.meta_sends_escape,
+            kitty_flags: self.kitty_keyboard_flags(),
+        }
+    }
+
+    /// Kitty keyboard protocol flags in effect (0: legacy key encoding)
+    pub fn kitty_keyboard_flags(&self) -> u8 {
+        self.kitty_keyboard.last().copied().unwrap_or(0)
+    }
+
+    /// Kitty keyboard protocol: `CSI > flags u` pushes, `CSI < n u` pops,
+    /// `CSI = flags ; mode u` changes the top entry (1: set, 2: add,
+    /// 3: remove) and `CSI ? u` reports it
+    fn handle_kitty_keyboard(&mut self, intermediates: &[u8], params: &[i64]) {
+        let flags = params.first().copied().unwrap_or(0).clamp(0, 0x1f) as u8;
+        match intermediates {
+            b">" => {
+                if self.kitty_keyboard.len() == MAX_KITTY_KEYBOARD_STACK {
+                    self.kitty_keyboard.remove(0);
+                }
+                self.kitty_keyboard.push(flags);
             }
-            's' => {
-                // Save cursor position
-                self.saved_cursor = Some(self.cursor.clone());
+            b"<" => {
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let keep = self.kitty_keyboard.len().saturating_sub(n);
+                self.kitty_keyboard.truncate(keep);
             }
-            'u' => {
-                // Restore cursor position
-                if let Some(saved) = &self.saved_cursor {
-                    self.cursor = saved.clone();
+            b"=" => {
+                let current = self.kitty_keyboard_flags();
+                let flags = match params.get(1).copied().unwrap_or(1) {
+                    1 => flags,
+                    2 => current | flags,
+                    3 => current & !flags,
+                    _ => {
+                        self.note_unhandled(SeqKind::Csi, intermediates, params, Some('u'));
+                        return;
+                    }
+                };
+                match self.kitty_keyboard.last_mut() {
+                    Some(top) => *top = flags,
+                    None => self.kitty_keyboard.push(flags),
                 }
             }
             _ => {
-                // Unhandled CSI sequence
+                let reply = format!("\x1b[?{}u", self.kitty_keyboard_flags());
+                self.responses.extend_from_slice(reply.as_bytes());
             }
         }
     }
 
-    fn esc_dispatch(&mut self, _intermediates: &[u8], _ignore: bool, _byte: u8) {
-        // Handle ESC sequences
+    /// Encode wheel notches (positive: up) as arrow keys, `scroll_multiplier`
+    /// per notch, or as Page Up/Down when Shift is held. At most a screenful
+    /// of keys is sent.
+    pub fn wheel_to_keys(&self, notches: i32, mods: Modifiers) -> Vec<u8> {
+        let page = mods.contains(Modifiers::SHIFT);
+        let modes = self.key_modes();
+        encode_wheel(notches, self.scroll_multiplier, page, self.rows, &modes)
+    }
+
+    /// Arrow-key presses that move a line editor's cursor from the terminal
+    /// cursor to a clicked cell. A shell edits one logical line, so the target
+    /// is clamped to the (possibly wrapped) line holding the cursor and the
+    /// motion is sent as Left/Right only; Up/Down would walk history. The
+    /// editor moves a character per press, so a wide character counts once.
+    pub fn cursor_move_sequence(&self, target_row: usize, target_col: usize) -> Vec<u8> {
+        let rows = &self.grid.rows;
+        let row = self.cursor.row.min(rows.len().saturating_sub(1));
+        let mut first = row;
+        while first > 0 && rows[first].wrapped {
+            first -= 1;
+        }
+        let mut last = row;
+        while last + 1 < rows.len() && rows[last + 1].wrapped {
+            last += 1;
+        }
+
+        // Characters before `col` on `row`, counted from the start of the line
+        let offset = |row: usize, col: usize| {
+            let before: usize = rows[first..row]
+                .iter()
+                .flat_map(|r| &r.cells)
+                .filter(|cell| {
+                    !cell.flags.contains(CellFlags::WIDE_SPACER)
+                        && !cell.flags.contains(CellFlags::WRAP_PAD)
+                })
+                .count();
+            before + rows[row].char_at_visual_col(col.min(self.cols - 1))
+        };
+        let from = offset(row, self.cursor.col);
+        let to = match target_row {
+            r if r < first => 0,
+            r if r > last => offset(last, self.cols - 1),
+            r => offset(r, target_col),
+        };
+
+        let key = if to > from { Key::Right } else { Key::Left };
+        self.encode_key(key, Modifiers::default())
+            .repeat(from.abs_diff(to))
+    }
+
+    /// Make pasted text safe to send as typed input. Without bracketed paste
+    /// a pasted newline runs a command and an ESC starts a key sequence, so
+    /// line breaks become spaces and other control bytes (except tab) are
+    /// dropped. With bracketed paste the data passes through untouched.
+    /// Returns the bytes to send and whether anything was changed.
+    pub fn sanitize_paste(&self, data: &[u8]) -> (Vec<u8>, bool) {
+        if self.bracketed_paste {
+            return (data.to_vec(), false);
+        }
+
+        let mut out = Vec::with_capacity(data.len());
+        let mut changed = false;
+        let mut bytes = data.iter().peekable();
+        while let Some(&byte) = bytes.next() {
+            match byte {
+                b'\t' => out.push(byte),
+                b'\r' | b'\n' => {
+                    // CRLF is one line break
+                    if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+                        bytes.next();
+                    }
+                    out.push(b' ');
+                    changed = true;
+                }
+                0x00..=0x1f | 0x7f => changed = true,
+                _ => out.push(byte),
+            }
+        }
+        (out, changed)
+    }
+
+    /// Send input to the PTY
+    pub fn send_input(&self, data: &[u8]) -> std::io::Result<()> {
+        if let Some(ref pty) = self.pty {
+            pty.write(data)?;
+        }
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Chainable configuration for a `Terminal`, applied in one place instead of
+/// a series of setters after construction
+#[derive(Debug, Clone)]
+pub struct TerminalBuilder {
+    rows: usize,
+    cols: usize,
+    scrollback: usize,
+    compress_scrollback: bool,
+    palette: Palette,
+    tab_width: usize,
+    scroll_multiplier: u16,
+    wide_char_wrap: WideCharWrap,
+    default_colors: Option<(Rgb, Rgb)>,
+    answerback: String,
+    shell: Option<String>,
+}
 
-    #[test]
-    fn test_terminal_creation() {
-        let term = Terminal::new(24, 80);
-        assert_eq!(term.rows, 24);
-        assert_eq!(term.cols, 80);
+impl Default for TerminalBuilder {
+    fn default() -> Self {
+        Self {
+            rows: 24,
+            cols: 80,
+            scrollback: 10000,
+            compress_scrollback: false,
+            palette: Palette::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            scroll_multiplier: DEFAULT_SCROLL_MULTIPLIER,
+            wide_char_wrap: WideCharWrap::Wrap,
+            default_colors: None,
+            answerback: String::new(),
+            shell: None,
+        }
     }
+}
 
-    #[test]
-    fn test_write_text() {
-        let mut term = Terminal::new(24, 80);
-        term.process_bytes(b"Hello");
+impl TerminalBuilder {
+    pub fn rows(mut self, rows: usize) -> Self {
+        self.rows = rows;
+        self
+    }
 
-        assert_eq!(term.grid.get_cell(0, 0).unwrap().c, 'H');
-        assert_eq!(term.grid.get_cell(0, 4).unwrap().c, 'o');
+    pub fn cols(mut self, cols: usize) -> Self {
+        self.cols = cols;
+        self
     }
 
-    #[test]
-    fn test_color_codes() {
-        let mut term = Terminal::new(24, 80);
-        // Red text
-        term.process_bytes(b"\x1b[31mRed\x1b[0m");
+    /// Maximum number of scrollback lines kept
+    pub fn scrollback(mut self, lines: usize) -> Self {
+        self.scrollback = lines;
+        self
+    }
 
-        assert_eq!(term.grid.get_cell(0, 0).unwrap().c, 'R');
-        assert_eq!(
-            term.grid.get_cell(0, 0).unwrap().fg,
-            Color::Named(NamedColor::Red)
-        );
+    /// Share storage between identical and blank scrollback rows; see
+    /// `Grid::compress_scrollback`
+    pub fn compress_scrollback(mut self, enabled: bool) -> Self {
+        self.compress_scrollback = enabled;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = width;
+        self
+    }
+
+    /// Lines per wheel notch for `wheel_to_keys`
+    pub fn scroll_multiplier(mut self, lines: u16) -> Self {
+        self.scroll_multiplier = lines;
+        self
+    }
+
+    /// Whether a wide character printed in the last column wraps or is dropped
+    pub fn wide_char_wrap(mut self, wrap: WideCharWrap) -> Self {
+        self.wide_char_wrap = wrap;
+        self
+    }
+
+    /// Default foreground and background, replacing the palette's
+    pub fn default_colors(mut self, fg: Rgb, bg: Rgb) -> Self {
+        self.default_colors = Some((fg, bg));
+        self
+    }
+
+    /// Reply to ENQ (empty: ENQ is ignored)
+    pub fn answerback(mut self, answerback: impl Into<String>) -> Self {
+        self.answerback = answerback.into();
+        self
+    }
+
+    /// Shell spawned by `build_with_pty` (defaults to `$SHELL`)
+    pub fn shell(mut self, shell: impl Into<String>) -> Self {
+        self.shell = Some(shell.into());
+        self
+    }
+
+    /// Build a terminal without a PTY
+    pub fn build(self) -> Terminal {
+        let mut terminal = Terminal::new(self.rows, self.cols);
+        terminal.grid.max_scrollback = self.scrollback;
+        terminal.grid.compress_scrollback = self.compress_scrollback;
+        terminal.set_palette(self.palette);
+        terminal.set_tab_width(self.tab_width);
+        terminal.scroll_multiplier = self.scroll_multiplier;
+        terminal.wide_char_wrap = self.wide_char_wrap;
+        terminal.answerback = self.answerback;
+        if let Some((fg, bg)) = self.default_colors {
+            terminal.set_default_colors(fg, bg);
+        }
+        terminal
+    }
+
+    /// Build a terminal and spawn the configured shell on a new PTY
+    pub fn build_with_pty(self) -> std::io::Result<Terminal> {
+        let mut pty = Pty::new(self.cols as u16, self.rows as u16)?;
+        pty.spawn_shell(self.shell.as_deref())?;
+
+        let mut terminal = self.build();
+        terminal.pty = Some(pty);
+        Ok(terminal)
+    }
+}
+
+impl Perform for Terminal {
+    fn print(&mut self, c: char) {
+        // vte's ground state passes DEL through as printable; like xterm,
+        // treat it as padding, ignored like the other controls without an action
+        if c == '\x7f' {
+            self.execute(0x7f);
+            return;
+        }
+        // C1 controls reaching here weren't translated (`c1_as_control` off)
+        if ('\u{80}'..='\u{9f}').contains(&c) {
+            return;
+        }
+        let c = self.charsets[self.active_charset].map(c);
+        self.print_normalized(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // LF, VT and FF all act as line feeds
+            b'\n' | 0x0b | 0x0c => self.newline(),
+            b'\r' => self.carriage_return(),
+            b'\t' => self.tab(),
+            0x08 => self.backspace(),
+            // A BEL terminating an OSC string is consumed by the parser and
+            // never reaches here, so only standalone bells are counted
+            0x07 => self.bell_count += 1,
+            // ENQ - reply with the answerback message, if one is configured
+            0x05 if !self.answerback.is_empty() => {
+                self.responses.extend_from_slice(self.answerback.as_bytes());
+            }
+            // SO / SI - invoke G1 / G0
+            0x0e => self.active_charset = 1,
+            0x0f => self.active_charset = 0,
+            // Anything else (and DEL, which vte hands to `print`) is ignored
+            // unless it should be shown
+            _ if self.print_control_chars => self.write_char(control_picture(byte)),
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
+        self.dcs_buffer.clear();
+        self.dcs = match (intermediates, c) {
+            ([], 'q') => Some(Dcs::Sixel),
+            ([b'$'], 'q') => Some(Dcs::Decrqss),
+            _ => None,
+        };
+        if self.dcs.is_none() {
+            let params = params_to_vec(params);
+            self.note_unhandled(SeqKind::Dcs, intermediates, &params, Some(c));
+        }
+    }
+
+    fn put(&mut self, byte: u8) {
+        if self.dcs.is_some() && self.dcs_buffer.len() < MAX_DCS_PAYLOAD {
+            self.dcs_buffer.push(byte);
+        }
+    }
+
+    fn unhook(&mut self) {
+        match self.dcs.take() {
+            Some(Dcs::Sixel) => self.pending_dcs = std::mem::take(&mut self.dcs_buffer),
+            Some(Dcs::Decrqss) => {
+                let query = std::mem::take(&mut self.dcs_buffer);
+                self.reply_decrqss(&query);
+            }
+            None => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        // The parser cut this string short; applying the rest (a partial
+        // clipboard payload or title) would be worse than ignoring it
+        if params.iter().map(|param| param.len()).sum::<usize>() >= MAX_OSC_PAYLOAD {
+            self.truncated_osc_count += 1;
+            return;
+        }
+        if self.intercept(|handler, term| handler.osc(term, params)) {
+            return;
+        }
+
+        // Handle OSC sequences (window title, etc.)
+        let Some(command) = params
+            .first()
+            .and_then(|p| std::str::from_utf8(p).ok())
+            .and_then(|p| p.parse::<u32>().ok())
+        else {
+            self.note_unhandled(SeqKind::Osc, &[], &[], None);
+            return;
+        };
+
+        match command {
+            0 | 2 => {
+                // Set window title (OSC 0 also sets the icon name, which we don't track)
+                let title = params[1..].join(&b';');
+                self.title = String::from_utf8_lossy(&title).into_owned();
+            }
+            7 => {
+                // Current working directory as a file:// URL
+                let url = params[1..].join(&b';');
+                if let Some(path) = parse_file_url(&url) {
+                    self.cwd = Some(path);
+                }
+            }
+            9 => {
+                // iTerm2-style notification: the rest is the message
+                let body = params[1..].join(&b';');
+                self.notify(String::new(), String::from_utf8_lossy(&body).into_owned());
+            }
+            10..=12 => self.set_dynamic_colors(command, &params[1..], bell_terminated),
+            104 => self.reset_palette(&params[1..]),
+            // Reset the default foreground / background
+            110 => {
+                self.palette.foreground = self.base_palette.foreground;
+                self.grid.mark_all_dirty();
+            }
+            111 => {
+                self.palette.background = self.base_palette.background;
+                self.grid.mark_all_dirty();
+            }
+            // Reset the cursor color
+            112 => self.cursor_color = None,
+            133 => self.prompt_mark(&params[1..]),
+            1337 => {
+                // iTerm2 inline image; other 1337 subcommands are ignored
+                match parse_inline_image(&params[1..].join(&b';')) {
+                    Some(image) => self.pending_image = Some(image),
+                    None => self.note_unhandled(SeqKind::Osc, &[], &[1337], None),
+                }
+            }
+            777 if params.get(1) == Some(&&b"notify"[..]) => {
+                // rxvt-style notification: OSC 777 ; notify ; title ; body
+                let text = |p: Option<&&[u8]>| {
+                    String::from_utf8_lossy(p.copied().unwrap_or(b"")).into_owned()
+                };
+                let body = params.get(3..).map(|p| p.join(&b';')).unwrap_or_default();
+                let body = String::from_utf8_lossy(&body).into_owned();
+                self.notify(text(params.get(2)), body);
+            }
+            _ => {
+                // Unhandled OSC
+                self.note_osc(command, &params[1..]);
+                self.note_unhandled(SeqKind::Osc, &[], &[command as i64], None);
+            }
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
+        let params = params_to_vec(params);
+        if self.intercept(|handler, term| handler.csi(term, &params, intermediates, c)) {
+            return;
+        }
+
+        if intermediates == b"'" {
+            // DECIC / DECDC - Insert / Delete Columns
+            let n = params.first().copied().unwrap_or(1).max(1) as usize;
+            match c {
+                '}' => self.insert_or_delete_columns(n, true),
+                '~' => self.insert_or_delete_columns(n, false),
+                _ => self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c)),
+            }
+            return;
+        }
+
+        if intermediates == b"?" {
+            match c {
+                'h' | 'l' => {
+                    // DECSET / DECRST
+                    for &mode in &params {
+                        self.set_private_mode(mode, c == 'h');
+                    }
+                }
+                'J' => {
+                    // DECSED - Selective Erase in Display
+                    self.selective_erase_display(params.first().copied().unwrap_or(0));
+                }
+                'K' => {
+                    // DECSEL - Selective Erase in Line
+                    self.selective_erase_line(params.first().copied().unwrap_or(0));
+                }
+                'n' => self.report_status(params.first().copied().unwrap_or(0), true),
+                'u' => self.handle_kitty_keyboard(intermediates, &params),
+                _ => {
+                    // Unhandled private CSI sequence
+                    self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c));
+                }
+            }
+            return;
+        }
+
+        if intermediates == b" " {
+            let n = params.first().copied().unwrap_or(1).max(1) as usize;
+            match c {
+                // DECSCUSR - Set Cursor Style
+                'q' => self.set_cursor_style(params.first().copied().unwrap_or(0)),
+                // SL / SR - Scroll Left / Right within the margins
+                '@' => self.shift_columns(self.left_margin, n, false),
+                'A' => self.shift_columns(self.left_margin, n, true),
+                _ => self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c)),
+            }
+            return;
+        }
+
+        if intermediates == b"\"" {
+            match (c, params.first().copied().unwrap_or(0)) {
+                ('q', mode) => {
+                    // DECSCA - Select Character Protection Attribute
+                    self.cursor.flags.set(CellFlags::PROTECTED, mode == 1);
+                }
+                ('p', level @ 61..=65) => {
+                    // DECSCL - Set Conformance Level, which also soft-resets
+                    self.conformance_level = level as u8;
+                    self.soft_reset();
+                }
+                _ => self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c)),
+            }
+            return;
+        }
+
+        if matches!(intermediates, b">" | b"<" | b"=") && c == 'u' {
+            self.handle_kitty_keyboard(intermediates, &params);
+            return;
+        }
+
+        // The `p` final means different things depending on the intermediate
+        if matches!(intermediates, b"!" | b"$" | b"?$") {
+            match (intermediates, c) {
+                // DECSTR - Soft Terminal Reset
+                (b"!", 'p') => self.soft_reset(),
+                // DECRQM - Request Mode (ANSI / private)
+                (_, 'p') => {
+                    let private = intermediates[0] == b'?';
+                    for &mode in &params {
+                        self.report_mode(mode, private);
+                    }
+                }
+                _ => self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c)),
+            }
+            return;
+        }
+
+        match c {
+            'A' => {
+                // Cursor Up
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let in_region = self.in_scroll_region();
+                self.goto(self.cursor.row.saturating_sub(n), self.cursor.col);
+                // Stop at the top margin when starting inside the region
+                if in_region {
+                    self.clamp_to_region();
+                }
+            }
+            'B' => {
+                // Cursor Down
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                let in_region = self.in_scroll_region();
+                self.goto(self.cursor.row + n, self.cursor.col);
+                // Stop at the bottom margin when starting inside the region
+                if in_region {
+                    self.clamp_to_region();
+                }
+            }
+            'C' => {
+                // Cursor Forward
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                self.goto(self.cursor.row, self.cursor.col + n);
+            }
+            'D' => {
+                // Cursor Backward
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                self.goto(self.cursor.row, self.cursor.col.saturating_sub(n));
+            }
+            'H' | 'f' => {
+                // Cursor Position
+                let row = params.first().copied().unwrap_or(1).max(1) as usize - 1;
+                let col = params.get(1).copied().unwrap_or(1).max(1) as usize - 1;
+                self.goto_origin(row, col);
+            }
+            'J' => {
+                // Erase in Display
+                self.pending_wrap = false;
+                let mode = params.first().copied().unwrap_or(0);
+                let blank = self.erase_cell();
+                match mode {
+                    0 => {
+                        // Clear from cursor to end
+                        self.grid
+                            .clear_to_end(self.cursor.row, self.cursor.col, &blank);
+                    }
+                    1 => {
+                        // Clear from start to cursor
+                        self.grid
+                            .clear_from_start(self.cursor.row, self.cursor.col, &blank);
+                    }
+                    2 => {
+                        // Clear entire screen
+                        self.grid.clear_to_end(0, 0, &blank);
+                    }
+                    // Erase saved lines (xterm)
+                    3 => self.clear_scrollback(),
+                    _ => {}
+                }
+            }
+            'K' => {
+                // Erase in Line
+                self.pending_wrap = false;
+                let mode = params.first().copied().unwrap_or(0);
+                let (row, col) = (self.cursor.row, self.cursor.col);
+                let blank = self.erase_cell();
+                match mode {
+                    // Clear entire line, also when 0 or 1 covers all of it
+                    2 => self.grid.fill_row(row, &blank),
+                    0 if col == 0 => self.grid.fill_row(row, &blank),
+                    1 if col + 1 >= self.cols => self.grid.fill_row(row, &blank),
+                    0 => {
+                        // Clear from cursor to end of line
+                        self.grid.fill_cells(row, col, self.cols, &blank);
+                    }
+                    1 => {
+                        // Clear from start of line to cursor
+                        self.grid.fill_cells(row, 0, col + 1, &blank);
+                    }
+                    _ => {}
+                }
+            }
+            'L' | 'M' => {
+                // IL / DL - Insert / Delete Lines
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                self.insert_or_delete_lines(n, c == 'L');
+            }
+            'S' | 'T' => {
+                // SU / SD - Scroll Up / Down within the region
+                let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                for _ in 0..n.min(self.rows) {
+                    if c == 'S' {
+                        self.grid
+                            .scroll_region_up(self.scroll_top, self.scroll_bottom);
+                    } else {
+                        self.grid
+                            .scroll_region_down(self.scroll_top, self.scroll_bottom);
+                    }
+                }
+            }
+            'r' => {
+                // DECSTBM - Set Top and Bottom Margins
+                let top = params.first().copied().unwrap_or(1).max(0) as usize;
+                let bottom = params.get(1).copied().unwrap_or(0).max(0) as usize;
+                self.set_scroll_region(top, bottom);
+            }
+            'g' => {
+                // TBC - Tab Clear
+                match params.first().copied().unwrap_or(0) {
+                    0 => {
+                        if let Some(stop) = self.tab_stops.get_mut(self.cursor.col) {
+                            *stop = false;
+                        }
+                    }
+                    3 => {
+                        self.tab_stops.iter_mut().for_each(|stop| *stop = false);
+                        self.tab_stops_cleared = true;
+                    }
+                    _ => {}
+                }
+            }
+            'b' => {
+                // REP - Repeat the preceding graphic character
+                if let Some(c) = self.last_printed {
+                    let n = params.first().copied().unwrap_or(1).max(1) as usize;
+                    for _ in 0..n.min(self.rows * self.cols) {
+                        self.write_char(c);
+                    }
+                }
+            }
+            'm' => {
+                // SGR - Select Graphic Rendition
+                self.handle_sgr(&params);
+            }
+            // SM / RM - ANSI modes
+            'h' | 'l' => {
+                for &mode in &params {
+                    match mode {
+                        4 => self.insert_mode = c == 'h',
+                        _ => self.note_unhandled(SeqKind::Csi, &[], &[mode], Some(c)),
+                    }
+                }
+            }
+            't' => {
+                // XTWINOPS - only the title stack; the icon name (1) isn't tracked
+                let which = params.get(1).copied().unwrap_or(0);
+                match params.first().copied() {
+                    Some(22) if which != 1 => {
+                        if self.title_stack.len() == MAX_TITLE_STACK {
+                            self.title_stack.remove(0);
+                        }
+                        self.title_stack.push(self.title.clone());
+                    }
+                    Some(23) if which != 1 => {
+                        if let Some(title) = self.title_stack.pop() {
+                            self.title = title;
+                        }
+                    }
+                    Some(22 | 23) => {}
+                    _ => self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c)),
+                }
+            }
+            // DSR - Device Status Report
+            'n' => self.report_status(params.first().copied().unwrap_or(0), false),
+            // DECSLRM while DECLRMM is set
+            's' if self.lr_margin_mode => {
+                let left = params.first().copied().unwrap_or(1).max(0) as usize;
+                let right = params.get(1).copied().unwrap_or(0).max(0) as usize;
+                self.set_lr_margins(left, right);
+            }
+            // SCOSC / SCORC - same as DECSC / DECRC
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {
+                // Unhandled CSI sequence
+                self.note_unhandled(SeqKind::Csi, intermediates, &params, Some(c));
+            }
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if self.intercept(|handler, term| handler.esc(term, intermediates, byte)) {
+            return;
+        }
+
+        // Handle ESC sequences
+        match (intermediates, byte) {
+            ([], b'H') => {
+                // HTS - Horizontal Tab Set
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor.col) {
+                    *stop = true;
+                }
+            }
+            // DECSC / DECRC
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            // IND / NEL / RI
+            ([], b'D') => self.index(),
+            ([], b'E') => self.newline(),
+            ([], b'M') => self.reverse_index(),
+            // DECKPAM / DECKPNM - application / numeric keypad
+            ([], b'=') => self.application_keypad = true,
+            ([], b'>') => self.application_keypad = false,
+            // SCS - designate G0 / G1
+            ([b'('], designator) => self.charsets[0] = Charset::from_designator(designator),
+            ([b')'], designator) => self.charsets[1] = Charset::from_designator(designator),
+            _ => {
+                // Unhandled ESC sequence
+                self.note_unhandled(SeqKind::Esc, intermediates, &[], Some(byte as char));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::KeypadKey;
+
+    #[test]
+    fn test_terminal_creation() {
+        let term = Terminal::new(24, 80);
+        assert_eq!(term.rows, 24);
+        assert_eq!(term.cols, 80);
+    }
+
+    #[test]
+    fn test_write_text() {
+        let mut term = Terminal::new(24, 80);
+        term.process_bytes(b"Hello");
+
+        assert_eq!(term.grid.get_cell(0, 0).unwrap().c, 'H');
+        assert_eq!(term.grid.get_cell(0, 4).unwrap().c, 'o');
+    }
+
+    #[test]
+    fn test_color_codes() {
+        let mut term = Terminal::new(24, 80);
+        // Red text
+        term.process_bytes(b"\x1b[31mRed\x1b[0m");
+
+        assert_eq!(term.grid.get_cell(0, 0).unwrap().c, 'R');
+        assert_eq!(
+            term.grid.get_cell(0, 0).unwrap().fg,
+            Color::Named(NamedColor::Red)
+        );
+    }
+
+    #[test]
+    fn test_rapid_blink() {
+        let mut term = Terminal::new(24, 80);
+        term.process_bytes(b"\x1b[6mA\x1b[5mB\x1b[25mC");
+
+        let flags = |col| term.grid.get_cell(0, col).unwrap().flags;
+        assert!(flags(0).contains(CellFlags::RAPID_BLINK));
+        assert!(!flags(0).contains(CellFlags::BLINK));
+        assert!(flags(1).contains(CellFlags::RAPID_BLINK));
+        assert!(flags(1).contains(CellFlags::BLINK));
+        assert!(!flags(2).contains(CellFlags::RAPID_BLINK));
+        assert!(!flags(2).contains(CellFlags::BLINK));
+    }
+
+    #[test]
+    fn test_display_colors_bold_dim_inverse() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[1;31mA\x1b[22;2mB\x1b[0;1;7;38;5;1mC");
+        let mut palette = Palette::default();
+        let cell = |col| *term.grid.get_cell(0, col).unwrap();
+
+        // Bold red stays red unless bold_is_bright is on
+        let red = palette.ansi[NamedColor::Red as usize];
+        let bright_red = palette.ansi[NamedColor::BrightRed as usize];
+        assert_eq!(cell(0).display_colors(&palette).0, red);
+        palette.bold_is_bright = true;
+        assert_eq!(
+            cell(0).display_colors(&palette),
+            (bright_red, palette.background)
+        );
+
+        // SGR 22 drops bold; dim darkens the foreground
+        assert!(!cell(1).flags.is_bold());
+        assert_eq!(cell(1).display_colors(&palette).0, red.dimmed());
+
+        // Indexed colors aren't brightened; inverse swaps
+        assert_eq!(cell(2).display_colors(&palette), (palette.background, red));
+    }
+
+    #[test]
+    fn test_p_final_dispatches_on_intermediate() {
+        let mut term = Terminal::new(5, 10);
+        term.record_unhandled = true;
+        term.process_bytes(b"\x1b[2;4r\x1b[?6;25l\x1b[4h\x1b[1;31m\x1b7\x1b[3;5H");
+
+        // DECRQM, ANSI and private, including an unknown mode
+        term.process_bytes(b"\x1b[4$p\x1b[?6;25;7$p\x1b[?9999$p");
+        assert_eq!(
+            term.take_responses(),
+            b"\x1b[4;1$y\x1b[?6;2$y\x1b[?25;2$y\x1b[?7;1$y\x1b[?9999;0$y"
+        );
+
+        // DECSTR resets modes, margins and SGR, but not the cursor or screen
+        term.process_bytes(b"X\x1b[!p");
+        assert!(term.cursor_visible && !term.insert_mode);
+        assert_eq!((term.scroll_top, term.scroll_bottom), (0, 4));
+        assert_eq!(term.cursor.fg, Color::Named(NamedColor::Foreground));
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 5));
+        assert_eq!(row_text(&term, 2), "    X     ");
+        term.process_bytes(b"\x1b8");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 0));
+
+        // DECSCL records the level and soft-resets too
+        term.process_bytes(b"\x1b[?25l\x1b[62;1\"p");
+        assert_eq!(term.conformance_level, 62);
+        assert!(term.cursor_visible);
+        term.process_bytes(b"\x1b[70\"p");
+        assert_eq!(term.conformance_level, 62);
+
+        let unhandled: Vec<_> = term
+            .take_unhandled()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(unhandled, ["CSI \"70p"]);
+    }
+
+    #[test]
+    fn test_kitty_keyboard_flags_stack() {
+        let mut term = Terminal::new(3, 10);
+        let ctrl = Modifiers(Modifiers::CTRL);
+        assert_eq!(term.encode_key(Key::Char('c'), ctrl), b"\x03");
+
+        term.process_bytes(b"\x1b[>1u\x1b[?u");
+        assert_eq!(term.take_responses(), b"\x1b[?1u");
+        assert_eq!(term.encode_key(Key::Char('c'), ctrl), b"\x1b[99;5u");
+        assert_eq!(
+            term.encode_key(Key::Escape, Modifiers::default()),
+            b"\x1b[27u"
+        );
+
+        // Push more, adjust the top entry, then pop back
+        term.process_bytes(b"\x1b[>1u\x1b[=8;2u");
+        assert_eq!(term.kitty_keyboard, [1, 9]);
+        assert_eq!(
+            term.encode_key(Key::Char('a'), Modifiers::default()),
+            b"\x1b[97u"
+        );
+        term.process_bytes(b"\x1b[=1;3u\x1b[<u");
+        assert_eq!(term.kitty_keyboard, [1]);
+
+        // The alternate screen has its own stack
+        term.process_bytes(b"\x1b[?1049h");
+        assert_eq!(term.kitty_keyboard_flags(), 0);
+        term.process_bytes(b"\x1b[>8u\x1b[?1049l");
+        assert_eq!(term.kitty_keyboard_flags(), 1);
+
+        // Popping more than was pushed empties the stack
+        term.process_bytes(b"\x1b[<5u");
+        assert_eq!(term.kitty_keyboard_flags(), 0);
+        assert_eq!(term.encode_key(Key::Char('c'), ctrl), b"\x03");
+    }
+
+    #[test]
+    fn test_current_sgr_round_trips() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[1\"q\x1b[1;2;3;4;6;7;9;53;38;5;208;48;2;10;20;30m");
+        let state = term.current_sgr();
+        assert_eq!(state.fg, Color::Spec256(208));
+        assert_eq!(state.bg, Color::Spec(Rgb::new(10, 20, 30)));
+        assert!(state.flags.is_bold() && state.flags.contains(CellFlags::DIM));
+        assert!(!state.flags.contains(CellFlags::PROTECTED));
+
+        let mut other = Terminal::new(2, 10);
+        other.process_bytes(b"\x1b[5;32m");
+        other.process_bytes(state.to_ansi().as_bytes());
+        assert_eq!(other.current_sgr(), state);
+
+        term.process_bytes(b"\x1b[0m");
+        assert_eq!(term.current_sgr().to_ansi(), "\x1b[0m");
+    }
+
+    #[test]
+    fn test_sgr_default_color_resets() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[1;31;44m\x1b[39m");
+        assert_eq!(term.cursor.fg, Color::Named(NamedColor::Foreground));
+        assert_eq!(term.cursor.bg, Color::Named(NamedColor::Blue));
+        assert!(term.cursor.flags.contains(CellFlags::BOLD));
+
+        term.process_bytes(b"\x1b[4;49m");
+        assert_eq!(term.cursor.bg, Color::Named(NamedColor::Background));
+        assert!(term.cursor.flags.contains(CellFlags::BOLD));
+        assert!(term.cursor.flags.contains(CellFlags::UNDERLINE));
+    }
+
+    #[test]
+    fn test_reverse_video_mode() {
+        let mut term = Terminal::new(24, 80);
+        let cell = *term.grid.get_cell(0, 0).unwrap();
+        assert_eq!(
+            cell.resolved_colors(term.reverse_video),
+            (
+                Color::Named(NamedColor::Foreground),
+                Color::Named(NamedColor::Background)
+            )
+        );
+
+        term.process_bytes(b"\x1b[?5h");
+        assert!(term.reverse_video);
+        assert_eq!(
+            cell.resolved_colors(term.reverse_video),
+            (
+                Color::Named(NamedColor::Background),
+                Color::Named(NamedColor::Foreground)
+            )
+        );
+
+        term.process_bytes(b"\x1b[?5l");
+        assert!(!term.reverse_video);
+    }
+
+    #[test]
+    fn test_reverse_video_with_themed_defaults() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"\x1b]11;#102030\x1b\\a\x1b[31mb\x1b[0mc\x1b[?5h");
+        let (fg, bg) = (term.palette.foreground, Rgb::new(0x10, 0x20, 0x30));
+        assert_eq!(term.palette.background, bg);
+
+        let shown = |col: usize| {
+            let (fg, bg) = term.grid.rows[0].cells[col].resolved_colors(term.reverse_video);
+            (term.palette.resolve(&fg), term.palette.resolve(&bg))
+        };
+        assert_eq!(shown(0), (bg, fg));
+        assert_eq!(shown(1), (term.palette.ansi[1], fg));
+        assert_eq!(shown(2), (bg, fg));
+        // Blank cells swap to the themed background too
+        assert_eq!(shown(5), (bg, fg));
+    }
+
+    #[test]
+    fn test_autowrap_marks_continuation_row() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"0123456789");
+        // The wrap is deferred until the next printable character
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 9));
+        assert!(term.pending_wrap);
+        assert!(!term.grid.rows[1].wrapped);
+
+        term.process_bytes(b"needle");
+        assert!(term.grid.rows[1].wrapped);
+
+        let matches = term.grid.search("89nee", false);
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].start_row, matches[0].start_col), (0, 8));
+        assert_eq!((matches[0].end_row, matches[0].end_col), (1, 2));
+    }
+
+    #[test]
+    fn test_vertical_tab_and_form_feed() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"a\x0bb");
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 1));
+        term.process_bytes(b"\x0cc");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 1));
+
+        // FF at the bottom scrolls like a line feed
+        term.process_bytes(b"\x0c");
+        assert_eq!(term.cursor.row, 2);
+        assert_eq!(term.grid.get_cell(1, 0).unwrap().c, 'c');
+        assert_eq!(term.grid.scrollback.len(), 1);
+    }
+
+    #[test]
+    fn test_backspace_cancels_pending_wrap() {
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes(b"abcde");
+        assert!(term.pending_wrap);
+
+        term.process_bytes(b"\x08X");
+        assert!(!term.pending_wrap);
+        assert_eq!(row_text(&term, 0), "abcXe");
+        assert_eq!(term.cursor.row, 0);
+
+        term.process_bytes(b"\r\x08\x08");
+        assert_eq!(term.cursor.col, 0);
+    }
+
+    #[test]
+    fn test_backspace_reverse_wrap() {
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes(b"abcdefg\r\x08");
+        // Off by default: BS stops at the left margin
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 0));
+
+        term.process_bytes(b"\x1b[?45h\x08");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 4));
+        term.process_bytes(b"X");
+        assert_eq!(row_text(&term, 0), "abcdX");
+
+        // A hard line break is not crossed
+        term.process_bytes(b"\x1b[3;1H\x08");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 0));
+    }
+
+    #[test]
+    fn test_export_text() {
+        let mut term = Terminal::new(3, 8);
+        term.process_bytes(b"$ echo hello world\r\nhello world\r\n$ ");
+
+        // Six rows of output: the command wraps over three, its output over two
+        assert_eq!(term.grid.scrollback.len(), 3);
+        assert_eq!(
+            term.export_text(false),
+            "$ echo hello world\nhello world\n$\n"
+        );
+        assert_eq!(term.export_text_range(3, 5), "hello world\n");
+        assert_eq!(term.export_text_range(5, 5), "");
+    }
+
+    #[test]
+    fn test_export_text_trim_blank_lines() {
+        let mut term = Terminal::new(4, 8);
+        term.process_bytes(b"\r\none\r\n\r\n\r\n\r\ntwo\r\n\r\nthree\r\n");
+        assert_eq!(term.export_text(false), "\none\n\n\n\ntwo\n\nthree\n\n");
+        assert_eq!(term.export_text(true), "one\n\ntwo\n\nthree\n");
+    }
+
+    #[test]
+    fn test_default_background_for_new_rows() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"a\r\nb\r\nc");
+        let blue = Rgb::new(0, 0, 255);
+        term.set_default_colors(term.palette.foreground, blue);
+        let bg = |term: &Terminal, row: usize, col: usize| {
+            term.palette
+                .resolve(&term.grid.get_cell(row, col).unwrap().bg)
+        };
+
+        // Text already on screen repaints, and the freshly scrolled-in bottom
+        // row is blank in the new default
+        term.process_bytes(b"\r\nd");
+        assert_eq!(term.grid.scrollback.len(), 1);
+        assert_eq!(bg(&term, 0, 0), blue);
+        assert!((0..10).all(|col| bg(&term, 2, col) == blue));
+
+        // Erase and SGR reset use it as well
+        term.process_bytes(b"\x1b[41m\x1b[0m\x1b[2J");
+        assert_eq!(term.cursor.bg, Color::Named(NamedColor::Background));
+        assert_eq!(bg(&term, 0, 0), blue);
+    }
+
+    #[test]
+    fn test_osc_sets_default_colors() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"x\x1b]10;#ff0000\x07\x1b]11;rgb:00/00/ff\x1b\\");
+        assert_eq!(term.palette.foreground, Rgb::new(255, 0, 0));
+        assert_eq!(term.palette.background, Rgb::new(0, 0, 255));
+        // Cells keep naming the defaults, so earlier text follows the change
+        let cell = term.grid.get_cell(0, 0).unwrap();
+        assert_eq!(term.palette.resolve(&cell.fg), Rgb::new(255, 0, 0));
+        assert_eq!(term.grid.template.bg, Color::Named(NamedColor::Background));
+
+        // Every row repaints; a query or a cursor color change redraws nothing
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1, 2]);
+        term.grid.mark_clean();
+        term.process_bytes(b"\x1b]10;?\x07\x1b]12;#00ff00\x07");
+        assert!(term.grid.dirty_rows().is_empty());
+        term.set_default_colors(Rgb::new(1, 1, 1), Rgb::new(2, 2, 2));
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let mut term = Terminal::new(3, 20);
+        term.process_bytes(b"a\t");
+        assert_eq!(term.cursor.col, 8);
+
+        term.set_tab_width(4);
+        term.process_bytes(b"\ra\t");
+        assert_eq!(term.cursor.col, 4);
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor.col, 8);
+
+        // Zero is clamped rather than looping forever
+        term.set_tab_width(0);
+        assert_eq!(term.tab_width, 1);
+    }
+
+    #[test]
+    fn test_tab_set_and_clear() {
+        let mut term = Terminal::new(3, 20);
+        term.process_bytes(b"\x1b[1;4H\x1bH\r\t");
+        assert_eq!(term.cursor.col, 3);
+        term.process_bytes(b"\x1b[g\r\t");
+        assert_eq!(term.cursor.col, 8);
+        term.process_bytes(b"\x1b[3g\r\t");
+        assert_eq!(term.cursor.col, 19);
+    }
+
+    #[test]
+    fn test_title_stack() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"\x1b]2;shell\x07\x1b[22;0t\x1b]2;vim\x07");
+        assert_eq!(term.title, "vim");
+        term.process_bytes(b"\x1b[22;2t\x1b]2;help\x07");
+
+        term.process_bytes(b"\x1b[23;0t");
+        assert_eq!(term.title, "vim");
+        term.process_bytes(b"\x1b[23t");
+        assert_eq!(term.title, "shell");
+        // Popping an empty stack leaves the title alone
+        term.process_bytes(b"\x1b[23;0t");
+        assert_eq!(term.title, "shell");
+    }
+
+    #[test]
+    fn test_oversized_osc_is_dropped() {
+        let mut term = Terminal::new(3, 10);
+        term.record_unhandled = true;
+        term.process_bytes(b"\x1b]2;short\x07");
+
+        let mut osc = b"\x1b]52;c;".to_vec();
+        osc.resize(osc.len() + MAX_OSC_PAYLOAD, b'A');
+        osc.extend_from_slice(b"\x07");
+        term.process_bytes(&osc);
+        let mut title = b"\x1b]2;".to_vec();
+        title.resize(title.len() + MAX_OSC_PAYLOAD, b'x');
+        title.extend_from_slice(b"\x07");
+        term.process_bytes(&title);
+
+        assert_eq!(term.take_truncated_osc_count(), 2);
+        assert_eq!(term.title, "short");
+        assert!(term.unhandled.is_empty());
+
+        // Parsing carries on normally, and a payload just under the limit is kept
+        let mut title = b"\x1b]2;".to_vec();
+        title.resize(MAX_OSC_PAYLOAD + 2, b'y');
+        title.extend_from_slice(b"\x07ok");
+        term.process_bytes(&title);
+        assert_eq!(term.title.len(), MAX_OSC_PAYLOAD - 2);
+        assert_eq!(row_text(&term, 0).trim_end(), "ok");
+        assert_eq!(term.take_truncated_osc_count(), 0);
+    }
+
+    #[test]
+    fn test_osc_bell_terminator_is_not_a_bell() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"\x1b]0;my title\x07");
+        assert_eq!(term.title, "my title");
+        assert_eq!(term.take_bell_count(), 0);
+
+        term.process_bytes(b"\x07\x1b]2;a;b\x1b\\\x07");
+        assert_eq!(term.title, "a;b");
+        assert_eq!(term.take_bell_count(), 2);
+        assert_eq!(term.bell_count, 0);
+    }
+
+    #[test]
+    fn test_pump_burst() {
+        let mut term = Terminal::new(3, 20);
+        term.pty = Some(Pty::new(20, 3).unwrap());
+        let slave = term.pty.as_ref().unwrap().slave;
+
+        for chunk in [&b"hello"[..], b" ", b"world"] {
+            let written = unsafe { libc::write(slave, chunk.as_ptr().cast(), chunk.len()) };
+            assert_eq!(written, chunk.len() as isize);
+        }
+
+        assert_eq!(term.pump().unwrap(), 11);
+        assert_eq!(row_text(&term, 0).trim_end(), "hello world");
+        assert_eq!(term.pump().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_scroll_region_membership() {
+        let mut term = Terminal::new(10, 10);
+        term.process_bytes(b"\x1b[3;6r");
+        assert_eq!((term.scroll_top, term.scroll_bottom), (2, 5));
+        // DECSTBM homes the cursor, which is above the region
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 0));
+        assert!(!term.in_scroll_region());
+
+        term.process_bytes(b"\x1b[4;1H");
+        assert!(term.in_scroll_region());
+
+        // Cursor movement stops at the margins from inside the region
+        term.process_bytes(b"\x1b[9A");
+        assert_eq!(term.cursor.row, 2);
+        term.process_bytes(b"\x1b[9B");
+        assert_eq!(term.cursor.row, 5);
+
+        // ...but not from outside it
+        term.process_bytes(b"\x1b[8;1H\x1b[9A");
+        assert_eq!(term.cursor.row, 0);
+
+        term.process_bytes(b"\x1b[9;1H");
+        assert!(!term.in_scroll_region());
+        term.clamp_to_region();
+        assert_eq!(term.cursor.row, 5);
+    }
+
+    #[test]
+    fn test_line_feed_scrolls_only_the_region() {
+        let mut term = Terminal::new(5, 4);
+        term.process_bytes(b"a\r\nb\r\nc\r\nd\r\ne");
+        term.process_bytes(b"\x1b[2;4r\x1b[4;1H\nX");
+
+        let column: String = (0..5).map(|row| row_text(&term, row).remove(0)).collect();
+        assert_eq!(column, "acdXe");
+        assert!(term.grid.scrollback.is_empty());
+
+        // IL/DL act from the cursor to the bottom margin
+        term.process_bytes(b"\x1b[2;1H\x1b[L");
+        let column: String = (0..5).map(|row| row_text(&term, row).remove(0)).collect();
+        assert_eq!(column, "a cde");
+        term.process_bytes(b"\x1b[2M");
+        let column: String = (0..5).map(|row| row_text(&term, row).remove(0)).collect();
+        assert_eq!(column, "ad  e");
+    }
+
+    #[test]
+    fn test_single_line_scroll_region() {
+        let mut term = Terminal::new(5, 4);
+        term.process_bytes(b"a\r\nb\r\nc\r\nd\r\ne");
+        term.process_bytes(b"\x1b[3;3r");
+        assert_eq!((term.scroll_top, term.scroll_bottom), (2, 2));
+
+        // Line feeds at the margin just clear the one line
+        term.process_bytes(b"\x1b[3;1HX\n\n");
+        let column: String = (0..5).map(|row| row_text(&term, row).remove(0)).collect();
+        assert_eq!(column, "ab de");
+        assert_eq!(term.cursor.row, 2);
+
+        term.process_bytes(b"Y\x1bM\x1b[S\x1b[T\x1b[L\x1b[M");
+        let column: String = (0..5).map(|row| row_text(&term, row).remove(0)).collect();
+        assert_eq!(column, "ab de");
+        assert!(term.grid.scrollback.is_empty());
+    }
+
+    #[test]
+    fn test_full_line_erase_resets_row() {
+        // Row 1 is a soft-wrapped continuation starting with a wide character
+        let wrapped_wide_row = || {
+            let mut term = Terminal::new(3, 6);
+            term.process_bytes("ab中文中\r\nyy".as_bytes());
+            assert!(term.grid.rows[1].wrapped);
+            assert!(term.grid.rows[1].cells[0].flags.contains(CellFlags::WIDE));
+            term.grid.mark_clean();
+            term
+        };
+        let assert_reset = |term: &Terminal| {
+            let row = &term.grid.rows[1];
+            assert!(!row.wrapped);
+            assert!(row.cells.iter().all(|cell| *cell == Cell::default()));
+            assert_eq!(row.dirty_span, Some((0, 5)));
+            assert_eq!(row_text(term, 0), "ab中 文 ");
+        };
+
+        for el in ["\x1b[2;3H\x1b[2K", "\x1b[2;1H\x1b[K", "\x1b[2;6H\x1b[1K"] {
+            let mut term = wrapped_wide_row();
+            term.process_bytes(el.as_bytes());
+            assert_reset(&term);
+        }
+
+        let mut term = wrapped_wide_row();
+        term.process_bytes(b"\x1b[2;4H");
+        term.kill_line();
+        assert_reset(&term);
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 0));
+    }
+
+    #[test]
+    fn test_insert_and_delete_columns() {
+        let mut term = Terminal::new(4, 8);
+        term.process_bytes(b"abcdefgh\r\nABCDEFGH\r\n01234567\r\nxyz");
+        // Margins: rows 1-3, columns 2-6
+        term.process_bytes(b"\x1b[1;3r\x1b[?69h\x1b[2;6s");
+        assert_eq!((term.left_margin, term.right_margin), (1, 5));
+
+        term.process_bytes(b"\x1b[1;3H\x1b[2'}");
+        assert_eq!(row_text(&term, 0), "ab  cdgh");
+        assert_eq!(row_text(&term, 1), "AB  CDGH");
+        assert_eq!(row_text(&term, 2), "01  2367");
+        assert_eq!(row_text(&term, 3).trim_end(), "xyz");
+
+        term.process_bytes(b"\x1b[3'~");
+        assert_eq!(row_text(&term, 0), "abd   gh");
+        assert_eq!(row_text(&term, 2), "013   67");
+
+        // Outside the margins nothing happens
+        term.process_bytes(b"\x1b[1;8H\x1b['}");
+        assert_eq!(row_text(&term, 0), "abd   gh");
+
+        // SL / SR shift the whole band, wherever the cursor is
+        term.process_bytes(b"\x1b[ @");
+        assert_eq!(row_text(&term, 0), "ad    gh");
+        assert_eq!(row_text(&term, 2), "03    67");
+        term.process_bytes(b"\x1b[2 A");
+        assert_eq!(row_text(&term, 2), "0  3  67");
+        assert_eq!(row_text(&term, 3).trim_end(), "xyz");
+
+        // Without DECLRMM, CSI s still saves the cursor
+        term.process_bytes(b"\x1b[?69l\x1b[2;2H\x1b[s\x1b[H\x1b[u");
+        assert_eq!((term.left_margin, term.right_margin), (0, 7));
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 1));
+    }
+
+    #[test]
+    fn test_compressed_scrollback_reads_back() {
+        let build = |compress| {
+            Terminal::builder()
+                .rows(3)
+                .cols(8)
+                .scrollback(200_000)
+                .compress_scrollback(compress)
+                .build()
+        };
+        let output =
+            b"same\r\nsame\r\n\r\n\x1b[31mred\x1b[0m\r\n\r\nsame\r\nlong line wraps\r\n\r\n";
+        let mut plain = build(false);
+        let mut compressed = build(true);
+        for term in [&mut plain, &mut compressed] {
+            term.process_bytes(output);
+            term.process_bytes(output);
+        }
+        let lines = |term: &Terminal| -> Vec<Row> {
+            (term.grid.oldest_line()..=term.grid.newest_line())
+                .map(|line| term.grid.absolute_line(line).unwrap().clone())
+                .collect()
+        };
+        let (expected, actual) = (lines(&plain), lines(&compressed));
+        assert_eq!(expected.len(), actual.len());
+        for (a, b) in expected.iter().zip(&actual) {
+            assert_eq!((&a.cells, a.wrapped), (&b.cells, b.wrapped));
+        }
+        assert!(compressed.grid.scrollback_memory() < plain.grid.scrollback_memory());
+
+        // Narrowing reflows shared rows, and they still come back intact
+        plain.resize(3, 5);
+        compressed.resize(3, 5);
+        assert_eq!(plain.export_text(false), compressed.export_text(false));
+
+        // 100k blank lines: one shared row plus a pointer per line
+        let mut term = build(true);
+        term.process_bytes(&[b'\n'; 100_000]);
+        assert_eq!(term.grid.scrollback.len(), 99_998);
+        let per_line = term.grid.scrollback_memory() / term.grid.scrollback.len();
+        assert!(per_line <= 2 * std::mem::size_of::<usize>());
+    }
+
+    #[test]
+    fn test_resize_keeps_prompt_with_cursor() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"a\r\nb\r\nc\r\n$ ");
+        assert_eq!(term.grid.scrollback.len(), 1);
+
+        term.resize(5, 10);
+        assert_eq!(row_text(&term, 0), "a         ");
+        assert_eq!((term.cursor.row, term.cursor.col), (3, 2));
+        assert_eq!(row_text(&term, term.cursor.row), "$         ");
+
+        term.resize(2, 10);
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 2));
+        assert_eq!(row_text(&term, 1), "$         ");
+    }
+
+    #[test]
+    fn test_normalization() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes("e\u{301}".as_bytes());
+        assert_eq!(row_text(&term, 0), "e\u{301}        ");
+
+        let mut term = Terminal::new(2, 10);
+        term.normalize = Some(NormalizationForm::Nfc);
+        term.process_bytes("e\u{301}x\u{fb01}".as_bytes());
+        assert_eq!(row_text(&term, 0), "\u{e9}x\u{fb01}       ");
+        assert_eq!(term.cursor.col, 3);
+
+        term.normalize = Some(NormalizationForm::Nfkc);
+        term.process_bytes("\u{fb01}".as_bytes());
+        assert_eq!(row_text(&term, 0), "\u{e9}x\u{fb01}fi     ");
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut palette = Palette::default();
+        palette.ansi[1] = Rgb::new(255, 0, 0);
+        let mut term = Terminal::builder()
+            .rows(4)
+            .cols(20)
+            .scrollback(5)
+            .palette(palette)
+            .tab_width(4)
+            .default_colors(Rgb::new(1, 2, 3), Rgb::new(0, 0, 17))
+            .build();
+
+        assert_eq!((term.rows, term.cols), (4, 20));
+        assert_eq!(term.grid.max_scrollback, 5);
+        assert_eq!(
+            term.palette.resolve(&Color::Named(NamedColor::Red)),
+            Rgb::new(255, 0, 0)
+        );
+        assert_eq!(term.palette.foreground, Rgb::new(1, 2, 3));
+        assert_eq!(term.palette.background, Rgb::new(0, 0, 17));
+
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor.col, 4);
+    }
+
+    #[test]
+    fn test_keypad_mode() {
+        let mut term = Terminal::new(2, 10);
+        let enter = Key::Keypad(KeypadKey::Enter);
+        assert_eq!(term.encode_key(enter, Modifiers::default()), b"\r");
+
+        term.process_bytes(b"\x1b=");
+        assert!(term.application_keypad);
+        assert_eq!(term.encode_key(enter, Modifiers::default()), b"\x1bOM");
+
+        term.process_bytes(b"\x1b>");
+        assert!(!term.application_keypad);
+        assert_eq!(term.encode_key(enter, Modifiers::default()), b"\r");
+    }
+
+    #[test]
+    fn test_meta_sends_escape_mode() {
+        let mut term = Terminal::new(3, 10);
+        let alt = Modifiers(Modifiers::ALT);
+        assert_eq!(term.encode_key(Key::Char('a'), alt), b"\x1ba");
+        term.process_bytes(b"\x1b[?1034l");
+        assert_eq!(term.encode_key(Key::Char('a'), alt), "\u{e1}".as_bytes());
+        term.process_bytes(b"\x1b[?1034h");
+        assert_eq!(term.encode_key(Key::Char('a'), alt), b"\x1ba");
+    }
+
+    #[test]
+    fn test_unhandled_sequences_recorded() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[>q");
+        assert!(term.unhandled.is_empty());
+
+        term.record_unhandled = true;
+        term.process_bytes(b"\x1b[>q\x1b[?8h\x1b]52;c;eA==\x07\x1b%G\x1b[1m");
+        let seen: Vec<String> = term
+            .take_unhandled()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(seen, ["CSI >0q", "CSI ?8h", "OSC 52", "ESC %G"]);
+        assert!(term.unhandled.is_empty());
+    }
+
+    #[test]
+    fn test_osc7_working_directory() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b]7;file://localhost/home/user\x1b\\");
+        assert_eq!(term.cwd, Some(PathBuf::from("/home/user")));
+
+        term.process_bytes(b"\x1b]7;file://host/tmp/my%20dir;x/%E2%82%AC\x07");
+        assert_eq!(term.cwd, Some(PathBuf::from("/tmp/my dir;x/\u{20ac}")));
+
+        // Not a file URL: keep the previous directory
+        term.process_bytes(b"\x1b]7;/etc\x07");
+        assert_eq!(term.cwd, Some(PathBuf::from("/tmp/my dir;x/\u{20ac}")));
+    }
+
+    #[test]
+    fn test_autowrap_disabled_overwrites_last_column() {
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes(b"\x1b[?7labcdefg");
+        assert_eq!(row_text(&term, 0), "abcdg");
+        assert_eq!(row_text(&term, 1), "     ");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 4));
+
+        // REP respects the setting too
+        term.process_bytes(b"\rx\x1b[10b");
+        assert_eq!(row_text(&term, 0), "xxxxx");
+        assert_eq!(term.cursor.row, 0);
+
+        term.process_bytes(b"\x1b[?7h\ryz\x1b[4b");
+        assert_eq!(row_text(&term, 0), "yzzzz");
+        assert_eq!(row_text(&term, 1), "z    ");
+    }
+
+    #[test]
+    fn test_set_autowrap_api() {
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes(b"abcde");
+        assert!(term.pending_wrap);
+        term.set_autowrap(false);
+        assert!(!term.autowrap());
+        assert!(!term.pending_wrap);
+        term.process_bytes(b"fg");
+        assert_eq!(row_text(&term, 0), "abcdg");
+        assert_eq!(term.cursor.row, 0);
+
+        term.set_autowrap(true);
+        term.process_bytes(b"\rvwxyz!");
+        assert_eq!(row_text(&term, 0), "vwxyz");
+        assert_eq!(row_text(&term, 1), "!    ");
+
+        // The escape sequence and the API agree
+        term.process_bytes(b"\x1b[?7l");
+        assert!(!term.autowrap());
+        term.process_bytes(b"\x1b[?7h");
+        assert!(term.autowrap());
+    }
+
+    #[test]
+    fn test_osc12_cursor_color() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b]12;#ff0000\x1b\\");
+        assert_eq!(term.cursor_color, Some(Color::Spec(Rgb::new(255, 0, 0))));
+
+        term.process_bytes(b"\x1b]12;?\x07");
+        assert_eq!(term.take_responses(), b"\x1b]12;rgb:ffff/0000/0000\x07");
+
+        term.process_bytes(b"\x1b]112\x1b\\");
+        assert_eq!(term.cursor_color, None);
+        term.process_bytes(b"\x1b]12;?\x1b\\");
+        assert_eq!(term.take_responses(), b"\x1b]12;rgb:c8c8/c8c8/c8c8\x1b\\");
+    }
+
+    #[test]
+    fn test_to_ansi_round_trip() {
+        let mut term = Terminal::new(5, 8);
+        term.process_bytes(b"\x1b[1;31mred\x1b[0m plain\r\n");
+        term.process_bytes(b"\x1b[44;38;5;200mwrapped line\x1b[0m\r\n");
+        term.process_bytes(b"\x1b[3;38;2;1;2;3mrgb\x1b[7m!");
+
+        let ansi = term.grid.to_ansi();
+        let mut copy = Terminal::new(5, 8);
+        copy.process_bytes(ansi.as_bytes());
+
+        for (a, b) in term.grid.rows.iter().zip(&copy.grid.rows) {
+            assert_eq!(a.cells, b.cells);
+            assert_eq!(a.wrapped, b.wrapped);
+        }
+        assert!(ansi.ends_with("\x1b[0m"));
+    }
+
+    #[test]
+    fn test_cursor_blink_mode_and_style() {
+        let mut term = Terminal::new(2, 10);
+        assert_eq!(term.cursor_shape, CursorShape::Block);
+        assert!(term.cursor_blinks());
+
+        term.process_bytes(b"\x1b[?12l");
+        assert!(!term.cursor_blinks());
+        term.process_bytes(b"\x1b[?12h");
+        assert!(term.cursor_blinks());
+
+        // DECSCUSR after mode 12 takes over, mode 12 after DECSCUSR overrides it
+        term.process_bytes(b"\x1b[?12h\x1b[6 q");
+        assert_eq!(term.cursor_shape, CursorShape::Bar);
+        assert!(!term.cursor_blinks());
+        term.process_bytes(b"\x1b[?12h");
+        assert!(term.cursor_blinks());
+        assert_eq!(term.cursor_shape, CursorShape::Bar);
+
+        // Style 0 resets to the default blinking block
+        term.process_bytes(b"\x1b[?12l\x1b[0 q");
+        assert_eq!(term.cursor_shape, CursorShape::Block);
+        assert!(term.cursor_blinks());
+        term.process_bytes(b"\x1b[4 q");
+        assert_eq!(term.cursor_shape, CursorShape::Underline);
+        assert!(!term.cursor_blinks());
+    }
+
+    #[test]
+    fn test_print_with_cursor_out_of_range() {
+        let mut term = Terminal::new(3, 5);
+        term.cursor.row = 7;
+        term.process_bytes(b"x");
+        assert_eq!(row_text(&term, 2), "x    ");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 1));
+
+        term.cursor.col = 9;
+        term.process_bytes(b"yz");
+        // "y" fills the last column, so "z" wraps and scrolls
+        assert_eq!(row_text(&term, 1), "x   y");
+        assert_eq!(row_text(&term, 2), "z    ");
+        assert_eq!(term.grid.scrollback.len(), 1);
+    }
+
+    #[test]
+    fn test_recent_osc_keeps_unknown_codes() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b]5555;key=value;x\x07\x1b]2;title\x07\x1b]6000\x1b\\");
+        assert_eq!(
+            term.recent_osc(),
+            [(5555, b"key=value;x".to_vec()), (6000, Vec::new())]
+        );
+
+        for i in 0..MAX_RECENT_OSC {
+            term.process_bytes(format!("\x1b]7000;{}\x07", i).as_bytes());
+        }
+        assert_eq!(term.recent_osc().len(), MAX_RECENT_OSC);
+        assert_eq!(term.recent_osc()[0], (7000, b"0".to_vec()));
+    }
+
+    #[test]
+    fn test_enq_answerback() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"a\x05b");
+        assert!(term.take_responses().is_empty());
+        assert_eq!(row_text(&term, 0), "ab        ");
+
+        let mut term = Terminal::builder()
+            .rows(2)
+            .cols(10)
+            .answerback("vt100")
+            .build();
+        term.process_bytes(b"a\x05b\x05");
+        assert_eq!(term.take_responses(), b"vt100vt100");
+        assert_eq!(row_text(&term, 0), "ab        ");
+    }
+
+    #[test]
+    fn test_diff_to_ansi_applies() {
+        let base: &[u8] = "hello \x1b[1;32mworld\x1b[0m\r\nwide 字 here\r\nsame\r\n".as_bytes();
+        let mut term = Terminal::new(4, 12);
+        term.process_bytes(base);
+        let mut other = Terminal::new(4, 12);
+        other.process_bytes(base);
+        assert!(term.grid.diff_to_ansi(&other.grid).is_empty());
+
+        // Split the wide character, print a new one and recolor some cells
+        other.process_bytes(b"\x1b[1;1H\x1b[31mHE\x1b[2;7Hab\x1b[0m");
+        other.process_bytes("\x1b[4;3H\x1b[44m界 x".as_bytes());
+
+        let diff = term.grid.diff_to_ansi(&other.grid);
+        assert!(!diff.contains("\x1b[3;"));
+        term.process_bytes(diff.as_bytes());
+        for (a, b) in term.grid.rows.iter().zip(&other.grid.rows) {
+            assert_eq!(a.cells, b.cells);
+        }
+    }
+
+    #[test]
+    fn test_tab_fills_with_spaces() {
+        let mut term = Terminal::new(2, 20);
+        term.process_bytes(b"abcdefghij\r\t");
+        assert_eq!(term.cursor.col, 8);
+        for cell in &term.grid.rows[0].cells[0..8] {
+            assert_eq!(*cell, term.grid.template);
+        }
+        assert_eq!(row_text(&term, 0).trim_end(), "        ij");
+
+        // Skipped cells take the current background
+        term.process_bytes(b"\r\nab\x1b[41m\t");
+        assert_eq!(term.cursor.col, 8);
+        let row = &term.grid.rows[1];
+        assert_eq!(row.cells[1].c, 'b');
+        assert_eq!(row.cells[1].bg, Color::Named(NamedColor::Background));
+        for cell in &row.cells[2..8] {
+            assert_eq!(cell.c, ' ');
+            assert_eq!(cell.bg, Color::Named(NamedColor::Red));
+        }
+    }
+
+    #[test]
+    fn test_to_ansi_round_trip_themed_background() {
+        // Blanks of a grid themed through its template, not the palette
+        let themed = || {
+            let mut term = Terminal::new(3, 8);
+            term.grid.template.bg = Color::Spec(Rgb::new(0, 0, 64));
+            let template = term.grid.template;
+            for row in &mut term.grid.rows {
+                row.clear(&template);
+            }
+            term
+        };
+        let mut term = themed();
+        term.process_bytes(b"one\r\n\x1b[41mtwo\x1b[49m three");
+
+        let ansi = term.grid.to_ansi();
+        // Trailing blanks and blank rows are left to the receiving grid
+        assert!(!ansi.contains("48;2"));
+        let mut copy = themed();
+        copy.process_bytes(ansi.as_bytes());
+        for (a, b) in term.grid.rows.iter().zip(&copy.grid.rows) {
+            assert_eq!(a.cells, b.cells);
+        }
+    }
+
+    #[test]
+    fn test_resize_keeps_scrolled_view_on_same_line() {
+        let mut term = Terminal::new(3, 10);
+        for line in ["first", "second line", "third", "fourth", "fifth"] {
+            term.process_bytes(line.as_bytes());
+            term.process_bytes(b"\r\n");
+        }
+        // Scrollback: "first", "second lin", "e" (wrapped), "third"
+        term.grid.scroll_view(2);
+        assert_eq!(term.grid.visible_row(0).unwrap().text().trim_end(), "e");
+
+        // Narrower: "second line" now spans three rows; the view still starts
+        // on the row holding its tail
+        term.resize(3, 5);
+        assert_eq!(term.grid.visible_row(0).unwrap().text(), "e    ");
+
+        // Wider: the line is rejoined and shown from its start
+        term.resize(3, 20);
+        let top = term.grid.visible_row(0).unwrap().text();
+        assert_eq!(top.trim_end(), "second line");
+    }
+
+    #[test]
+    fn test_erase_uses_sgr_background() {
+        let green = Color::Named(NamedColor::Green);
+        let mut term = Terminal::new(3, 6);
+        term.process_bytes(b"abcdef\r\n\x1b[42mxy\x1b[K");
+        let row = &term.grid.rows[1];
+        assert!(
+            row.cells[2..]
+                .iter()
+                .all(|cell| cell.c == ' ' && cell.bg == green)
+        );
+
+        term.process_bytes(b"\x1b[1;3H\x1b[1K");
+        let row = &term.grid.rows[0];
+        assert!(row.cells[..3].iter().all(|cell| cell.bg == green));
+        assert_eq!(row_text(&term, 0), "   def");
+
+        term.bce = false;
+        term.process_bytes(b"\x1b[2K");
+        assert!(
+            term.grid.rows[0]
+                .cells
+                .iter()
+                .all(|cell| cell.bg == Color::Named(NamedColor::Background))
+        );
+    }
+
+    #[test]
+    fn test_national_charsets() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b(A#1 \x1b(K{}\x1b(B#");
+        assert_eq!(row_text(&term, 0), "\u{a3}1 \u{e4}\u{fc}#    ");
+
+        // G1 line drawing via SO, back to G0 with SI; unknown sets are ASCII
+        term.process_bytes(b"\r\n\x1b)0\x0eqx\x0fq\x1b(Z#");
+        assert_eq!(row_text(&term, 1), "\u{2500}\u{2502}q#      ");
+    }
+
+    #[test]
+    fn test_overline() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[4;53ma\x1b[55mb");
+        let a = term.grid.get_cell(0, 0).unwrap().flags;
+        let b = term.grid.get_cell(0, 1).unwrap().flags;
+        assert!(a.contains(CellFlags::OVERLINE) && a.contains(CellFlags::UNDERLINE));
+        assert!(!b.contains(CellFlags::OVERLINE) && b.contains(CellFlags::UNDERLINE));
+    }
+
+    #[test]
+    fn test_idle_and_notifications() {
+        let mut term = Terminal::new(2, 10);
+        assert!(term.idle_since().is_none());
+        term.process_bytes(b"x");
+        let first = term.idle_since().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(term.idle_since().unwrap() >= first + Duration::from_millis(20));
+
+        // Output that leaves the screen alone doesn't end the quiet spell
+        term.process_bytes(b"\x1b]9;build done; 0 errors\x07\x1b]777;notify;make;ok\x1b\\");
+        term.process_bytes(b"\x1b[6n\x1b[?25l");
+        assert!(term.idle_since().unwrap() >= first + Duration::from_millis(20));
+        term.process_bytes(b"\r\n");
+        assert!(term.idle_since().unwrap() >= first + Duration::from_millis(20));
+        term.process_bytes(b"y");
+        assert!(term.idle_since().unwrap() < Duration::from_millis(20));
+        let notes = term.take_notifications();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "");
+        assert_eq!(notes[0].body, "build done; 0 errors");
+        assert_eq!(
+            (notes[1].title.as_str(), notes[1].body.as_str()),
+            ("make", "ok")
+        );
+        assert!(term.notifications.is_empty());
+
+        // A flood keeps only the newest
+        for i in 0..MAX_NOTIFICATIONS + 10 {
+            term.process_bytes(format!("\x1b]9;{i}\x07").as_bytes());
+        }
+        let notes = term.take_notifications();
+        assert_eq!(notes.len(), MAX_NOTIFICATIONS);
+        assert_eq!(notes[0].body, "10");
+    }
+
+    #[test]
+    fn test_index_keeps_column() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"abc\x1bDd");
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 4));
+        assert_eq!(row_text(&term, 1).trim_end(), "   d");
+
+        term.process_bytes(b"\x1bEe");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 1));
+
+        // IND at the bottom margin scrolls; RI at the top scrolls back down
+        term.process_bytes(b"\x1bD");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 1));
+        assert_eq!(row_text(&term, 0).trim_end(), "   d");
+        term.process_bytes(b"\x1b[H\x1bM");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 0));
+        assert_eq!(row_text(&term, 0).trim_end(), "");
+        assert_eq!(row_text(&term, 1).trim_end(), "   d");
+    }
+
+    #[test]
+    fn test_del_ignored() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"ab");
+        term.grid.mark_clean();
+        term.process_bytes(b"\x7f\x7f");
+        assert_eq!(row_text(&term, 0).trim_end(), "ab");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 2));
+        assert!(term.grid.dirty_rows().is_empty());
+    }
+
+    #[test]
+    fn test_process_and_damage() {
+        let mut term = Terminal::new(3, 10);
+        term.grid.mark_clean();
+        let damage = term.process_and_damage(b"a\r\nb\r\nc\r\nd\r\ne\x1b]2;build\x07");
+        assert_eq!(damage.scrolled_lines, 2);
+        assert!(damage.title_changed);
+        assert!(!damage.bell);
+        assert_eq!(damage.dirty_rows, vec![0, 1, 2]);
+
+        term.grid.mark_clean();
+        let damage = term.process_and_damage(b"\x1b]2;build\x07\x07");
+        assert_eq!(damage.scrolled_lines, 0);
+        assert!(!damage.title_changed);
+        assert!(damage.bell);
+        assert!(damage.dirty_rows.is_empty());
+    }
+
+    #[test]
+    fn test_cursor_move_sequence() {
+        let mut term = Terminal::new(5, 10);
+        term.process_bytes(b"$ \r\n$ echo");
+        assert_eq!(term.cursor_move_sequence(1, 11), b"\x1b[C".repeat(3));
+        term.process_bytes(b"\x1b[1;2H");
+        assert_eq!(term.cursor_move_sequence(0, 6), b"\x1b[C".repeat(5));
+        term.application_cursor = true;
+        assert_eq!(term.cursor_move_sequence(0, 0), b"\x1bOD");
+        // Lines below are out of reach: clamp to the end of the current one
+        assert_eq!(term.cursor_move_sequence(3, 0), b"\x1bOC".repeat(8));
+        term.application_cursor = false;
+
+        // Two rows up within a wrapped command line is a run of Left presses
+        term.process_bytes(b"\x1b[3;1H$ 0123456789abcdefghijklmnop");
+        assert_eq!((term.cursor.row, term.cursor.col), (4, 8));
+        assert_eq!(term.cursor_move_sequence(2, 8), b"\x1b[D".repeat(20));
+        assert_eq!(term.cursor_move_sequence(0, 0), b"\x1b[D".repeat(28));
+    }
+
+    #[test]
+    fn test_cursor_move_sequence_wide_chars() {
+        let mut term = Terminal::new(5, 10);
+        term.process_bytes("$ 中文ab".as_bytes());
+        assert_eq!(term.cursor.col, 8);
+        // One press per character, whichever half of the glyph was clicked
+        assert_eq!(term.cursor_move_sequence(0, 2), b"\x1b[D".repeat(4));
+        assert_eq!(term.cursor_move_sequence(0, 5), b"\x1b[D".repeat(3));
+        term.process_bytes(b"\x1b[1;3H");
+        assert_eq!(term.cursor_move_sequence(0, 7), b"\x1b[C".repeat(3));
+
+        // A wide char pushed to the next row leaves a pad that is not counted
+        term.process_bytes("\x1b[3;1H$ 0123456字".as_bytes());
+        assert_eq!((term.cursor.row, term.cursor.col), (3, 2));
+        assert_eq!(term.cursor_move_sequence(2, 2), b"\x1b[D".repeat(8));
+    }
+
+    #[test]
+    fn test_sanitize_paste() {
+        let mut term = Terminal::new(2, 10);
+        let paste = b"ls\r\nrm -rf /\x1b[201~\tok\x03";
+        let (safe, changed) = term.sanitize_paste(paste);
+        assert_eq!(safe, b"ls rm -rf /[201~\tok");
+        assert!(changed);
+        assert_eq!(
+            term.sanitize_paste("naïve".as_bytes()),
+            ("naïve".as_bytes().to_vec(), false)
+        );
+
+        term.process_bytes(b"\x1b[?2004h");
+        assert!(term.bracketed_paste);
+        assert_eq!(term.sanitize_paste(paste), (paste.to_vec(), false));
+    }
+
+    #[test]
+    fn test_index_outside_scroll_region() {
+        let mut term = Terminal::new(6, 10);
+        term.process_bytes(b"top\x1b[2;3r\x1b[4;1Hx\n\n\n\x1bD");
+        // Below the region: line feeds move down to the last row and stop there
+        assert_eq!((term.cursor.row, term.cursor.col), (5, 0));
+        assert_eq!(row_text(&term, 0).trim_end(), "top");
+        assert_eq!(row_text(&term, 3).trim_end(), "x");
+        assert!(term.grid.scrollback.is_empty());
+
+        // Above the region RI moves up without scrolling, stopping at row 0
+        term.process_bytes(b"\x1b[1;5H\x1bM\x1bM");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 4));
+        assert_eq!(row_text(&term, 0).trim_end(), "top");
+
+        // Only the region's bottom margin scrolls, and only the region
+        term.process_bytes(b"\x1b[3;1Hy\n");
+        assert_eq!(term.cursor.row, 2);
+        assert_eq!(row_text(&term, 1).trim_end(), "y");
+        assert_eq!(row_text(&term, 3).trim_end(), "x");
+    }
+
+    #[test]
+    fn test_c1_controls() {
+        let mut term = Terminal::new(2, 10);
+        // A raw CSI byte and a UTF-8 encoded one are both dropped by default
+        term.process_bytes(b"a\x9b1mb");
+        term.process_bytes("c\u{9b}1md".as_bytes());
+        assert_eq!(row_text(&term, 0).trim_end(), "a1mbc1md");
+        assert!(!term.grid.get_cell(0, 1).unwrap().flags.is_bold());
+
+        // As controls, split across chunks
+        term.c1_as_control = true;
+        term.process_bytes(b"\r\xc2");
+        term.process_bytes(b"\x9b1mX\xc2\xb0");
+        assert!(term.grid.get_cell(0, 0).unwrap().flags.is_bold());
+        assert_eq!(row_text(&term, 0).trim_end(), "X°mbc1md");
+
+        // A raw 8-bit CSI acts as one too, but not inside a UTF-8 character
+        term.process_bytes(b"\r\x9b4m\xe2\x82");
+        term.process_bytes(b"\xac\x9b0mZ");
+        assert!(term.grid.get_cell(0, 0).unwrap().flags.is_underline());
+        assert!(!term.grid.get_cell(0, 1).unwrap().flags.is_underline());
+        assert_eq!(row_text(&term, 0).trim_end(), "€Zmbc1md");
+    }
+
+    #[test]
+    fn test_put_str_bypasses_parser() {
+        let mut term = Terminal::new(3, 8);
+        term.process_bytes(b"\x1b[1m");
+        term.put_str(1, 3, "hi\x1b[0mthere");
+        assert_eq!(row_text(&term, 1), "   hi\x1b[0");
+        assert!(term.grid.get_cell(1, 3).unwrap().flags.is_bold());
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 0));
+
+        term.set_cell(2, 7, Cell::new('z'));
+        term.set_cell(9, 9, Cell::new('?'));
+        assert_eq!(row_text(&term, 2), "       z");
+        assert!(term.grid.dirty_rows().contains(&2));
+    }
+
+    #[test]
+    fn test_alt_screen_1049() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"shell$ ");
+        term.process_bytes(b"\x1b[?1049h");
+        assert!(term.alt_screen);
+        assert_eq!(row_text(&term, 0).trim_end(), "");
+        term.process_bytes(b"\x1b[2;3Hvim");
+        term.process_bytes(b"\x1b[?1049l");
+        assert!(!term.alt_screen);
+        assert_eq!(row_text(&term, 0).trim_end(), "shell$");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 7));
+
+        // Re-entering starts from a blank alternate screen
+        term.process_bytes(b"\x1b[?1049h");
+        assert_eq!(row_text(&term, 1).trim_end(), "");
+    }
+
+    #[test]
+    fn test_private_modes_combined_in_one_sequence() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"shell$ \x1b[?1049;2004h");
+        assert!(term.alt_screen && term.bracketed_paste);
+
+        term.process_bytes(b"\x1b[?25;1049l");
+        assert!(!term.cursor_visible);
+        assert!(!term.alt_screen);
+        assert_eq!(row_text(&term, 0).trim_end(), "shell$");
+    }
+
+    #[test]
+    fn test_alt_screen_47_and_1047() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"main\x1b[?47halt");
+        // 47 neither saves nor restores the cursor
+        assert_eq!(row_text(&term, 0).trim_end(), "    alt");
+        term.process_bytes(b"\x1b[?47l");
+        assert_eq!(row_text(&term, 0).trim_end(), "main");
+        assert_eq!(term.cursor.col, 7);
+
+        // 47 keeps the alternate screen's contents across switches
+        term.process_bytes(b"\x1b[?47h");
+        assert_eq!(row_text(&term, 0).trim_end(), "    alt");
+
+        // 1047 clears it on the way out
+        term.process_bytes(b"\x1b[?1047l");
+        assert_eq!(row_text(&term, 0).trim_end(), "main");
+        term.process_bytes(b"\x1b[?47h");
+        assert_eq!(row_text(&term, 0).trim_end(), "");
+    }
+
+    #[test]
+    fn test_wide_char_at_margin() {
+        // Odd width: after "abcd" only the last column is left
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes("abcd中x".as_bytes());
+        assert_eq!(row_text(&term, 0), "abcd ");
+        assert!(term.grid.rows[1].wrapped);
+        let wide = term.grid.get_cell(1, 0).unwrap();
+        assert_eq!(wide.c, '中');
+        assert!(wide.flags.contains(CellFlags::WIDE));
+        assert!(
+            term.grid
+                .get_cell(1, 1)
+                .unwrap()
+                .flags
+                .contains(CellFlags::WIDE_SPACER)
+        );
+        assert_eq!(term.grid.rows[1].text(), "中x  ");
+        assert_eq!(term.cursor.col, 3);
+
+        // Filling the last two columns leaves a pending wrap, as for narrow text
+        term.process_bytes("\r\n\x1b[3C文".as_bytes());
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 4));
+        assert!(term.pending_wrap);
+
+        // Overwriting either half clears the whole wide character
+        term.process_bytes(b"\x1b[2;2Hy");
+        assert_eq!(term.grid.rows[1].text(), " yx  ");
+        assert_eq!(term.grid.get_cell(1, 1).unwrap().flags, CellFlags::new());
+
+        // Without autowrap the last two columns are overwritten instead
+        term.process_bytes("\x1b[?7l\x1b[1;5H字".as_bytes());
+        assert_eq!(term.grid.rows[0].text(), "abc字");
+        assert_eq!(term.cursor.col, 4);
+    }
+
+    #[test]
+    fn test_wide_char_truncated_at_margin() {
+        let mut term = Terminal::builder()
+            .rows(3)
+            .cols(5)
+            .wide_char_wrap(WideCharWrap::Truncate)
+            .build();
+        term.process_bytes("abcd中".as_bytes());
+        assert_eq!(term.grid.rows[0].text(), "abcd ");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 4));
+        assert!(!term.grid.rows[1].wrapped);
+        term.process_bytes(b"e");
+        assert_eq!(term.grid.rows[0].text(), "abcde");
+    }
+
+    #[test]
+    fn test_reflow_keeps_wide_pairs_together() {
+        // The early-wrap pad is dropped when the line is joined again
+        let mut term = Terminal::new(3, 5);
+        // Rows by absolute line, as narrowing pushes the top into scrollback
+        let row = |term: &Terminal, line: usize| term.grid.absolute_line(line).cloned().unwrap();
+        term.process_bytes("abcd中x".as_bytes());
+        assert!(row(&term, 0).cells[4].flags.contains(CellFlags::WRAP_PAD));
+        term.resize(3, 8);
+        assert_eq!(row(&term, 0).text(), "abcd中x ");
+        assert!(!row(&term, 1).wrapped);
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 7));
+
+        // Narrower again: the wide character moves whole to the next row
+        term.resize(3, 5);
+        assert_eq!(row(&term, 0).text(), "abcd ");
+        assert!(row(&term, 0).cells[4].flags.contains(CellFlags::WRAP_PAD));
+        assert_eq!(row(&term, 1).text(), "中x  ");
+        assert!(row(&term, 1).wrapped);
+        let wide = &row(&term, 1).cells;
+        assert!(wide[0].flags.contains(CellFlags::WIDE));
+        assert!(wide[1].flags.contains(CellFlags::WIDE_SPACER));
+        let cursor_line = term.grid.oldest_line() + term.grid.scrollback.len() + term.cursor.row;
+        assert_eq!((cursor_line, term.cursor.col), (1, 3));
+
+        // A width where the pair would straddle the edge of a middle row
+        term.resize(3, 3);
+        assert_eq!(row(&term, 1).text(), "d中");
+        term.resize(3, 4);
+        assert_eq!(row(&term, 0).text(), "abcd");
+        assert_eq!(row(&term, 1).text(), "中x ");
+    }
+
+    #[test]
+    fn test_save_restore_full_cursor_state() {
+        let mut term = Terminal::new(4, 5);
+        // Save with a wrap pending, G1 = DEC graphics invoked, in origin mode
+        term.process_bytes(b"\x1b[2;4r\x1b[?6h\x1b)0\x0e\x1b[31m\x1b[1;5Hq\x1b7");
+        assert!(term.pending_wrap);
+        term.process_bytes(b"\x0f\x1b(B\x1b[?6l\x1b[0m\x1b[1;1H");
+        assert!(!term.pending_wrap && !term.origin_mode);
+
+        term.process_bytes(b"\x1b8");
+        assert!(term.pending_wrap && term.origin_mode);
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 4));
+        assert_eq!(term.active_charset, 1);
+        assert_eq!(term.cursor.fg, Color::Named(NamedColor::Red));
+        // The wrap still happens, and the charset still maps
+        term.process_bytes(b"q");
+        assert_eq!(row_text(&term, 2), "─    ");
+        assert!(term.grid.rows[2].wrapped);
+
+        // CSI s / CSI u save the same state
+        term.process_bytes(b"\x1b[s\x0f\x1b[u");
+        assert_eq!(term.active_charset, 1);
+    }
+
+    #[test]
+    fn test_origin_mode() {
+        let mut term = Terminal::new(5, 5);
+        term.process_bytes(b"\x1b[2;4r\x1b[?6h");
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 0));
+        term.process_bytes(b"\x1b[2;3H");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 2));
+        // Positions past the region clamp to its bottom margin
+        term.process_bytes(b"\x1b[9;1H");
+        assert_eq!(term.cursor.row, 3);
+        term.process_bytes(b"\x1b[?6l");
+        assert_eq!((term.cursor.row, term.cursor.col), (0, 0));
+    }
+
+    #[test]
+    fn test_prompt_marks() {
+        let mut term = Terminal::new(3, 20);
+        term.process_bytes(b"\x1b]133;A\x07$ \x1b]133;B\x07false\r\n\x1b]133;C\x07");
+        term.process_bytes(b"oops\r\n\x1b]133;D;1\x07\x1b]133;A\x07$ \x1b]133;B\x07");
+        let kinds: Vec<_> = term
+            .prompt_marks
+            .iter()
+            .map(|m| (m.kind, m.line, m.col, m.exit_code))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (PromptMarkKind::PromptStart, 0, 0, None),
+                (PromptMarkKind::CommandStart, 0, 2, None),
+                (PromptMarkKind::OutputStart, 1, 0, None),
+                (PromptMarkKind::CommandEnd, 2, 0, Some(1)),
+                (PromptMarkKind::PromptStart, 2, 0, None),
+                (PromptMarkKind::CommandStart, 2, 2, None),
+            ]
+        );
+
+        // Lines stay absolute as the screen scrolls
+        term.process_bytes(b"\r\n\r\n");
+        assert_eq!(term.grid.absolute_line(2).unwrap().text().trim_end(), "$");
+        assert_eq!(term.previous_prompt(3), Some(2));
+        assert_eq!(term.previous_prompt(2), Some(0));
+        assert_eq!(term.previous_prompt(0), None);
+    }
+
+    #[test]
+    fn test_utf8_split_across_feeds() {
+        // The parser keeps partial UTF-8 between calls, so nothing is printed
+        // until the character is complete
+        let mut term = Terminal::new(2, 10);
+        for byte in "€".as_bytes() {
+            assert_eq!(term.cursor.col, 0);
+            term.process_bytes(&[*byte]);
+        }
+        assert_eq!(row_text(&term, 0).trim_end(), "€");
+        assert_eq!(term.cursor.col, 1);
+
+        // Also when 0xC2 lead bytes are held back for C1 translation
+        term.c1_as_control = true;
+        for byte in "°😀".as_bytes() {
+            term.process_bytes(&[*byte]);
+        }
+        assert_eq!(row_text(&term, 0).trim_end(), "€°😀");
+        assert!(!row_text(&term, 0).contains('\u{fffd}'));
+    }
+
+    #[test]
+    fn test_sequences_split_across_feeds() {
+        // CSI parameters, an OSC string and an ESC intermediate each span two
+        // calls; the parser's state must survive between them
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[3");
+        term.process_bytes(b"1mR\x1b]2;ti");
+        term.process_bytes(b"tle\x07\x1b(");
+        term.process_bytes(b"0q");
+
+        let cell = term.grid.get_cell(0, 0).unwrap();
+        assert_eq!(cell.c, 'R');
+        assert_eq!(cell.fg, Color::Named(NamedColor::Red));
+        assert_eq!(term.title, "title");
+        assert_eq!(term.grid.get_cell(0, 1).unwrap().c, '─');
+        assert_eq!(term.cursor.col, 2);
+    }
+
+    #[test]
+    fn test_visual_columns() {
+        let mut term = Terminal::new(2, 12);
+        term.process_bytes("a中\tb".as_bytes());
+        let row = &term.grid.rows[0];
+        assert_eq!(row.text().trim_end(), "a中     b");
+
+        // 'a', the wide char, the five blanks the tab left, then 'b'
+        assert_eq!(row.visual_col_of_char(0), 0);
+        assert_eq!(row.visual_col_of_char(1), 1);
+        assert_eq!(row.visual_col_of_char(2), 3);
+        assert_eq!(row.visual_col_of_char(7), 8);
+        assert_eq!(row.visual_col_of_char(50), 12);
+
+        assert_eq!(row.char_at_visual_col(1), 1);
+        assert_eq!(row.char_at_visual_col(2), 1);
+        assert_eq!(row.char_at_visual_col(3), 2);
+        assert_eq!(row.char_at_visual_col(8), 7);
+        for index in 0..10 {
+            assert_eq!(row.char_at_visual_col(row.visual_col_of_char(index)), index);
+        }
+    }
+
+    #[test]
+    fn test_resize_clamps_scroll_region() {
+        let mut term = Terminal::new(10, 10);
+        term.process_bytes(b"\x1b[3;8r");
+        term.resize(6, 10);
+        assert_eq!((term.scroll_top, term.scroll_bottom), (2, 5));
+
+        // Scrolling at the clipped bottom margin stays inside the grid
+        term.process_bytes(b"\x1b[1;1Htop\x1b[6;1Hlast\r\n\r\nx");
+        assert_eq!(term.grid.rows.len(), 6);
+        assert_eq!(term.cursor.row, 5);
+        assert_eq!(row_text(&term, 0).trim_end(), "top");
+        assert_eq!(row_text(&term, 3).trim_end(), "last");
+        assert_eq!(row_text(&term, 5).trim_end(), "x");
+
+        // Nothing of the region left: back to full-screen
+        term.resize(2, 10);
+        assert_eq!((term.scroll_top, term.scroll_bottom), (0, 1));
+        // A full-screen region grows with the screen
+        term.resize(8, 10);
+        assert_eq!((term.scroll_top, term.scroll_bottom), (0, 7));
+    }
+
+    #[test]
+    fn test_zero_height_is_one_row() {
+        let mut term = Terminal::new(0, 10);
+        assert_eq!((term.rows, term.scroll_bottom), (1, 0));
+        term.process_bytes(b"a\r\nb");
+        assert_eq!(row_text(&term, 0).trim_end(), "b");
+
+        term.resize(0, 10);
+        assert_eq!((term.rows, term.grid.rows.len()), (1, 1));
+        assert_eq!(Terminal::builder().rows(0).build().rows, 1);
+    }
+
+    #[test]
+    fn test_zero_width_is_one_column() {
+        let mut term = Terminal::new(3, 0);
+        assert_eq!((term.cols, term.right_margin), (1, 0));
+        term.process_bytes(b"ab");
+        assert_eq!(row_text(&term, 0), "a");
+        assert_eq!(row_text(&term, 1), "b");
+        // Margin-relative edits stay inside the single column
+        term.process_bytes(b"\x1b[3@\x1b[2P\x1b[5C");
+        assert_eq!((term.cursor.row, term.cursor.col), (1, 0));
+
+        term.resize(3, 0);
+        assert_eq!((term.cols, term.grid.cols), (1, 1));
+        assert_eq!(Terminal::builder().cols(0).build().cols, 1);
+    }
+
+    #[test]
+    fn test_insert_mode_and_cursor_visibility() {
+        let mut term = Terminal::new(2, 6);
+        term.process_bytes(b"abcdef\r\x1b[4hXY\x1b[4lZ");
+        assert_eq!(row_text(&term, 0), "XYZbcd");
+        assert!(!term.insert_mode);
+
+        assert!(term.cursor_visible);
+        term.process_bytes(b"\x1b[?25l");
+        assert!(!term.cursor_visible);
+    }
+
+    #[test]
+    fn test_tab_clear_all_survives_resize() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"\x1b[3g");
+        term.resize(2, 30);
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor.col, 29);
+
+        // Stops set after the clear still work; new columns still get none
+        term.process_bytes(b"\r\x1b[5C\x1bH\r\t");
+        assert_eq!(term.cursor.col, 5);
+        term.resize(2, 40);
+        term.process_bytes(b"\t");
+        assert_eq!(term.cursor.col, 39);
+
+        // Resetting the width brings the defaults back
+        term.set_tab_width(8);
+        term.resize(2, 50);
+        assert!(term.tab_stops[48]);
+    }
+
+    #[test]
+    fn test_scroll_to_prompt() {
+        let mut term = Terminal::builder().rows(3).cols(8).scrollback(4).build();
+        term.process_bytes(b"\x1b]133;A\x07$ one\r\na\r\nb\r\n");
+        term.process_bytes(b"\x1b]133;A\x07$ two\r\nc\r\nd\r\n\x1b]133;A\x07$ ");
+        assert_eq!(term.prompt_marks().len(), 3);
+        let top = |term: &Terminal| term.grid.visible_row(0).unwrap().text();
+
+        assert!(term.scroll_to_prompt(0));
+        assert_eq!(term.grid.view_offset, 4);
+        assert_eq!(top(&term), "$ one   ");
+        assert!(term.scroll_to_prompt(1));
+        assert_eq!(top(&term), "$ two   ");
+        // The last prompt is on screen: back to the live view
+        assert!(term.scroll_to_prompt(2));
+        assert_eq!(term.grid.view_offset, 0);
+        assert!(!term.scroll_to_prompt(3));
+
+        // Once the first prompt has left the scrollback it is skipped
+        term.process_bytes(b"\r\n");
+        assert!(term.scroll_to_prompt(1));
+        assert!(!term.scroll_to_prompt(0));
+        assert_eq!(top(&term), "$ two   ");
+    }
+
+    #[test]
+    fn test_prompt_marks_follow_reflow() {
+        let mut term = Terminal::builder().rows(3).cols(10).scrollback(20).build();
+        term.process_bytes(b"\x1b]133;A\x07$ one\r\n0123456789abcdefghij\r\n");
+        term.process_bytes(b"\x1b]133;A\x07$ two\r\nxyz\r\n");
+        let top = |term: &Terminal| {
+            term.grid
+                .visible_row(0)
+                .unwrap()
+                .text()
+                .trim_end()
+                .to_string()
+        };
+
+        // Narrowing wraps the long output onto more rows, pushing "$ two" down
+        term.resize(3, 5);
+        assert!(term.scroll_to_prompt(1));
+        assert_eq!(top(&term), "$ two");
+        assert!(term.scroll_to_prompt(0));
+        assert_eq!(top(&term), "$ one");
+
+        // Widening joins them back up
+        term.resize(3, 20);
+        assert!(term.scroll_to_prompt(1));
+        assert_eq!(top(&term), "$ two");
+        let (output, end) = (term.prompt_marks[0].line + 1, term.prompt_marks[1].line);
+        assert_eq!(term.text_between_lines(output, end), "0123456789abcdefghij");
+
+        // A mark's column follows its cell onto the wrapped row and back
+        term.process_bytes(b"\x1b]133;A\x07prompt> \x1b]133;B\x07");
+        let position = |term: &Terminal, index: usize| {
+            let mark = &term.prompt_marks[index];
+            (mark.line, mark.col)
+        };
+        let (line, _) = position(&term, 2);
+        assert_eq!(position(&term, 3), (line, 8));
+        term.resize(3, 5);
+        let (line, _) = position(&term, 2);
+        assert_eq!(position(&term, 3), (line + 1, 3));
+        term.resize(3, 20);
+        let (line, _) = position(&term, 2);
+        assert_eq!(position(&term, 3), (line, 8));
+    }
+
+    #[test]
+    fn test_text_between_prompt_marks() {
+        let mut term = Terminal::builder().rows(3).cols(6).scrollback(2).build();
+        term.process_bytes(b"\x1b]133;A\x07$ ls\r\n\x1b]133;C\x07");
+        term.process_bytes(b"a.txt\r\nlong-name\r\n\x1b]133;D;0\x07\x1b]133;A\x07$ ");
+        let output = term.prompt_marks[1].line;
+        let end = term.prompt_marks[2].line;
+        assert_eq!(term.text_between_lines(output, end), "a.txt\nlong-name");
+
+        // Once rows scroll off, whatever is left is returned, down to the
+        // tail of a wrapped line
+        term.process_bytes(b"\r\n\r\n");
+        assert!(term.grid.oldest_line() > output);
+        assert_eq!(term.text_between_lines(output, end), "long-name");
+        term.process_bytes(b"\r\n");
+        assert_eq!(term.text_between_lines(output, end), "ame");
+        assert_eq!(term.text_between_lines(0, 1), "");
+    }
+
+    #[test]
+    fn test_exported_text_has_no_control_chars() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"ab");
+        term.set_cell(0, 2, Cell::new('\x1b'));
+        term.put_str(0, 3, "[31m\x07c");
+
+        let text = term.export_text_range(0, 1);
+        assert_eq!(text, "ab [31m c\n");
+        assert!(!text.trim_end_matches('\n').chars().any(char::is_control));
+    }
+
+    #[test]
+    fn test_cell_pixel_size_reaches_pty() {
+        let mut term = Terminal::new(24, 80);
+        term.pty = Some(Pty::new(80, 24).unwrap());
+        term.set_cell_pixel_size(8, 16);
+        term.resize(30, 100);
+
+        let winsize = term.pty.as_ref().unwrap().window_size().unwrap();
+        assert_eq!((winsize.ws_col, winsize.ws_row), (100, 30));
+        assert_eq!((winsize.ws_xpixel, winsize.ws_ypixel), (800, 480));
+    }
+
+    #[test]
+    fn test_measure() {
+        let mut term = Terminal::new(3, 20);
+        assert_eq!(term.measure("abc"), 3);
+        assert_eq!(term.measure("日本語"), 6);
+        assert_eq!(term.measure("ab\tc"), 9);
+        assert_eq!(term.measure("中\t文x"), 11);
+        assert_eq!(term.measure("a\x07b"), 2);
+        assert_eq!(term.measure("e\u{301}\u{200d}x"), 2);
+
+        term.set_tab_width(4);
+        assert_eq!(term.measure("ab\tc"), 5);
+
+        // Matches where the cursor ends up
+        term.process_bytes("x中\ty".as_bytes());
+        assert_eq!(term.cursor.col, term.measure("x中\ty"));
+    }
+
+    #[test]
+    fn test_print_control_chars() {
+        let mut term = Terminal::new(2, 10);
+        term.process_bytes(b"a\x01b\x7fc");
+        assert_eq!(row_text(&term, 0).trim_end(), "abc");
+
+        term.print_control_chars = true;
+        term.process_bytes(b"\r\na\x01b\x7fc\x07");
+        assert_eq!(row_text(&term, 1).trim_end(), "a\u{2401}b\u{2421}c");
+        // Controls with an action still perform it
+        assert_eq!(term.take_bell_count(), 1);
+        assert_eq!(term.cursor.row, 1);
+    }
+
+    #[test]
+    fn test_blank_current_row() {
+        let mut term = Terminal::new(3, 6);
+        term.process_bytes(b"abcdefgh\x1b[41;1m");
+        term.grid.mark_clean();
+
+        term.blank_current_row();
+        let row = &term.grid.rows[1];
+        assert!(row.cells.iter().all(|cell| *cell == Cell::default()));
+        assert!(!row.wrapped);
+        assert_eq!(term.grid.dirty_spans(), vec![(1, 0, 5)]);
+        assert_eq!(row_text(&term, 0), "abcdef");
+    }
+
+    #[test]
+    fn test_sequence_interceptor() {
+        struct AppProtocol(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+        impl SequenceHandler for AppProtocol {
+            fn osc(&mut self, term: &mut Terminal, params: &[&[u8]]) -> bool {
+                if params.first() != Some(&&b"1337"[..]) {
+                    return false;
+                }
+                let payload = params[1..].join(&b';');
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&payload).into_owned());
+                term.put_str(1, 0, "!");
+                true
+            }
+        }
+
+        let seen = std::sync::Arc::default();
+        let mut term = Terminal::new(2, 10);
+        term.record_unhandled = true;
+        term.set_sequence_interceptor(Box::new(AppProtocol(std::sync::Arc::clone(&seen))));
+        term.process_bytes(b"\x1b]1337;SetMark;x\x07\x1b]2;title\x07ab");
+
+        assert_eq!(*seen.lock().unwrap(), ["SetMark;x"]);
+        assert!(term.unhandled.is_empty());
+        assert_eq!(term.title, "title");
+        assert_eq!(row_text(&term, 0).trim_end(), "ab");
+        assert_eq!(row_text(&term, 1).trim_end(), "!");
+
+        // Without it, OSC 1337 is unhandled again
+        assert!(term.take_sequence_interceptor().is_some());
+        term.process_bytes(b"\x1b]1337;SetMark\x07");
+        assert_eq!(term.unhandled.len(), 1);
+    }
+
+    #[test]
+    fn test_device_status_reports() {
+        let mut term = Terminal::new(5, 10);
+        term.process_bytes(b"\x1b[5n\x1b[3;4H\x1b[6n");
+        assert_eq!(term.take_responses(), b"\x1b[0n\x1b[3;4R");
+
+        // Origin mode reports relative to the scroll region
+        term.process_bytes(b"\x1b[2;4r\x1b[?6h\x1b[2;2H\x1b[?6n");
+        assert_eq!(term.take_responses(), b"\x1b[?2;2R");
+    }
+
+    #[test]
+    fn test_chunked_input_matches_single_call() {
+        let mut input = b"\x1b[2;20r".to_vec();
+        for i in 0..60 {
+            input.extend_from_slice(
+                format!(
+                    "\x1b[1;{}m\u{e9}l\u{e8}ve {i} \u{4e2d}\u{6587}\x1b[0m\x1b]0;t{i}\x07\r\n",
+                    31 + i % 7
+                )
+                .as_bytes(),
+            );
+        }
+
+        let mut whole = Terminal::new(24, 20);
+        whole.process_bytes(&input);
+        for chunk_len in [1, 3, 7, 64] {
+            let mut chunked = Terminal::new(24, 20);
+            for chunk in input.chunks(chunk_len) {
+                chunked.process_bytes(chunk);
+            }
+            assert_eq!(chunked.cursor.row, whole.cursor.row);
+            assert_eq!(chunked.cursor.col, whole.cursor.col);
+            assert_eq!(chunked.title, whole.title);
+            assert_eq!(chunked.grid.scrollback.len(), whole.grid.scrollback.len());
+            for row in 0..24 {
+                for col in 0..20 {
+                    assert_eq!(
+                        chunked.grid.get_cell(row, col),
+                        whole.grid.get_cell(row, col),
+                        "chunk {chunk_len} differs at {row},{col}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_screen() {
+        let screen = |lines: &[&str], width: usize| -> Vec<Row> {
+            lines
+                .iter()
+                .map(|text| {
+                    let mut row = Row::new(width);
+                    for (col, c) in text.chars().enumerate() {
+                        row.cells[col].c = c;
+                        row.cells[col].flags.set(CellFlags::BOLD, true);
+                    }
+                    row
+                })
+                .collect()
+        };
+
+        let mut term = Terminal::new(3, 5);
+        term.process_bytes(b"old");
+        assert!(term.load_screen(screen(&["one", "two", "$ ls"], 5), (2, 4)));
+        assert_eq!(row_text(&term, 0), "one  ");
+        assert_eq!(row_text(&term, 2), "$ ls ");
+        assert!(term.grid.get_cell(1, 0).unwrap().flags.is_bold());
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 4));
+        term.process_bytes(b"!");
+        assert_eq!(row_text(&term, 2), "$ ls!");
+
+        // A taller screen keeps the cursor's line; the top goes to scrollback
+        let rows = screen(&["a", "b", "c", "d"], 5);
+        assert!(term.load_screen(rows, (3, 0)));
+        assert_eq!(term.grid.scrollback.back().unwrap().text(), "a    ");
+        assert_eq!(row_text(&term, 2), "d    ");
+        assert_eq!((term.cursor.row, term.cursor.col), (2, 0));
+
+        // Ragged rows and a cursor outside them are rejected
+        let mut ragged = screen(&["x", "y"], 5);
+        ragged.push(Row::new(4));
+        assert!(!term.load_screen(ragged, (0, 0)));
+        assert!(!term.load_screen(screen(&["x"], 5), (1, 0)));
+        assert!(!term.load_screen(Vec::new(), (0, 0)));
+        assert_eq!(row_text(&term, 2), "d    ");
+    }
+
+    #[test]
+    fn test_visible_snapshot_is_frozen() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"one\r\ntwo");
+        let snapshot = term.visible_snapshot();
+
+        term.process_bytes(b"\r\nthree\r\nfour\x1b[1;1HX");
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot[0].text().trim_end(), "one");
+        assert_eq!(snapshot[1].text().trim_end(), "two");
+        assert_eq!(snapshot[2].text().trim_end(), "");
+        assert_eq!(term.visible_snapshot()[0].text().trim_end(), "Xwo");
+
+        // Scrolled back, the snapshot shows what the view shows
+        term.grid.scroll_view(1);
+        assert_eq!(term.visible_snapshot()[0].text().trim_end(), "one");
+    }
+
+    #[test]
+    fn test_osc_palette_and_color_resets() {
+        let mut palette = Palette::default();
+        palette.ansi[2] = Rgb::new(0, 200, 0);
+        let mut term = Terminal::builder()
+            .rows(2)
+            .cols(10)
+            .palette(palette)
+            .build();
+        let base = term.base_palette;
+
+        term.palette.ansi[1] = Rgb::new(1, 1, 1);
+        term.palette.ansi[2] = Rgb::new(2, 2, 2);
+        term.palette.ansi[3] = Rgb::new(3, 3, 3);
+        term.process_bytes(b"\x1b]104;1\x1b\\");
+        assert_eq!(term.palette.ansi[1], base.ansi[1]);
+        assert_eq!(term.palette.ansi[2], Rgb::new(2, 2, 2));
+        // Cells in any palette color may change, so everything repaints
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1]);
+
+        // Without indices everything goes back to the configured palette
+        term.process_bytes(b"\x1b]104\x07");
+        assert_eq!(term.palette.ansi, base.ansi);
+        assert_eq!(term.palette.ansi[2], Rgb::new(0, 200, 0));
+
+        // Host changes become the new reset target
+        term.grid.mark_clean();
+        term.set_palette_color(4, Rgb::new(4, 4, 4));
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1]);
+        term.process_bytes(b"\x1b]104;4\x07");
+        assert_eq!(term.palette.ansi[4], Rgb::new(4, 4, 4));
+        let mut host = Palette::default();
+        host.ansi[5] = Rgb::new(5, 5, 5);
+        term.grid.mark_clean();
+        term.set_palette(host);
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1]);
+        term.palette.ansi[5] = Rgb::new(9, 9, 9);
+        term.process_bytes(b"\x1b]104\x07");
+        assert_eq!(term.palette.ansi, host.ansi);
+
+        let blue = Rgb::new(0, 0, 255);
+        term.set_default_colors(host.foreground, blue);
+        term.process_bytes(b"\x1b]10;#ff0000\x07\x1b]11;#00ff00\x07");
+        term.grid.mark_clean();
+        term.process_bytes(b"\x1b]110\x07");
+        assert_eq!(term.grid.dirty_rows(), vec![0, 1]);
+        assert_eq!(term.palette.foreground, host.foreground);
+        assert_eq!(term.palette.background, Rgb::new(0, 255, 0));
+        term.process_bytes(b"\x1b]111\x07");
+        assert_eq!(term.palette.background, blue);
+    }
+
+    #[test]
+    fn test_sixel_dcs_capture() {
+        let mut term = Terminal::new(3, 10);
+        term.process_bytes(b"\x1bP0;1;0q\"1;1;2;2#0~~-~~\x1b\\after");
+        assert_eq!(term.pending_dcs, b"\"1;1;2;2#0~~-~~");
+        // Back in the ground state: text prints normally
+        assert_eq!(row_text(&term, 0), "after     ");
+
+        // Other DCS strings are consumed without being captured
+        term.pending_dcs.clear();
+        term.process_bytes(b"\x1bP+q544e\x1b\\");
+        assert!(term.pending_dcs.is_empty());
+    }
+
+    #[test]
+    fn test_osc_1337_inline_image() {
+        let mut term = Terminal::new(3, 10);
+        // name=dot.png, 1x1 PNG header bytes as the "file"
+        term.process_bytes(
+            b"\x1b]1337;File=name=ZG90LnBuZw==;size=8;width=10px;height=50%;inline=1:iVBO\nRw0KGgo\x07",
+        );
+        let image = term.pending_image.take().unwrap();
+        assert_eq!(image.name.as_deref(), Some("dot.png"));
+        assert_eq!(image.width, ImageDimension::Pixels(10));
+        assert_eq!(image.height, ImageDimension::Percent(50));
+        assert!(image.inline);
+        assert!(image.preserve_aspect_ratio);
+        assert_eq!(image.data, b"\x89PNG\r\n\x1a\n");
+
+        term.process_bytes(b"\x1b]1337;File=width=3;height=auto;preserveAspectRatio=0:AAE\x07");
+        let image = term.pending_image.take().unwrap();
+        assert_eq!(
+            (image.width, image.height),
+            (ImageDimension::Cells(3), ImageDimension::Auto)
+        );
+        assert!(!image.preserve_aspect_ratio && !image.inline);
+        assert_eq!(image.data, [0, 1]);
+
+        // Other subcommands and broken payloads leave nothing behind
+        term.process_bytes(b"\x1b]1337;SetMark\x07\x1b]1337;File=inline=1:!!\x07");
+        assert!(term.pending_image.is_none());
+
+        // 48 KiB of data fits in the OSC limit once encoded; 50 KiB does not
+        for (len, fits) in [(48_000, true), (50_000, false)] {
+            let data = base64::engine::general_purpose::STANDARD.encode(vec![7u8; len]);
+            term.process_bytes(format!("\x1b]1337;File=inline=1:{data}\x07").as_bytes());
+            let image = term.pending_image.take();
+            assert_eq!(image.map(|image| image.data.len()), fits.then_some(len));
+            assert_eq!(term.take_truncated_osc_count(), usize::from(!fits));
+        }
+    }
+
+    #[test]
+    fn test_decrqss_replies() {
+        let mut term = Terminal::new(10, 10);
+        term.process_bytes(b"\x1b[1;31m\x1bP$qm\x1b\\");
+        assert_eq!(term.take_responses(), b"\x1bP1$r0;1;31m\x1b\\");
+
+        term.process_bytes(b"\x1b[0;48;5;200m\x1bP$qm\x1b\\");
+        assert_eq!(term.take_responses(), b"\x1bP1$r0;48;5;200m\x1b\\");
+
+        term.process_bytes(b"\x1b[3;7r\x1bP$qr\x1b\\");
+        assert_eq!(term.take_responses(), b"\x1bP1$r3;7r\x1b\\");
+
+        // Unsupported settings get an explicit "invalid" reply
+        term.process_bytes(b"\x1bP$qz\x1b\\");
+        assert_eq!(term.take_responses(), b"\x1bP0$r\x1b\\");
+    }
+
+    fn row_text(term: &Terminal, row: usize) -> String {
+        term.grid.rows[row]
+            .cells
+            .iter()
+            .map(|cell| cell.c)
+            .collect()
+    }
+
+    #[test]
+    fn test_selective_erase_line_keeps_protected() {
+        let mut term = Terminal::new(3, 6);
+        term.process_bytes(b"\x1b[1\"qAB\x1b[0\"qCD");
+        assert!(
+            term.grid
+                .get_cell(0, 0)
+                .unwrap()
+                .flags
+                .contains(CellFlags::PROTECTED)
+        );
+        assert!(
+            !term
+                .grid
+                .get_cell(0, 2)
+                .unwrap()
+                .flags
+                .contains(CellFlags::PROTECTED)
+        );
+
+        term.process_bytes(b"\x1b[?2K");
+        assert_eq!(row_text(&term, 0), "AB    ");
+
+        // Normal erase ignores protection
+        term.process_bytes(b"\x1b[2K");
+        assert_eq!(row_text(&term, 0), "      ");
+    }
+
+    #[test]
+    fn test_selective_erase_display_keeps_protected() {
+        let mut term = Terminal::new(3, 6);
+        term.process_bytes(b"one\r\n\x1b[1\"qtwo\x1b[0m\r\nthree");
+        // SGR reset does not clear protection
+        assert!(term.cursor.flags.contains(CellFlags::PROTECTED));
+        term.process_bytes(b"\x1b[0\"q\x1b[H\x1b[?2J");
+
+        assert_eq!(row_text(&term, 0), "      ");
+        assert_eq!(row_text(&term, 1), "two   ");
+        assert_eq!(row_text(&term, 2), "three ");
     }
 }
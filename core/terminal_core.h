@@ -3,6 +3,7 @@
 
 #include <stdint.h>
 #include <stddef.h>
+#include <stdbool.h>
 
 #ifdef __cplusplus
 extern "C" {
@@ -20,9 +21,83 @@ typedef struct {
     uint8_t bg_r;     // Background red
     uint8_t bg_g;     // Background green
     uint8_t bg_b;     // Background blue
-    uint8_t flags;    // Text attributes (bold, italic, etc.)
+    uint16_t flags;   // Text attributes (CELL_FLAG_*)
 } CCell;
 
+// RGB color
+typedef struct {
+    uint8_t r;
+    uint8_t g;
+    uint8_t b;
+} CRgb;
+
+// Changed column range within a row (end inclusive)
+typedef struct {
+    uint16_t row;
+    uint16_t start_col;
+    uint16_t end_col;
+} CDirtySpan;
+
+// What one terminal_process_and_damage feed changed
+typedef struct {
+    uint32_t dirty_row_count;  // total dirty rows (may exceed the buffer)
+    uint32_t scrolled_lines;
+    bool title_changed;
+    bool bell;
+} CDamage;
+
+// Scrollbar geometry: the view shows visible_rows lines, starting view_offset
+// lines (0: the live screen) above the bottom of scrollback_len history lines
+typedef struct {
+    uint32_t scrollback_len;
+    uint32_t view_offset;
+    uint16_t visible_rows;
+} CScrollInfo;
+
+// Semantic prompt mark from OSC 133 (line is an absolute line number)
+typedef struct {
+    uint32_t line;
+    uint16_t col;
+    uint8_t kind;         // PROMPT_MARK_*
+    bool has_exit_code;   // exit_code is set for PROMPT_MARK_COMMAND_END with a status
+    int32_t exit_code;
+} CPromptMark;
+
+#define PROMPT_MARK_PROMPT_START  0  // OSC 133;A
+#define PROMPT_MARK_COMMAND_START 1  // OSC 133;B
+#define PROMPT_MARK_OUTPUT_START  2  // OSC 133;C
+#define PROMPT_MARK_COMMAND_END   3  // OSC 133;D[;status]
+
+// OSC 1337 inline image description; sizes are 0 for IMAGE_UNIT_AUTO
+typedef struct {
+    uint32_t width;
+    uint32_t height;
+    uint8_t width_unit;   // IMAGE_UNIT_*
+    uint8_t height_unit;  // IMAGE_UNIT_*
+    bool preserve_aspect_ratio;
+    bool inline_image;    // inline=1: display rather than offer as a download
+} CImageInfo;
+
+#define IMAGE_UNIT_AUTO    0
+#define IMAGE_UNIT_CELLS   1
+#define IMAGE_UNIT_PIXELS  2
+#define IMAGE_UNIT_PERCENT 3  // of the terminal's width or height
+
+// Dirty row with the version it had when fetched
+typedef struct {
+    uint16_t row;
+    uint64_t version;
+} CRowVersion;
+
+// Search match (rows are absolute line numbers, see terminal_oldest_line;
+// end position is inclusive and may be on a later row for wrapped lines)
+typedef struct {
+    uint32_t start_row;
+    uint16_t start_col;
+    uint32_t end_row;
+    uint16_t end_col;
+} CSearchMatch;
+
 // Cell flag constants
 #define CELL_FLAG_BOLD          0x01
 #define CELL_FLAG_ITALIC        0x02
@@ -30,6 +105,14 @@ typedef struct {
 #define CELL_FLAG_BLINK         0x08
 #define CELL_FLAG_INVERSE       0x10
 #define CELL_FLAG_STRIKETHROUGH 0x20
+#define CELL_FLAG_PROTECTED     0x40
+#define CELL_FLAG_RAPID_BLINK   0x80
+#define CELL_FLAG_OVERLINE      0x100
+#define CELL_FLAG_WIDE          0x200  // double-width character
+#define CELL_FLAG_WIDE_SPACER   0x400  // right half of a wide character; skip when drawing
+#define CELL_FLAG_WRAP_PAD      0x800  // blank left where a wide character wrapped early
+#define CELL_FLAG_DIM           0x1000
+#define CELL_FLAG_ROW_WRAPPED   0x8000 // on column 0 only: the row continues the previous one
 
 // Create a new terminal
 Terminal* terminal_new(uint16_t rows, uint16_t cols);
@@ -43,6 +126,11 @@ void terminal_free(Terminal* term);
 // Process bytes from PTY (parse ANSI sequences and update grid)
 void terminal_process_bytes(Terminal* term, const uint8_t* data, size_t len);
 
+// Process bytes and summarize the damage in one call; dirty rows (since the
+// last mark_clean) are written to rows (may be NULL) up to rows_len
+CDamage terminal_process_and_damage(Terminal* term, const uint8_t* data, size_t len,
+                                    uint16_t* rows, size_t rows_len);
+
 // Send input to the PTY (keyboard input, etc.)
 int terminal_send_input(Terminal* term, const uint8_t* data, size_t len);
 
@@ -52,25 +140,322 @@ CCell terminal_get_cell(const Terminal* term, uint16_t row, uint16_t col);
 // Get all cells in a row (bulk operation for performance)
 size_t terminal_get_row(const Terminal* term, uint16_t row, CCell* buffer, size_t buffer_len);
 
+// Visible screen as rows*cols codepoints, row-major: 0x20 for blanks, 0 for
+// the second column of a wide character. Returns rows*cols; pass NULL to probe.
+size_t terminal_get_codepoints(const Terminal* term, uint32_t* buffer, size_t buffer_len);
+
+// Whole visible screen as rows*cols cells, row-major, copied in one call so
+// rows never come from different updates. Returns rows*cols; pass NULL to probe.
+size_t terminal_get_screen(const Terminal* term, CCell* buffer, size_t buffer_len);
+
+// Whether a visible row continues the previous one (soft wrap): 1 or 0, -1
+// for an invalid row. Row buffers also carry CELL_FLAG_ROW_WRAPPED.
+int32_t terminal_row_is_wrapped(const Terminal* term, uint16_t row);
+
 // Get cursor position
 uint16_t terminal_get_cursor_row(const Terminal* term);
 uint16_t terminal_get_cursor_col(const Terminal* term);
 
-// Resize the terminal
+// Cursor shape set by DECSCUSR (ESC[Ps SP q); *blink (may be NULL) receives
+// whether it should blink. Mode 12 (ESC[?12h/l) also sets the blink: whichever
+// of the two the application used last wins.
+#define CURSOR_SHAPE_BLOCK     0
+#define CURSOR_SHAPE_UNDERLINE 1
+#define CURSOR_SHAPE_BAR       2
+uint8_t terminal_get_cursor_shape(const Terminal* term, bool* blink);
+
+// Resize the terminal; a size of 0 in either dimension is ignored
 void terminal_resize(Terminal* term, uint16_t rows, uint16_t cols);
+// Cell size in pixels, reported to the PTY as rows*height x cols*width
+void terminal_set_cell_pixel_size(Terminal* term, uint16_t width, uint16_t height);
 
 // Get dirty rows (rows that have changed since last mark_clean)
 size_t terminal_get_dirty_rows(const Terminal* term, uint16_t* buffer, size_t buffer_len);
 
+// Get changed column ranges per dirty row (since last mark_clean). Returns
+// the total number of spans; pass NULL to probe.
+size_t terminal_get_dirty_spans(const Terminal* term, CDirtySpan* buffer, size_t buffer_len);
+
 // Mark all cells as clean (call after rendering)
 void terminal_mark_clean(Terminal* term);
 
-// Read from PTY (non-blocking, returns -1 on error, bytes read otherwise)
+// For asynchronous renderers: fetch dirty rows with versions, then after
+// drawing acknowledge them. Rows modified since the fetch stay dirty.
+// Returns the total number of dirty rows; pass NULL to probe.
+size_t terminal_get_dirty_versions(const Terminal* term, CRowVersion* buffer, size_t buffer_len);
+void terminal_mark_clean_up_to(Terminal* term, const CRowVersion* versions, size_t count);
+
+// Returned by terminal_read_pty/terminal_pump once the shell has exited
+#define PTY_CLOSED -2
+
+// Read from PTY (returns bytes read, -1 on error, PTY_CLOSED once the shell has exited)
 ssize_t terminal_read_pty(Terminal* term, uint8_t* buffer, size_t buffer_len);
 
+// Read all currently available PTY output and process it in one call
+// (returns bytes processed, -1 on error, PTY_CLOSED once the shell has exited)
+ssize_t terminal_pump(Terminal* term);
+
 // Get PTY master file descriptor (for select/poll)
 int terminal_get_pty_fd(const Terminal* term);
 
+// Search scrollback and screen for a UTF-8 string. Returns the total number
+// of matches; pass NULL to probe.
+size_t terminal_search(const Terminal* term, const char* needle, bool case_insensitive,
+                       CSearchMatch* buffer, size_t buffer_len);
+
+// Export scrollback and screen as plain text, one logical line per line.
+// Returns the full UTF-8 length (not NUL-terminated); pass NULL to probe.
+size_t terminal_export_text(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Same, with runs of blank lines collapsed to one and leading/trailing blank
+// lines dropped
+size_t terminal_export_text_trimmed(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Export lines [start_line, end_line) (absolute line numbers) as plain text
+size_t terminal_export_text_range(const Terminal* term, uint32_t start_line, uint32_t end_line,
+                                  uint8_t* buffer, size_t buffer_len);
+
+// Lines are numbered from the first one ever produced (scrollback, then
+// screen), so a number keeps naming the same line as older lines are dropped.
+// These return the oldest kept line and the bottom screen row.
+uint32_t terminal_oldest_line(const Terminal* term);
+uint32_t terminal_newest_line(const Terminal* term);
+
+// Convert between visible rows (following the scrollback view) and absolute
+// line numbers; terminal_absolute_to_visible returns -1 for lines not in view
+uint32_t terminal_visible_to_absolute(const Terminal* term, uint16_t row);
+int32_t terminal_absolute_to_visible(const Terminal* term, uint32_t line);
+
+// Scrollback length, view offset and visible height in one call, for a
+// scrollbar. Returns false on NULL.
+bool terminal_get_scroll_info(const Terminal* term, CScrollInfo* out);
+
+// Copy the OSC 133 prompt marks, oldest first (for prompt navigation and
+// command status). Returns the total count; pass NULL to probe.
+size_t terminal_get_prompt_marks(const Terminal* term, CPromptMark* buffer, size_t buffer_len);
+
+// Scroll the view so prompt mark index (from terminal_get_prompt_marks) is at
+// the top, for Cmd+Up/Down navigation. Returns false for an invalid index or
+// a mark whose line has been dropped from scrollback.
+bool terminal_scroll_to_prompt(Terminal* term, uint32_t index);
+
+// Drop the scrollback (also done by ED 3, `ESC[3J`), leaving the screen and
+// cursor untouched; a scrolled-back view returns to the live screen
+void terminal_clear_scrollback(Terminal* term);
+
+// Default colors: the palette's foreground and background (also set by OSC
+// 10/11). Text and blanks in the default colors repaint when they change.
+void terminal_set_default_fg(Terminal* term, uint8_t r, uint8_t g, uint8_t b);
+void terminal_set_default_bg(Terminal* term, uint8_t r, uint8_t g, uint8_t b);
+CRgb terminal_get_default_fg(const Terminal* term);
+CRgb terminal_get_default_bg(const Terminal* term);
+
+// Set one of the 16 ANSI palette colors (other indices are ignored); OSC 104
+// resets the color to this value
+void terminal_set_palette_color(Terminal* term, uint8_t index, uint8_t r, uint8_t g,
+                                uint8_t b);
+
+// Set the default tab stop spacing (default 8, minimum 1; resets all tab stops)
+void terminal_set_tab_width(Terminal* term, uint16_t width);
+
+// Get the window title set via OSC 0/2 (returns full UTF-8 length; pass NULL to probe)
+size_t terminal_get_title(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Return and reset the number of audible bells (BEL terminating an OSC is not counted)
+uint32_t terminal_take_bell_count(Terminal* term);
+
+// Return and reset the number of OSC strings (titles, clipboard, ...) dropped
+// for reaching the 64 KiB payload limit; shorter ones are never truncated
+uint32_t terminal_take_truncated_osc_count(Terminal* term);
+
+// Reap the shell if it exited: exit code (128 + signal if killed),
+// -1 while still running, -2 without a PTY or on error
+int terminal_child_exit_status(Terminal* term);
+
+// Toggle echo / canonical input on the PTY (false without a PTY or on error)
+bool terminal_set_pty_echo(const Terminal* term, bool enabled);
+bool terminal_set_pty_canonical(const Terminal* term, bool enabled);
+
+// Send a signal to the shell / the PTY's foreground process group
+// (false without a PTY, for an unknown signal or on error)
+bool terminal_kill_child(const Terminal* term, int sig);
+bool terminal_signal_foreground(const Terminal* term, int sig);
+
+// Get the payload of the last sixel DCS (data after the 'q' final byte).
+// Returns the full length; pass NULL to probe. Clear it once rendered.
+size_t terminal_get_pending_dcs(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+void terminal_clear_pending_dcs(Terminal* term);
+
+// Describe the pending OSC 1337 inline image and copy its decoded file data.
+// Returns the full data length, or 0 when none is pending; info and buffer
+// may be NULL. Clear it once rendered. The sequence must fit the 64 KiB OSC
+// payload limit, so images over about 48 KiB of file data are dropped and
+// show up in terminal_take_truncated_osc_count instead.
+size_t terminal_get_pending_image(const Terminal* term, CImageInfo* info, uint8_t* buffer,
+                                  size_t buffer_len);
+void terminal_clear_pending_image(Terminal* term);
+
+// Move up to buffer_len bytes of queued query replies (DECRQSS, ...) into
+// buffer, returning the count copied. terminal_pump writes them to the PTY.
+size_t terminal_take_responses(Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Process input and copy the query replies it produced (plus any already
+// queued) into out. Returns the reply length (0 if none), -1 on NULL, or
+// RESPONSE_BUFFER_TOO_SMALL, in which case the replies stay queued.
+#define RESPONSE_BUFFER_TOO_SMALL -2
+ssize_t terminal_process_and_respond(Terminal* term, const uint8_t* input, size_t input_len, uint8_t* out, size_t out_len);
+
+// Unicode normalization of printed text (NFC composes combining marks into
+// the preceding cell). Returns 0 on success, -1 for an unknown mode.
+#define NORMALIZE_NONE 0
+#define NORMALIZE_NFC 1
+#define NORMALIZE_NFKC 2
+int32_t terminal_set_normalization(Terminal* term, uint8_t mode);
+
+// Send a NUL-terminated UTF-8 string to the PTY. Returns 0 on success, -1 on
+// NULL or write failure, INVALID_UTF8 if the text is malformed.
+#define INVALID_UTF8 -3
+int32_t terminal_send_text(Terminal* term, const char* utf8);
+
+// Write a NUL-terminated UTF-8 string straight into the grid in the cursor's
+// style, bypassing the parser (for tests and replay): no control handling, no
+// wrapping, cursor unchanged. Returns 0, -1 on NULL, or INVALID_UTF8.
+int32_t terminal_put_str(Terminal* term, uint16_t row, uint16_t col, const char* utf8);
+
+// Message queued as a reply to ENQ (0x05), e.g. for legacy serial setups.
+// Empty by default, which ignores ENQ. Returns 0, -1 on NULL, or INVALID_UTF8.
+int32_t terminal_set_answerback(Terminal* term, const char* utf8);
+
+// Columns the string would occupy printed from column 0 (wide = 2, tabs
+// expand, zero-width = 0). Returns -1 on NULL or INVALID_UTF8.
+int32_t terminal_measure(const Terminal* term, const char* utf8);
+
+// Key codes for terminal_encode_key; values below KEY_ENTER are Unicode
+// scalar values for character keys
+#define KEY_ENTER        0x110000
+#define KEY_TAB          0x110001
+#define KEY_BACKSPACE    0x110002
+#define KEY_ESCAPE       0x110003
+#define KEY_UP           0x110004
+#define KEY_DOWN         0x110005
+#define KEY_RIGHT        0x110006
+#define KEY_LEFT         0x110007
+#define KEY_HOME         0x110008
+#define KEY_END          0x110009
+#define KEY_INSERT       0x11000a
+#define KEY_DELETE       0x11000b
+#define KEY_PAGE_UP      0x11000c
+#define KEY_PAGE_DOWN    0x11000d
+#define KEY_F1           0x110100  // F1-F12: KEY_F1 + n - 1
+#define KEY_KP_0         0x110200  // Keypad 0-9: KEY_KP_0 + n
+#define KEY_KP_ENTER     0x110210
+#define KEY_KP_PLUS      0x110211
+#define KEY_KP_MINUS     0x110212
+#define KEY_KP_MULTIPLY  0x110213
+#define KEY_KP_DIVIDE    0x110214
+#define KEY_KP_DECIMAL   0x110215
+#define KEY_KP_EQUAL     0x110216
+
+#define KEY_MOD_SHIFT    0x01
+#define KEY_MOD_ALT      0x02
+#define KEY_MOD_CTRL     0x04
+
+// Encode a key press for the current input modes (application cursor keys,
+// application keypad). Returns the full length; pass NULL to probe.
+size_t terminal_encode_key(const Terminal* term, uint32_t key, uint8_t mods, uint8_t* buffer, size_t buffer_len);
+bool terminal_application_keypad(const Terminal* term);
+
+// Whether the alternate screen (modes 47/1047/1049) is showing; it keeps no
+// scrollback
+bool terminal_is_alt_screen(const Terminal* term);
+
+// Mouse wheel in alternate scroll mode (1007): when terminal_alternate_scroll
+// is set, send the wheel as keys instead of scrolling the view. Positive
+// notches scroll up; each sends scroll_multiplier arrows (default 3), or one
+// Page Up/Down with KEY_MOD_SHIFT. Returns the full length; pass NULL to probe.
+bool terminal_alternate_scroll(const Terminal* term);
+void terminal_set_scroll_multiplier(Terminal* term, uint16_t lines);
+size_t terminal_wheel_to_keys(const Terminal* term, int32_t notches, uint8_t mods,
+                              uint8_t* buffer, size_t buffer_len);
+
+// Interpret C1 controls (UTF-8 encoded U+0080-U+009F, e.g. U+009B as CSI, or
+// raw 8-bit bytes such as a lone 0x9B) as their ESC equivalents. Off by
+// default: they are ignored, never printed.
+void terminal_set_c1_as_control(Terminal* term, bool enabled);
+
+// Show C0 controls without an action (and DEL) as control pictures (U+2401
+// for 0x01, ...) instead of ignoring them. Off by default.
+void terminal_set_print_control_chars(Terminal* term, bool enabled);
+
+// Pasting: with bracketed paste (mode 2004) on, wrap the data in ESC[200~ /
+// ESC[201~. Otherwise run it through terminal_sanitize_paste, which turns
+// line breaks into spaces and drops other control bytes except tab; stripped
+// (may be NULL) reports whether anything changed. Returns the full length;
+// pass NULL to probe.
+bool terminal_bracketed_paste(const Terminal* term);
+size_t terminal_sanitize_paste(const Terminal* term, const uint8_t* data, size_t len,
+                               uint8_t* buffer, size_t buffer_len, bool* stripped);
+
+// Click-to-move: Left/Right presses that move the shell's cursor to the
+// clicked cell, clamped to the (wrapped) line holding the cursor. Returns the
+// full length; pass NULL to probe.
+size_t terminal_cursor_move_sequence(const Terminal* term, uint16_t row, uint16_t col,
+                                     uint8_t* buffer, size_t buffer_len);
+
+// Debugging aid: record escape sequences the terminal ignores (off by default).
+// terminal_get_unhandled returns them one per line, e.g. "CSI >0q"; it returns
+// the full length, pass NULL to probe.
+void terminal_set_record_unhandled(Terminal* term, bool enabled);
+size_t terminal_get_unhandled(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+void terminal_clear_unhandled(Terminal* term);
+
+// Get the working directory reported by the shell via OSC 7 (returns the full
+// byte length, 0 if unknown; pass NULL to probe)
+size_t terminal_get_cwd(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Get the cursor color set via OSC 12 (reset by OSC 112). Returns false when
+// the application hasn't set one; use the default cursor color then.
+bool terminal_get_cursor_color(const Terminal* term, CRgb* out);
+
+// Serialize the screen as text with SGR sequences; feeding it to a terminal of
+// the same size reproduces the screen (returns the full length; pass NULL to probe)
+size_t terminal_to_ansi(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+
+// Scroll the view delta lines back into history (negative: towards the live
+// screen); terminal_get_cell/terminal_get_row then return the scrolled view
+void terminal_scroll_view(Terminal* term, int32_t delta);
+
+// Zero-copy access to a visible row in renderer-ready form. Rows are converted
+// lazily, only when changed. Writes the cell count to out_len; returns NULL for
+// an invalid row. Valid until the terminal is modified or the row re-fetched.
+const CCell* terminal_get_row_ptr(Terminal* term, uint16_t row, size_t* out_len);
+
+// Milliseconds since output last changed the screen, or -1 before it has.
+// Query replies, mode changes and notifications don't count as output.
+int64_t terminal_idle_ms(const Terminal* term);
+
+// Desktop notifications requested via OSC 9 / OSC 777, oldest first. Read the
+// title (empty for OSC 9) and body of the oldest (full length returned; pass
+// NULL to probe), then pop it.
+uint32_t terminal_notification_count(const Terminal* term);
+size_t terminal_get_notification_title(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+size_t terminal_get_notification_body(const Terminal* term, uint8_t* buffer, size_t buffer_len);
+bool terminal_pop_notification(Terminal* term);
+
+// Boolean modes as a bitmask, to snapshot and restore them (e.g. across a
+// reconnect). Setting the mask doesn't move the cursor.
+#define MODE_AUTOWRAP        (1ull << 0)  // DECAWM, ?7
+#define MODE_ORIGIN          (1ull << 1)  // DECOM, ?6
+#define MODE_INSERT          (1ull << 2)  // IRM, 4
+#define MODE_APP_CURSOR      (1ull << 3)  // DECCKM, ?1
+#define MODE_APP_KEYPAD      (1ull << 4)  // DECKPAM, ESC =
+#define MODE_BRACKETED_PASTE (1ull << 5)  // ?2004
+#define MODE_CURSOR_VISIBLE  (1ull << 6)  // DECTCEM, ?25
+#define MODE_REVERSE_VIDEO   (1ull << 7)  // DECSCNM, ?5
+#define MODE_META_SENDS_ESCAPE (1ull << 8)  // ?1034 (set by default)
+uint64_t terminal_get_modes(const Terminal* term);
+void terminal_set_modes(Terminal* term, uint64_t modes);
+
 #ifdef __cplusplus
 }
 #endif